@@ -0,0 +1,495 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    net::IpAddr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use lan_mouse_ipc::{
+    ClientConfig, ClientHandle, ClientState, DEFAULT_PORT, FrontendEvent, FrontendRequest,
+    LanMouseClient, MacAddress, Position,
+};
+
+use crate::CliError;
+
+/// desired state of the service as read by `apply` and written by `export`.
+/// Sections that are omitted are left untouched by `apply`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Document {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clients: Option<Vec<Client>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authorized_fingerprints: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Client {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ips: Vec<IpAddr>,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    position: Position,
+    #[serde(default)]
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enter_hook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mac_address: Option<MacAddress>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+impl Client {
+    fn new(config: ClientConfig, state: &ClientState) -> Self {
+        Self {
+            hostname: config.hostname,
+            ips: config.fix_ips,
+            port: config.port,
+            position: config.pos,
+            active: state.active,
+            enter_hook: config.cmd,
+            mac_address: config.mac,
+        }
+    }
+
+    /// whether this describes the client with the given config
+    fn matches(&self, config: &ClientConfig) -> bool {
+        match &self.hostname {
+            Some(hostname) => config.hostname.as_ref() == Some(hostname),
+            None => config.hostname.is_none() && same_ips(&self.ips, &config.fix_ips),
+        }
+    }
+}
+
+impl From<Client> for ClientConfig {
+    fn from(client: Client) -> Self {
+        Self {
+            hostname: client.hostname,
+            fix_ips: client.ips,
+            port: client.port,
+            pos: client.position,
+            cmd: client.enter_hook,
+            mac: client.mac_address,
+        }
+    }
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hostname {
+            Some(hostname) => write!(f, "{hostname}"),
+            None => write!(f, "{}", ips(&self.ips)),
+        }
+    }
+}
+
+/// the current state of the service
+pub(crate) struct State {
+    port: u16,
+    clients: Vec<(ClientHandle, ClientConfig, ClientState)>,
+    authorized: HashMap<String, String>,
+}
+
+impl State {
+    /// request the current state from the service
+    pub(crate) async fn fetch(client: &mut LanMouseClient) -> Result<Self, CliError> {
+        let (mut port, mut clients, mut authorized) = (None, None, None);
+        for event in client.sync().await? {
+            match event {
+                FrontendEvent::PortChanged { port: p, .. } => port = Some(p),
+                FrontendEvent::Enumerate(c) => clients = Some(c),
+                FrontendEvent::AuthorizedUpdated(a) => authorized = Some(a),
+                _ => {}
+            }
+        }
+        match (port, clients, authorized) {
+            (Some(port), Some(clients), Some(authorized)) => Ok(Self {
+                port,
+                clients,
+                authorized,
+            }),
+            _ => Err(CliError::Service("incomplete state".to_owned())),
+        }
+    }
+
+    pub(crate) fn export(self) -> Document {
+        let mut clients = self.clients;
+        clients.sort_by_key(|(h, _, _)| *h);
+        Document {
+            port: Some(self.port),
+            clients: Some(
+                clients
+                    .into_iter()
+                    .map(|(_, config, state)| Client::new(config, &state))
+                    .collect(),
+            ),
+            authorized_fingerprints: Some(self.authorized.into_iter().collect()),
+        }
+    }
+}
+
+/// a step necessary to reach the desired state
+pub(crate) enum Action {
+    /// create a client and configure it
+    Create(Client),
+    Request {
+        description: String,
+        request: FrontendRequest,
+    },
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Create(client) => {
+                write!(
+                    f,
+                    "create client {client} ({}, port {}",
+                    client.position, client.port
+                )?;
+                if client.active {
+                    write!(f, ", active")?;
+                }
+                write!(f, ")")
+            }
+            Action::Request { description, .. } => write!(f, "{description}"),
+        }
+    }
+}
+
+/// the minimal set of actions turning `state` into `document`
+pub(crate) fn plan(state: &State, document: &Document) -> Vec<Action> {
+    let mut actions = vec![];
+
+    if let Some(port) = document.port {
+        if port != state.port {
+            actions.push(Action::Request {
+                description: format!("change port {} -> {port}", state.port),
+                request: FrontendRequest::ChangePort(port),
+            });
+        }
+    }
+
+    if let Some(clients) = &document.clients {
+        let mut existing = state.clients.iter().collect::<Vec<_>>();
+        existing.sort_by_key(|(h, _, _)| *h);
+        let (mut matched, mut updates) = (vec![], vec![]);
+        for client in clients {
+            let found = existing
+                .iter()
+                .find(|(h, c, _)| !matched.contains(h) && client.matches(c));
+            match found {
+                Some((handle, config, state)) => {
+                    matched.push(*handle);
+                    updates.extend(update_client(*handle, config, state.active, client));
+                }
+                None => actions.push(Action::Create(client.clone())),
+            }
+        }
+        /* create new clients first and delete the ones no longer wanted last,
+         * so a failure halfway through leaves as many clients as possible */
+        actions.extend(updates);
+        for (handle, config, state) in existing.iter().filter(|(h, _, _)| !matched.contains(h)) {
+            let client = Client::new(config.clone(), state);
+            actions.push(Action::Request {
+                description: format!("delete client {handle} ({client})"),
+                request: FrontendRequest::Delete(*handle),
+            });
+        }
+    }
+
+    if let Some(authorized) = &document.authorized_fingerprints {
+        for (fp, desc) in authorized {
+            if state.authorized.get(fp) != Some(desc) {
+                actions.push(Action::Request {
+                    description: format!("authorize key {fp} ({desc})"),
+                    request: FrontendRequest::AuthorizeKey {
+                        description: desc.clone(),
+                        fingerprint: fp.clone(),
+                    },
+                });
+            }
+        }
+        let mut removed = state
+            .authorized
+            .iter()
+            .filter(|(fp, _)| !authorized.contains_key(*fp))
+            .collect::<Vec<_>>();
+        removed.sort();
+        for (fp, desc) in removed {
+            actions.push(Action::Request {
+                description: format!("remove authorized key {fp} ({desc})"),
+                request: FrontendRequest::RemoveAuthorizedKey(fp.clone()),
+            });
+        }
+    }
+
+    actions
+}
+
+/// the requests changing the client from `config` to `desired`
+fn update_client(
+    handle: ClientHandle,
+    config: &ClientConfig,
+    active: bool,
+    desired: &Client,
+) -> Vec<Action> {
+    let mut actions = vec![];
+    let mut change = |what: String, request: FrontendRequest| {
+        actions.push(Action::Request {
+            description: format!("client {handle} ({desired}): {what}"),
+            request,
+        })
+    };
+    if config.hostname != desired.hostname {
+        change(
+            format!("hostname {:?} -> {:?}", config.hostname, desired.hostname),
+            FrontendRequest::UpdateHostname {
+                handle,
+                hostname: desired.hostname.clone(),
+            },
+        );
+    }
+    if config.port != desired.port {
+        change(
+            format!("port {} -> {}", config.port, desired.port),
+            FrontendRequest::UpdatePort {
+                handle,
+                port: desired.port,
+            },
+        );
+    }
+    if !same_ips(&config.fix_ips, &desired.ips) {
+        change(
+            format!("ips {} -> {}", ips(&config.fix_ips), ips(&desired.ips)),
+            FrontendRequest::UpdateFixIps {
+                handle,
+                ips: desired.ips.clone(),
+            },
+        );
+    }
+    if config.pos != desired.position {
+        change(
+            format!("position {} -> {}", config.pos, desired.position),
+            FrontendRequest::UpdatePosition {
+                handle,
+                position: desired.position,
+            },
+        );
+    }
+    if config.cmd != desired.enter_hook {
+        change(
+            format!("enter hook {:?} -> {:?}", config.cmd, desired.enter_hook),
+            FrontendRequest::UpdateEnterHook {
+                handle,
+                enter_hook: desired.enter_hook.clone(),
+            },
+        );
+    }
+    if config.mac != desired.mac_address {
+        let mac = |m: Option<MacAddress>| m.map(|m| m.to_string()).unwrap_or("none".into());
+        change(
+            format!(
+                "mac address {} -> {}",
+                mac(config.mac),
+                mac(desired.mac_address)
+            ),
+            FrontendRequest::UpdateMacAddress {
+                handle,
+                mac: desired.mac_address,
+            },
+        );
+    }
+    /* activate last, with the final position */
+    if active != desired.active {
+        let what = if desired.active {
+            "activate"
+        } else {
+            "deactivate"
+        };
+        change(
+            what.to_owned(),
+            FrontendRequest::Activate {
+                handle,
+                active: desired.active,
+            },
+        );
+    }
+    actions
+}
+
+/// perform `action`
+pub(crate) async fn execute(action: Action, client: &mut LanMouseClient) -> Result<(), CliError> {
+    match action {
+        Action::Request { request, .. } => {
+            client.request(request).await?;
+        }
+        Action::Create(desired) => {
+            let active = desired.active;
+            let handle = client.create_client(desired.into()).await?;
+            /* activate last, with the final position */
+            if active {
+                client.set_active(handle, true).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn same_ips(a: &[IpAddr], b: &[IpAddr]) -> bool {
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.sort();
+    a.dedup();
+    b.sort();
+    b.dedup();
+    a == b
+}
+
+fn ips(ips: &[IpAddr]) -> String {
+    let ips = ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+    format!("[{}]", ips.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use lan_mouse_ipc::{ClientConfig, ClientHandle, ClientState, FrontendRequest, Position};
+
+    use super::{Action, Document, State, plan};
+
+    fn state(clients: Vec<ClientConfig>) -> State {
+        State {
+            port: 4242,
+            clients: clients
+                .into_iter()
+                .enumerate()
+                .map(|(h, c)| (h as ClientHandle, c, ClientState::default()))
+                .collect(),
+            authorized: HashMap::new(),
+        }
+    }
+
+    fn config(hostname: Option<&str>, ips: &[&str], pos: Position) -> ClientConfig {
+        ClientConfig {
+            hostname: hostname.map(ToOwned::to_owned),
+            fix_ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            pos,
+            ..Default::default()
+        }
+    }
+
+    /// the description of each action
+    fn describe(actions: &[Action]) -> Vec<String> {
+        actions.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn match_by_hostname() {
+        let state = state(vec![
+            config(Some("a"), &[], Position::Left),
+            config(Some("b"), &["10.0.0.2"], Position::Right),
+        ]);
+        let document = toml::from_str::<Document>(
+            "[[clients]]\n\
+             hostname = \"b\"\n\
+             position = \"right\"\n\
+             [[clients]]\n\
+             hostname = \"a\"\n\
+             position = \"top\"\n",
+        )
+        .unwrap();
+        let actions = plan(&state, &document);
+        assert_eq!(
+            describe(&actions),
+            [
+                "client 1 (b): ips [10.0.0.2] -> []",
+                "client 0 (a): position left -> top",
+            ]
+        );
+        assert!(matches!(
+            actions[1],
+            Action::Request {
+                request: FrontendRequest::UpdatePosition {
+                    handle: 0,
+                    position: Position::Top
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn match_by_ips_without_hostname() {
+        let state = state(vec![
+            config(None, &["10.0.0.1", "10.0.0.2"], Position::Left),
+            config(Some("10.0.0.3"), &["10.0.0.3"], Position::Left),
+        ]);
+        let document = toml::from_str::<Document>(
+            "[[clients]]\n\
+             ips = [\"10.0.0.2\", \"10.0.0.1\"]\n\
+             [[clients]]\n\
+             ips = [\"10.0.0.3\"]\n",
+        )
+        .unwrap();
+        /* a client with a hostname is not matched by its ips */
+        assert_eq!(
+            describe(&plan(&state, &document)),
+            [
+                "create client [10.0.0.3] (left, port 4242)",
+                "delete client 1 (10.0.0.3)",
+            ]
+        );
+    }
+
+    #[test]
+    fn create_update_delete() {
+        let state = state(vec![
+            config(Some("old"), &[], Position::Left),
+            config(Some("kept"), &[], Position::Left),
+        ]);
+        let document = toml::from_str::<Document>(
+            "port = 4243\n\
+             [[clients]]\n\
+             hostname = \"kept\"\n\
+             position = \"bottom\"\n\
+             active = true\n\
+             [[clients]]\n\
+             hostname = \"new\"\n\
+             [authorized_fingerprints]\n\
+             aa = \"laptop\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            describe(&plan(&state, &document)),
+            [
+                "change port 4242 -> 4243",
+                "create client new (left, port 4242)",
+                "client 1 (kept): position left -> bottom",
+                "client 1 (kept): activate",
+                "delete client 0 (old)",
+                "authorize key aa (laptop)",
+            ]
+        );
+    }
+
+    #[test]
+    fn omitted_sections() {
+        let state = state(vec![config(Some("a"), &[], Position::Left)]);
+        let document = toml::from_str::<Document>("port = 4242\n").unwrap();
+        assert!(plan(&state, &document).is_empty());
+        let document = toml::from_str::<Document>("clients = []\n").unwrap();
+        assert_eq!(describe(&plan(&state, &document)), ["delete client 0 (a)"]);
+    }
+}
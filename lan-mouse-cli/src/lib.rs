@@ -1,26 +1,45 @@
+mod apply;
+mod status;
+mod watch;
+
 use clap::{Args, Parser, Subcommand};
-use futures::StreamExt;
 
-use std::{net::IpAddr, time::Duration};
+use serde_json::json;
+use std::{collections::BTreeMap, fs, io, net::IpAddr, path::PathBuf, time::Duration};
 use thiserror::Error;
 
+use apply::{Document, State};
+use status::Status;
+
 use lan_mouse_ipc::{
-    ClientHandle, ConnectionError, FrontendEvent, FrontendRequest, IpcError, Position,
-    connect_async,
+    ClientConfig, ClientError, ClientHandle, ConnectionStats, DEFAULT_PORT, FrontendEvent,
+    FrontendRequest, FrontendResponse, LanMouseClient, MacAddress, Position,
 };
 
 #[derive(Debug, Error)]
 pub enum CliError {
-    /// is the service running?
-    #[error("could not connect: `{0}` - is the service running?")]
-    ServiceNotRunning(#[from] ConnectionError),
-    #[error("error communicating with service: {0}")]
-    Ipc(#[from] IpcError),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error("failed to read passphrase: {0}")]
+    Passphrase(#[from] io::Error),
+    #[error("{0}")]
+    Service(String),
+    #[error("{0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+    #[error(transparent)]
+    Export(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
 }
 
 #[derive(Parser, Clone, Debug, PartialEq, Eq)]
 #[command(name = "lan-mouse-cli", about = "LanMouse CLI interface")]
 pub struct CliArgs {
+    /// print machine-readable json instead of text
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     command: CliSubcommand,
 }
@@ -35,11 +54,19 @@ struct Client {
     ips: Option<Vec<IpAddr>>,
     #[arg(long)]
     enter_hook: Option<String>,
+    /// mac address for Wake-on-LAN
+    #[arg(long)]
+    mac: Option<MacAddress>,
+    #[arg(long)]
+    position: Option<Position>,
+    /// activate the client right away
+    #[arg(long)]
+    activate: bool,
 }
 
 #[derive(Clone, Subcommand, Debug, PartialEq, Eq)]
 enum CliSubcommand {
-    /// add a new client
+    /// add a new client and print its id
     AddClient(Client),
     /// remove an existing client
     RemoveClient { id: ClientHandle },
@@ -49,6 +76,12 @@ enum CliSubcommand {
     Deactivate { id: ClientHandle },
     /// list configured clients
     List,
+    /// show capture / emulation status, port and connected devices
+    Status,
+    /// show the public key fingerprint of this device
+    Fingerprint,
+    /// list authorized public keys
+    ListKeys,
     /// change hostname
     SetHost {
         id: ClientHandle,
@@ -56,10 +89,24 @@ enum CliSubcommand {
     },
     /// change port
     SetPort { id: ClientHandle, port: u16 },
+    /// change the port the service listens on
+    SetServicePort { port: u16 },
     /// set position
     SetPosition { id: ClientHandle, pos: Position },
     /// set ips
     SetIps { id: ClientHandle, ips: Vec<IpAddr> },
+    /// set the command executed when entering a client
+    SetEnterHook {
+        id: ClientHandle,
+        command: Option<String>,
+    },
+    /// resolve the hostname of a client again
+    Resolve { id: ClientHandle },
+    /// set the mac address used for Wake-on-LAN
+    SetMac {
+        id: ClientHandle,
+        mac: Option<MacAddress>,
+    },
     /// re-enable capture
     EnableCapture,
     /// re-enable emulation
@@ -71,97 +118,319 @@ enum CliSubcommand {
     },
     /// deauthorize a public key
     RemoveAuthorizedKey { sha256_fingerprint: String },
+    /// unlock a passphrase protected private key
+    Unlock,
+    /// never accept connections using a public key
+    BlockKey { sha256_fingerprint: String },
+    /// remove a public key from the blocklist
+    UnblockKey { sha256_fingerprint: String },
+    /// never accept connections from an ip address
+    BlockIp { ip: IpAddr },
+    /// remove an ip address from the blocklist
+    UnblockIp { ip: IpAddr },
+    /// show traffic and health statistics of all connections
+    Stats,
+    /// list layout profiles
+    Profiles,
+    /// switch to another layout profile
+    SwitchProfile { name: String },
+    /// set the current network (gateway mac address or SSID),
+    /// switching to the profile configured for it
+    SetNetwork { network: String },
+    /// set the key combination releasing the captured input,
+    /// keys are named like in the config file (e.g. `KeyLeftCtrl KeyLeftAlt`)
+    SetReleaseBind {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// reconcile clients, authorized keys and port with a file
+    Apply {
+        /// the desired state, in the format written by `export`
+        path: PathBuf,
+        /// only show the changes that would be made
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// print clients, authorized keys and port in the format read by `apply`
+    Export,
+    /// print the current state and all further events of the service
+    Watch,
 }
 
 pub async fn run(args: CliArgs) -> Result<(), CliError> {
-    execute(args.command).await?;
+    execute(args.command, args.json).await?;
     Ok(())
 }
 
-async fn execute(cmd: CliSubcommand) -> Result<(), CliError> {
-    let (mut rx, mut tx) = connect_async(Some(Duration::from_millis(500))).await?;
-    match cmd {
+async fn execute(cmd: CliSubcommand, json: bool) -> Result<(), CliError> {
+    let mut client = LanMouseClient::connect(Some(Duration::from_millis(500))).await?;
+    let request = match cmd {
         CliSubcommand::AddClient(Client {
             hostname,
             port,
             ips,
             enter_hook,
+            mac,
+            position,
+            activate,
         }) => {
-            tx.request(FrontendRequest::Create).await?;
-            while let Some(e) = rx.next().await {
-                if let FrontendEvent::Created(handle, _, _) = e? {
-                    if let Some(hostname) = hostname {
-                        tx.request(FrontendRequest::UpdateHostname(handle, Some(hostname)))
-                            .await?;
-                    }
-                    if let Some(port) = port {
-                        tx.request(FrontendRequest::UpdatePort(handle, port))
-                            .await?;
-                    }
-                    if let Some(ips) = ips {
-                        tx.request(FrontendRequest::UpdateFixIps(handle, ips))
-                            .await?;
-                    }
-                    if let Some(enter_hook) = enter_hook {
-                        tx.request(FrontendRequest::UpdateEnterHook(handle, Some(enter_hook)))
-                            .await?;
-                    }
-                    break;
-                }
+            let config = ClientConfig {
+                hostname,
+                port: port.unwrap_or(DEFAULT_PORT),
+                fix_ips: ips.unwrap_or_default(),
+                cmd: enter_hook,
+                mac,
+                pos: position.unwrap_or_default(),
+            };
+            let handle = client.create_client(config).await?;
+            if activate {
+                client.set_active(handle, true).await?;
             }
+            if json {
+                println!("{}", json!({ "handle": handle }));
+            } else {
+                println!("{handle}");
+            }
+            return Ok(());
         }
-        CliSubcommand::RemoveClient { id } => tx.request(FrontendRequest::Delete(id)).await?,
-        CliSubcommand::Activate { id } => tx.request(FrontendRequest::Activate(id, true)).await?,
-        CliSubcommand::Deactivate { id } => {
-            tx.request(FrontendRequest::Activate(id, false)).await?
-        }
+        CliSubcommand::RemoveClient { id } => FrontendRequest::Delete(id),
+        CliSubcommand::Activate { id } => FrontendRequest::Activate {
+            handle: id,
+            active: true,
+        },
+        CliSubcommand::Deactivate { id } => FrontendRequest::Activate {
+            handle: id,
+            active: false,
+        },
         CliSubcommand::List => {
-            tx.request(FrontendRequest::Enumerate()).await?;
-            while let Some(e) = rx.next().await {
-                if let FrontendEvent::Enumerate(clients) = e? {
-                    for (handle, config, state) in clients {
-                        let host = config.hostname.unwrap_or("unknown".to_owned());
-                        let port = config.port;
-                        let pos = config.pos;
-                        let active = state.active;
-                        let ips = state.ips;
-                        println!(
-                            "id {handle}: {host}:{port} ({pos}) active: {active}, ips: {ips:?}"
-                        );
-                    }
-                    break;
-                }
+            let clients = client.clients().await?;
+            if json {
+                let clients = clients
+                    .into_iter()
+                    .map(|(handle, config, state)| {
+                        json!({ "handle": handle, "config": config, "state": state })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::to_string(&clients)?);
+                return Ok(());
             }
+            for (handle, config, state) in clients {
+                let host = config.hostname.unwrap_or("unknown".to_owned());
+                let port = config.port;
+                let pos = config.pos;
+                let active = state.active;
+                let ips = state.ips;
+                println!("id {handle}: {host}:{port} ({pos}) active: {active}, ips: {ips:?}");
+            }
+            return Ok(());
         }
-        CliSubcommand::SetHost { id, host } => {
-            tx.request(FrontendRequest::UpdateHostname(id, host))
-                .await?
+        CliSubcommand::Status => {
+            let status = Status::fetch(&mut client).await?;
+            if json {
+                println!("{}", serde_json::to_string(&status)?);
+            } else {
+                println!("{status}");
+            }
+            return Ok(());
         }
-        CliSubcommand::SetPort { id, port } => {
-            tx.request(FrontendRequest::UpdatePort(id, port)).await?
+        CliSubcommand::Fingerprint => {
+            let fingerprint = client.sync().await?.into_iter().find_map(|e| match e {
+                FrontendEvent::PublicKeyFingerprint(fingerprint) => Some(fingerprint),
+                _ => None,
+            });
+            let Some(fingerprint) = fingerprint else {
+                return Err(CliError::Service("no fingerprint reported".to_owned()));
+            };
+            if json {
+                println!("{}", json!({ "fingerprint": fingerprint }));
+            } else {
+                println!("{fingerprint}");
+            }
+            return Ok(());
         }
-        CliSubcommand::SetPosition { id, pos } => {
-            tx.request(FrontendRequest::UpdatePosition(id, pos)).await?
+        CliSubcommand::ListKeys => {
+            let keys = client
+                .sync()
+                .await?
+                .into_iter()
+                .rev()
+                .find_map(|e| match e {
+                    FrontendEvent::AuthorizedUpdated(keys) => Some(keys),
+                    _ => None,
+                });
+            let Some(keys) = keys else {
+                return Err(CliError::Service("no authorized keys reported".to_owned()));
+            };
+            let keys = keys.into_iter().collect::<BTreeMap<_, _>>();
+            if json {
+                println!("{}", serde_json::to_string(&keys)?);
+            } else {
+                for (fingerprint, description) in keys {
+                    println!("{fingerprint} {description}");
+                }
+            }
+            return Ok(());
         }
-        CliSubcommand::SetIps { id, ips } => {
-            tx.request(FrontendRequest::UpdateFixIps(id, ips)).await?
+        CliSubcommand::SetHost { id, host } => FrontendRequest::UpdateHostname {
+            handle: id,
+            hostname: host,
+        },
+        CliSubcommand::SetPort { id, port } => FrontendRequest::UpdatePort { handle: id, port },
+        CliSubcommand::SetServicePort { port } => {
+            let port = client.change_port(port).await?;
+            if json {
+                println!("{}", json!({ "port": port }));
+            } else {
+                println!("listening on port {port}");
+            }
+            return Ok(());
         }
-        CliSubcommand::EnableCapture => tx.request(FrontendRequest::EnableCapture).await?,
-        CliSubcommand::EnableEmulation => tx.request(FrontendRequest::EnableEmulation).await?,
+        CliSubcommand::SetEnterHook { id, command } => FrontendRequest::UpdateEnterHook {
+            handle: id,
+            enter_hook: command,
+        },
+        CliSubcommand::Resolve { id } => FrontendRequest::ResolveDns(id),
+        CliSubcommand::SetPosition { id, pos } => FrontendRequest::UpdatePosition {
+            handle: id,
+            position: pos,
+        },
+        CliSubcommand::SetIps { id, ips } => FrontendRequest::UpdateFixIps { handle: id, ips },
+        CliSubcommand::SetMac { id, mac } => FrontendRequest::UpdateMacAddress { handle: id, mac },
+        CliSubcommand::EnableCapture => FrontendRequest::EnableCapture,
+        CliSubcommand::EnableEmulation => FrontendRequest::EnableEmulation,
         CliSubcommand::AuthorizeKey {
             description,
             sha256_fingerprint,
-        } => {
-            tx.request(FrontendRequest::AuthorizeKey(
-                description,
-                sha256_fingerprint,
-            ))
-            .await?
-        }
+        } => FrontendRequest::AuthorizeKey {
+            description,
+            fingerprint: sha256_fingerprint,
+        },
         CliSubcommand::RemoveAuthorizedKey { sha256_fingerprint } => {
-            tx.request(FrontendRequest::RemoveAuthorizedKey(sha256_fingerprint))
+            FrontendRequest::RemoveAuthorizedKey(sha256_fingerprint)
+        }
+        CliSubcommand::Unlock => {
+            let passphrase = rpassword::prompt_password("passphrase: ")?;
+            FrontendRequest::Unlock(passphrase)
+        }
+        CliSubcommand::BlockKey { sha256_fingerprint } => {
+            FrontendRequest::BlockKey(sha256_fingerprint)
+        }
+        CliSubcommand::UnblockKey { sha256_fingerprint } => {
+            FrontendRequest::UnblockKey(sha256_fingerprint)
+        }
+        CliSubcommand::BlockIp { ip } => FrontendRequest::BlockIp(ip),
+        CliSubcommand::UnblockIp { ip } => FrontendRequest::UnblockIp(ip),
+        CliSubcommand::Stats => {
+            let (clients, incoming) = client.stats().await?;
+            if json {
+                println!("{}", json!({ "clients": clients, "incoming": incoming }));
+                return Ok(());
+            }
+            for (handle, stats) in clients {
+                print_stats(&format!("client {handle}"), &stats);
+            }
+            for (fingerprint, stats) in incoming {
+                print_stats(&format!("incoming {fingerprint}"), &stats);
+            }
+            return Ok(());
+        }
+        CliSubcommand::Profiles => {
+            let state = client
+                .sync()
                 .await?
+                .into_iter()
+                .rev()
+                .find_map(|e| match e {
+                    FrontendEvent::ProfileChanged { active, profiles } => Some((active, profiles)),
+                    _ => None,
+                });
+            let Some((active, profiles)) = state else {
+                return Err(CliError::Service("no profiles reported".to_owned()));
+            };
+            if json {
+                println!("{}", json!({ "active": active, "profiles": profiles }));
+                return Ok(());
+            }
+            for profile in profiles {
+                let marker = if profile == active { "*" } else { " " };
+                println!("{marker} {profile}");
+            }
+            return Ok(());
         }
+        CliSubcommand::SwitchProfile { name } => FrontendRequest::SwitchProfile(name),
+        CliSubcommand::SetNetwork { network } => FrontendRequest::SetNetwork(network),
+        CliSubcommand::SetReleaseBind { keys } => FrontendRequest::SetReleaseBind(keys),
+        CliSubcommand::Apply { path, dry_run } => {
+            let document = fs::read_to_string(&path).map_err(|e| CliError::Read(path, e))?;
+            let document = toml::from_str::<Document>(&document)?;
+            let state = State::fetch(&mut client).await?;
+            let actions = apply::plan(&state, &document);
+            if json {
+                let changes = actions.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                println!("{}", json!({ "changes": changes, "dry_run": dry_run }));
+            } else if actions.is_empty() {
+                println!("nothing to do");
+            }
+            for action in actions {
+                if !json {
+                    println!("{action}");
+                }
+                if !dry_run {
+                    apply::execute(action, &mut client).await?;
+                }
+            }
+            return Ok(());
+        }
+        CliSubcommand::Export => {
+            let state = State::fetch(&mut client).await?;
+            if json {
+                println!("{}", serde_json::to_string(&state.export())?);
+            } else {
+                print!("{}", toml::to_string(&state.export())?);
+            }
+            return Ok(());
+        }
+        CliSubcommand::Watch => return watch::watch(&mut client, json).await,
+    };
+    match client.request(request).await? {
+        response if json => println!("{}", serde_json::to_string(&response)?),
+        FrontendResponse::Profile(active) => println!("active profile: {active}"),
+        _ => {}
     }
     Ok(())
 }
+
+fn print_stats(name: &str, stats: &ConnectionStats) {
+    match stats.addr {
+        Some(addr) => println!("{name}: {} @ {addr}", stats.state),
+        None => println!("{name}: {}", stats.state),
+    }
+    println!(
+        "  sent: {} datagrams, {} bytes ({})",
+        stats.datagrams_sent,
+        stats.bytes_sent,
+        event_counts(&stats.events_sent)
+    );
+    println!(
+        "  received: {} datagrams, {} bytes ({})",
+        stats.datagrams_received,
+        stats.bytes_received,
+        event_counts(&stats.events_received)
+    );
+    let last_event = match stats.since_last_event {
+        Some(d) => format!("{:.1}s ago", d.as_secs_f64()),
+        None => "never".to_owned(),
+    };
+    println!(
+        "  send errors: {}, reconnects: {}, last event: {last_event}",
+        stats.send_errors, stats.reconnects
+    );
+}
+
+fn event_counts(events: &BTreeMap<String, u64>) -> String {
+    events
+        .iter()
+        .map(|(event, n)| format!("{event}: {n}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,91 @@
+use std::{fmt::Display, net::SocketAddr};
+
+use serde::Serialize;
+
+use lan_mouse_ipc::{ClientHandle, ConnectionState, FrontendEvent, LanMouseClient};
+
+use crate::CliError;
+
+/// overall state of the service
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Status {
+    capture: bool,
+    emulation: bool,
+    /// the client currently receiving input
+    controlling: Option<ClientHandle>,
+    port: u16,
+    fingerprint: String,
+    profile: String,
+    release_bind: Vec<String>,
+    clients: usize,
+    active_clients: usize,
+    /// connected incoming devices
+    incoming: Vec<Incoming>,
+}
+
+#[derive(Debug, Serialize)]
+struct Incoming {
+    fingerprint: String,
+    addr: Option<SocketAddr>,
+}
+
+impl Status {
+    /// request the current status from the service
+    pub(crate) async fn fetch(client: &mut LanMouseClient) -> Result<Self, CliError> {
+        let mut status = Self::default();
+        for event in client.sync().await? {
+            match event {
+                FrontendEvent::CaptureStatus(s) => status.capture = s.into(),
+                FrontendEvent::EmulationStatus(s) => status.emulation = s.into(),
+                FrontendEvent::Controlling(handle) => status.controlling = handle,
+                FrontendEvent::PortChanged { port, .. } => status.port = port,
+                FrontendEvent::PublicKeyFingerprint(f) => status.fingerprint = f,
+                FrontendEvent::ProfileChanged { active, .. } => status.profile = active,
+                FrontendEvent::ReleaseBind(keys) => status.release_bind = keys,
+                FrontendEvent::Enumerate(clients) => {
+                    status.clients = clients.len();
+                    status.active_clients = clients.iter().filter(|(_, _, s)| s.active).count();
+                }
+                _ => {}
+            }
+        }
+        let (_, incoming) = client.stats().await?;
+        status.incoming = incoming
+            .into_iter()
+            .filter(|(_, stats)| stats.state == ConnectionState::Connected)
+            .map(|(fingerprint, stats)| Incoming {
+                fingerprint,
+                addr: stats.addr,
+            })
+            .collect();
+        Ok(status)
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enabled = |b| if b { "enabled" } else { "disabled" };
+        writeln!(f, "capture: {}", enabled(self.capture))?;
+        writeln!(f, "emulation: {}", enabled(self.emulation))?;
+        if let Some(handle) = self.controlling {
+            writeln!(f, "controlling: client {handle}")?;
+        }
+        writeln!(f, "port: {}", self.port)?;
+        writeln!(f, "fingerprint: {}", self.fingerprint)?;
+        writeln!(f, "profile: {}", self.profile)?;
+        writeln!(f, "release bind: {}", self.release_bind.join(" + "))?;
+        writeln!(
+            f,
+            "clients: {} ({} active)",
+            self.clients, self.active_clients
+        )?;
+        write!(f, "incoming devices: {}", self.incoming.len())?;
+        for Incoming { fingerprint, addr } in &self.incoming {
+            match addr {
+                Some(addr) => write!(f, "\n  {fingerprint} @ {addr}")?,
+                None => write!(f, "\n  {fingerprint}")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use lan_mouse_ipc::{ClientConfig, ClientError, ClientState, FrontendEvent, LanMouseClient};
+
+use crate::CliError;
+
+/// print events of the service until the connection is closed,
+/// starting with the current state sent to every new frontend
+pub(crate) async fn watch(client: &mut LanMouseClient, json: bool) -> Result<(), CliError> {
+    loop {
+        match client.next_event().await {
+            Ok(event) => print_event(&event, json)?,
+            Err(ClientError::Closed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn print_event(event: &FrontendEvent, json: bool) -> Result<(), CliError> {
+    if json {
+        println!("{}", serde_json::to_string(event)?);
+    } else {
+        for line in describe(event) {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// human readable description of an event
+fn describe(event: &FrontendEvent) -> Vec<String> {
+    let line = match event {
+        FrontendEvent::Created {
+            handle,
+            config,
+            state,
+        } => format!("client {handle} created: {}", client(config, state)),
+        FrontendEvent::State {
+            handle,
+            config,
+            state,
+        } => format!("client {handle}: {}", client(config, state)),
+        FrontendEvent::Deleted(handle) => format!("client {handle} deleted"),
+        FrontendEvent::PortChanged { port, error: None } => format!("listening on port {port}"),
+        FrontendEvent::PortChanged {
+            port,
+            error: Some(e),
+        } => format!("failed to change port, still listening on {port}: {e}"),
+        FrontendEvent::Enumerate(clients) => {
+            return clients
+                .iter()
+                .map(|(handle, config, state)| {
+                    format!("client {handle}: {}", client(config, state))
+                })
+                .collect();
+        }
+        FrontendEvent::Error(e) => format!("error: {e}"),
+        FrontendEvent::CaptureStatus(status) => format!("capture: {status:?}"),
+        FrontendEvent::EmulationStatus(status) => format!("emulation: {status:?}"),
+        FrontendEvent::Controlling(Some(handle)) => format!("controlling client {handle}"),
+        FrontendEvent::Controlling(None) => "not controlling a client".to_owned(),
+        FrontendEvent::AuthorizedUpdated(keys) => {
+            format!("authorized keys: {}", keys.len())
+        }
+        FrontendEvent::PublicKeyFingerprint(fingerprint) => {
+            format!("public key fingerprint: {fingerprint}")
+        }
+        FrontendEvent::DeviceConnected { addr, fingerprint } => {
+            format!("device connected: {fingerprint} @ {addr}")
+        }
+        FrontendEvent::DeviceEntered {
+            fingerprint,
+            addr,
+            pos,
+        } => format!("device entered ({pos}): {fingerprint} @ {addr}"),
+        FrontendEvent::IncomingDisconnected(addr) => format!("device left: {addr}"),
+        FrontendEvent::ConnectionAttempt { fingerprint } => {
+            format!("connection attempt of unauthorized key {fingerprint}")
+        }
+        FrontendEvent::PassphraseRequired => "passphrase required".to_owned(),
+        FrontendEvent::BlockedUpdated { fingerprints, ips } => format!(
+            "blocked: {} keys, {} ip addresses",
+            fingerprints.len(),
+            ips.len()
+        ),
+        FrontendEvent::ProfileChanged { active, .. } => format!("profile: {active}"),
+        FrontendEvent::ReleaseBind(keys) => format!("release bind: {}", keys.join(" + ")),
+    };
+    vec![line]
+}
+
+fn client(config: &ClientConfig, state: &ClientState) -> String {
+    let host = config.hostname.as_deref().unwrap_or("unknown");
+    let port = config.port;
+    let pos = config.pos;
+    let active = if state.active { "active" } else { "inactive" };
+    let alive = if state.alive { "alive" } else { "unreachable" };
+    format!(
+        "{host}:{port} ({pos}) {active}, {alive}, ips: {:?}",
+        state.ips
+    )
+}
@@ -15,6 +15,8 @@ pub struct AuthorizationWindow {
     #[template_child]
     pub fingerprint: TemplateChild<Label>,
     #[template_child]
+    pub block_button: TemplateChild<Button>,
+    #[template_child]
     pub cancel_button: TemplateChild<Button>,
     #[template_child]
     pub confirm_button: TemplateChild<Button>,
@@ -46,6 +48,12 @@ impl AuthorizationWindow {
         self.obj().emit_by_name("confirm-clicked", &[&fp])
     }
 
+    #[template_callback]
+    fn handle_block(&self, _button: Button) {
+        let fp = self.fingerprint.text().as_str().trim().to_owned();
+        self.obj().emit_by_name("block-clicked", &[&fp])
+    }
+
     #[template_callback]
     fn handle_cancel(&self, _: Button) {
         self.obj().emit_by_name("cancel-clicked", &[])
@@ -64,6 +72,9 @@ impl ObjectImpl for AuthorizationWindow {
                 Signal::builder("confirm-clicked")
                     .param_types([String::static_type()])
                     .build(),
+                Signal::builder("block-clicked")
+                    .param_types([String::static_type()])
+                    .build(),
                 Signal::builder("cancel-clicked").build(),
             ]
         })
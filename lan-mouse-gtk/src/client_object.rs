@@ -16,6 +16,7 @@ impl ClientObject {
             .property("hostname", client.hostname)
             .property("port", client.port as u32)
             .property("position", client.pos.to_string())
+            .property("mac", client.mac.map(|m| m.to_string()))
             .property("active", state.active)
             .property(
                 "ips",
@@ -41,6 +42,7 @@ pub struct ClientData {
     pub port: u32,
     pub active: bool,
     pub position: String,
+    pub mac: Option<String>,
     pub resolving: bool,
     pub ips: Vec<String>,
 }
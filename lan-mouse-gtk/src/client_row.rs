@@ -4,7 +4,7 @@ use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gtk::glib::{self, Object};
 
-use lan_mouse_ipc::{DEFAULT_PORT, Position};
+use lan_mouse_ipc::{DEFAULT_PORT, MacAddress, Position};
 
 use super::ClientObject;
 
@@ -79,6 +79,13 @@ impl ClientRow {
             .sync_create()
             .build();
 
+        // bind mac address to mac edit field
+        let mac_binding = client_object
+            .bind_property("mac", &self.imp().mac.get(), "text")
+            .transform_to(|_, v: Option<String>| Some(v.unwrap_or_default()))
+            .sync_create()
+            .build();
+
         // bind position to selected position
         let position_binding = client_object
             .bind_property("position", &self.imp().position.get(), "selected")
@@ -120,6 +127,7 @@ impl ClientRow {
         bindings.push(title_binding);
         bindings.push(port_binding);
         bindings.push(subtitle_binding);
+        bindings.push(mac_binding);
         bindings.push(position_binding);
         bindings.push(resolve_binding);
         bindings.push(ip_binding);
@@ -143,6 +151,10 @@ impl ClientRow {
         self.imp().set_port(port);
     }
 
+    pub fn set_mac(&self, mac: Option<MacAddress>) {
+        self.imp().set_mac(mac.map(|m| m.to_string()));
+    }
+
     pub fn set_position(&self, pos: Position) {
         self.imp().set_pos(pos);
     }
@@ -23,6 +23,8 @@ pub struct ClientRow {
     #[template_child]
     pub port: TemplateChild<gtk::Entry>,
     #[template_child]
+    pub mac: TemplateChild<gtk::Entry>,
+    #[template_child]
     pub position: TemplateChild<ComboRow>,
     #[template_child]
     pub delete_row: TemplateChild<ActionRow>,
@@ -33,6 +35,7 @@ pub struct ClientRow {
     pub bindings: RefCell<Vec<Binding>>,
     hostname_change_handler: RefCell<Option<SignalHandlerId>>,
     port_change_handler: RefCell<Option<SignalHandlerId>>,
+    mac_change_handler: RefCell<Option<SignalHandlerId>>,
     position_change_handler: RefCell<Option<SignalHandlerId>>,
     set_state_handler: RefCell<Option<SignalHandlerId>>,
     pub client_object: RefCell<Option<ClientObject>>,
@@ -83,6 +86,14 @@ impl ObjectImpl for ClientRow {
             }
         ));
         self.port_change_handler.replace(Some(handler));
+        let handler = self.mac.connect_changed(clone!(
+            #[weak(rename_to = row)]
+            self,
+            move |entry| {
+                row.handle_mac_changed(entry);
+            }
+        ));
+        self.mac_change_handler.replace(Some(handler));
         let handler = self.position.connect_selected_notify(clone!(
             #[weak(rename_to = row)]
             self,
@@ -119,6 +130,9 @@ impl ObjectImpl for ClientRow {
                 Signal::builder("request-port-change")
                     .param_types([u32::static_type()])
                     .build(),
+                Signal::builder("request-mac-change")
+                    .param_types([String::static_type()])
+                    .build(),
                 Signal::builder("request-position-change")
                     .param_types([u32::static_type()])
                     .build(),
@@ -157,6 +171,11 @@ impl ClientRow {
             .emit_by_name::<()>("request-hostname-change", &[&hostname_entry.text()]);
     }
 
+    fn handle_mac_changed(&self, mac_entry: &Entry) {
+        self.obj()
+            .emit_by_name::<()>("request-mac-change", &[&mac_entry.text()]);
+    }
+
     fn handle_position_changed(&self, position: &ComboRow) {
         self.obj()
             .emit_by_name("request-position-change", &[&position.selected()])
@@ -190,6 +209,20 @@ impl ClientRow {
         self.port.set_position(position);
     }
 
+    pub(super) fn set_mac(&self, mac: Option<String>) {
+        let position = self.mac.position();
+        let handler = self.mac_change_handler.borrow();
+        let handler = handler.as_ref().expect("signal handler");
+        self.mac.block_signal(handler);
+        self.client_object
+            .borrow_mut()
+            .as_mut()
+            .expect("client object")
+            .set_property("mac", mac);
+        self.mac.unblock_signal(handler);
+        self.mac.set_position(position);
+    }
+
     pub(super) fn set_pos(&self, pos: Position) {
         let handler = self.position_change_handler.borrow();
         let handler = handler.as_ref().expect("signal handler");
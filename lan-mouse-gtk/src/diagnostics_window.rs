@@ -0,0 +1,93 @@
+mod imp;
+
+use std::collections::{BTreeMap, HashSet};
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use glib::Object;
+use gtk::{gio, glib};
+
+use lan_mouse_ipc::ConnectionStats;
+
+glib::wrapper! {
+    pub struct DiagnosticsWindow(ObjectSubclass<imp::DiagnosticsWindow>)
+    @extends adw::Window, gtk::Window, gtk::Widget,
+    @implements gio::ActionGroup, gio::ActionMap, gtk::Accessible, gtk::Buildable,
+                gtk::ConstraintTarget, gtk::Native, gtk::Root, gtk::ShortcutManager;
+}
+
+impl DiagnosticsWindow {
+    pub(crate) fn new() -> Self {
+        Object::builder().build()
+    }
+
+    /// update the statistics, given as (id, title, stats)
+    pub(crate) fn set_stats(&self, stats: Vec<(String, String, ConnectionStats)>) {
+        let list = &self.imp().stats_list;
+        let mut rows = self.imp().rows.borrow_mut();
+        let ids: HashSet<&String> = stats.iter().map(|(id, _, _)| id).collect();
+        rows.retain(|id, (row, _)| {
+            let keep = ids.contains(id);
+            if !keep {
+                list.remove(row);
+            }
+            keep
+        });
+        for (id, title, stats) in stats.iter() {
+            let (row, details) = rows.entry(id.clone()).or_insert_with(|| {
+                let row = adw::ExpanderRow::new();
+                let details = gtk::Label::builder()
+                    .xalign(0.0)
+                    .selectable(true)
+                    .margin_top(10)
+                    .margin_bottom(10)
+                    .margin_start(10)
+                    .margin_end(10)
+                    .build();
+                details.add_css_class("monospace");
+                row.add_row(&details);
+                list.append(&row);
+                (row, details)
+            });
+            row.set_title(title);
+            row.set_subtitle(&summary(stats));
+            details.set_text(&details_text(stats));
+        }
+    }
+}
+
+fn summary(stats: &ConnectionStats) -> String {
+    let state = match stats.addr {
+        Some(addr) => format!("{} @ {addr}", stats.state),
+        None => stats.state.to_string(),
+    };
+    match stats.since_last_event {
+        Some(d) => format!("{state}, last event {:.1}s ago", d.as_secs_f64()),
+        None => state,
+    }
+}
+
+fn details_text(stats: &ConnectionStats) -> String {
+    format!(
+        "sent:        {} datagrams, {} bytes\n{}\n\
+         received:    {} datagrams, {} bytes\n{}\n\
+         send errors: {}\n\
+         reconnects:  {}",
+        stats.datagrams_sent,
+        stats.bytes_sent,
+        event_counts(&stats.events_sent),
+        stats.datagrams_received,
+        stats.bytes_received,
+        event_counts(&stats.events_received),
+        stats.send_errors,
+        stats.reconnects,
+    )
+}
+
+fn event_counts(events: &BTreeMap<String, u64>) -> String {
+    events
+        .iter()
+        .map(|(event, n)| format!("  {event:<13}{n}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
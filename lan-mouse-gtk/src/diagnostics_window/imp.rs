@@ -0,0 +1,38 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use adw::ExpanderRow;
+use adw::subclass::prelude::*;
+use glib::subclass::InitializingObject;
+use gtk::{CompositeTemplate, Label, ListBox, glib};
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/feschber/LanMouse/diagnostics_window.ui")]
+pub struct DiagnosticsWindow {
+    #[template_child]
+    pub stats_list: TemplateChild<ListBox>,
+    /// rows and detail labels by connection
+    pub rows: RefCell<HashMap<String, (ExpanderRow, Label)>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DiagnosticsWindow {
+    const NAME: &'static str = "DiagnosticsWindow";
+    const ABSTRACT: bool = false;
+
+    type Type = super::DiagnosticsWindow;
+    type ParentType = adw::Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for DiagnosticsWindow {}
+impl WidgetImpl for DiagnosticsWindow {}
+impl WindowImpl for DiagnosticsWindow {}
+impl ApplicationWindowImpl for DiagnosticsWindow {}
+impl AdwWindowImpl for DiagnosticsWindow {}
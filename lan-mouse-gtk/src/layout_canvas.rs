@@ -0,0 +1,299 @@
+mod imp;
+
+use adw::subclass::prelude::*;
+use gtk::glib::{self, clone};
+use gtk::{cairo, gdk, prelude::*};
+
+use lan_mouse_ipc::{ClientHandle, Position};
+
+glib::wrapper! {
+    pub struct LayoutCanvas(ObjectSubclass<imp::LayoutCanvas>)
+        @extends gtk::DrawingArea, gtk::Widget,
+        @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget;
+}
+
+/// space between the screens
+const GAP: f64 = 8.;
+/// offset between clients sharing a position
+const STACK_OFFSET: f64 = 12.;
+/// aspect ratio used if the geometry of the local screen is unknown
+const DEFAULT_ASPECT: f64 = 16. / 9.;
+
+#[derive(Clone)]
+pub struct LayoutClient {
+    pub name: String,
+    pub position: Position,
+    pub active: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+impl Rect {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x <= self.x + self.w && y >= self.y && y <= self.y + self.h
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.x + self.w / 2., self.y + self.h / 2.)
+    }
+
+    fn offset(self, dx: f64, dy: f64) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..self
+        }
+    }
+}
+
+impl LayoutCanvas {
+    fn setup(&self) {
+        self.set_content_height(260);
+        self.set_draw_func(clone!(
+            #[weak(rename_to = canvas)]
+            self,
+            move |_, cr, width, height| canvas.draw(cr, width as f64, height as f64)
+        ));
+        let drag = gtk::GestureDrag::new();
+        drag.connect_drag_begin(clone!(
+            #[weak(rename_to = canvas)]
+            self,
+            move |_, x, y| canvas.drag_begin(x, y)
+        ));
+        drag.connect_drag_update(clone!(
+            #[weak(rename_to = canvas)]
+            self,
+            move |_, dx, dy| canvas.drag_update(dx, dy)
+        ));
+        drag.connect_drag_end(clone!(
+            #[weak(rename_to = canvas)]
+            self,
+            move |_, dx, dy| canvas.drag_end(dx, dy)
+        ));
+        self.add_controller(drag);
+    }
+
+    pub fn set_client(&self, handle: ClientHandle, hostname: Option<String>, position: Position) {
+        let name = hostname.unwrap_or_else(|| format!("client {handle}"));
+        let mut clients = self.imp().clients.borrow_mut();
+        let client = clients.entry(handle).or_insert_with(|| LayoutClient {
+            name: name.clone(),
+            position,
+            active: false,
+        });
+        client.name = name;
+        client.position = position;
+        drop(clients);
+        self.queue_draw();
+    }
+
+    pub fn set_client_active(&self, handle: ClientHandle, active: bool) {
+        if let Some(client) = self.imp().clients.borrow_mut().get_mut(&handle) {
+            client.active = active;
+        }
+        self.queue_draw();
+    }
+
+    pub fn remove_client(&self, handle: ClientHandle) {
+        self.imp().clients.borrow_mut().remove(&handle);
+        self.queue_draw();
+    }
+
+    /// the local screen, centered and sized to leave room for a client on each side
+    fn local_screen(&self, width: f64, height: f64) -> Rect {
+        let aspect = gdk::Display::default()
+            .and_then(|d| d.monitors().item(0))
+            .and_downcast::<gdk::Monitor>()
+            .map(|m| m.geometry())
+            .filter(|g| g.width() > 0 && g.height() > 0)
+            .map(|g| g.width() as f64 / g.height() as f64)
+            .unwrap_or(DEFAULT_ASPECT);
+        let w = ((width - 4. * GAP) / 3.)
+            .min((height - 4. * GAP) / 3. * aspect)
+            .max(1.);
+        let h = w / aspect;
+        Rect {
+            x: (width - w) / 2.,
+            y: (height - h) / 2.,
+            w,
+            h,
+        }
+    }
+
+    /// rectangles of all clients as (handle, client, rect, conflicting)
+    fn client_rects(&self, local: Rect) -> Vec<(ClientHandle, LayoutClient, Rect, bool)> {
+        let clients = self.imp().clients.borrow();
+        let mut stacked = [0usize; 4];
+        let mut active = [0usize; 4];
+        for client in clients.values().filter(|c| c.active) {
+            active[index(client.position)] += 1;
+        }
+        let drag = self.imp().drag.get();
+        clients
+            .iter()
+            .map(|(&handle, client)| {
+                let i = index(client.position);
+                let stack = stacked[i] as f64 * STACK_OFFSET;
+                stacked[i] += 1;
+                let mut rect = slot(local, client.position).offset(stack, stack);
+                if let Some((_, dx, dy)) = drag.filter(|(h, _, _)| *h == handle) {
+                    rect = rect.offset(dx, dy);
+                }
+                let conflict = client.active && active[i] > 1;
+                (handle, client.clone(), rect, conflict)
+            })
+            .collect()
+    }
+
+    fn draw(&self, cr: &cairo::Context, width: f64, height: f64) {
+        let local = self.local_screen(width, height);
+        let clients = self.client_rects(local);
+
+        draw_screen(cr, local, (0.21, 0.52, 0.89, 0.9), "this device");
+
+        /* the edge the dragged client snaps to */
+        let drag = self.imp().drag.get();
+        if let Some((_, _, rect, _)) = clients
+            .iter()
+            .find(|(h, _, _, _)| drag.is_some_and(|(d, _, _)| d == *h))
+        {
+            let edge = match target(local, *rect) {
+                Position::Left => Rect { w: 4., ..local },
+                Position::Right => Rect {
+                    x: local.x + local.w - 4.,
+                    w: 4.,
+                    ..local
+                },
+                Position::Top => Rect { h: 4., ..local },
+                Position::Bottom => Rect {
+                    y: local.y + local.h - 4.,
+                    h: 4.,
+                    ..local
+                },
+            };
+            cr.set_source_rgba(1., 1., 1., 0.9);
+            cr.rectangle(edge.x, edge.y, edge.w, edge.h);
+            let _ = cr.fill();
+        }
+
+        /* the dragged client is drawn last to stay on top */
+        let (dragged, others): (Vec<_>, Vec<_>) = clients
+            .into_iter()
+            .partition(|(h, _, _, _)| drag.is_some_and(|(d, _, _)| d == *h));
+        for (_, client, rect, conflict) in others.into_iter().chain(dragged) {
+            let color = match (conflict, client.active) {
+                (true, _) => (0.88, 0.11, 0.14, 0.9),
+                (false, true) => (0.15, 0.64, 0.41, 0.9),
+                (false, false) => (0.6, 0.6, 0.6, 0.7),
+            };
+            let label = match conflict {
+                true => format!("{} (conflict)", client.name),
+                false => client.name,
+            };
+            draw_screen(cr, rect, color, &label);
+        }
+    }
+
+    fn drag_begin(&self, x: f64, y: f64) {
+        let (width, height) = (self.width() as f64, self.height() as f64);
+        let local = self.local_screen(width, height);
+        let hit = self
+            .client_rects(local)
+            .into_iter()
+            .rev()
+            .find(|(_, _, rect, _)| rect.contains(x, y));
+        self.imp()
+            .drag
+            .set(hit.map(|(handle, _, _, _)| (handle, 0., 0.)));
+    }
+
+    fn drag_update(&self, dx: f64, dy: f64) {
+        if let Some((handle, _, _)) = self.imp().drag.get() {
+            self.imp().drag.set(Some((handle, dx, dy)));
+            self.queue_draw();
+        }
+    }
+
+    fn drag_end(&self, dx: f64, dy: f64) {
+        let Some((handle, _, _)) = self.imp().drag.get() else {
+            return;
+        };
+        self.imp().drag.set(Some((handle, dx, dy)));
+        let (width, height) = (self.width() as f64, self.height() as f64);
+        let local = self.local_screen(width, height);
+        let dropped = self
+            .client_rects(local)
+            .into_iter()
+            .find(|(h, _, _, _)| *h == handle);
+        self.imp().drag.set(None);
+        self.queue_draw();
+        if let Some((_, client, rect, _)) = dropped {
+            let position = target(local, rect);
+            if position != client.position {
+                self.emit_by_name::<()>(
+                    "request-position-change",
+                    &[&handle, &position.to_string()],
+                );
+            }
+        }
+    }
+}
+
+fn index(position: Position) -> usize {
+    match position {
+        Position::Left => 0,
+        Position::Right => 1,
+        Position::Top => 2,
+        Position::Bottom => 3,
+    }
+}
+
+/// where a client at `position` is drawn
+fn slot(local: Rect, position: Position) -> Rect {
+    match position {
+        Position::Left => local.offset(-local.w - GAP, 0.),
+        Position::Right => local.offset(local.w + GAP, 0.),
+        Position::Top => local.offset(0., -local.h - GAP),
+        Position::Bottom => local.offset(0., local.h + GAP),
+    }
+}
+
+/// the edge of the local screen closest to a client dropped at `rect`
+fn target(local: Rect, rect: Rect) -> Position {
+    let ((x, y), (lx, ly)) = (rect.center(), local.center());
+    let (dx, dy) = ((x - lx) / local.w, (y - ly) / local.h);
+    if dx.abs() > dy.abs() {
+        if dx < 0. {
+            Position::Left
+        } else {
+            Position::Right
+        }
+    } else if dy < 0. {
+        Position::Top
+    } else {
+        Position::Bottom
+    }
+}
+
+fn draw_screen(cr: &cairo::Context, rect: Rect, (r, g, b, a): (f64, f64, f64, f64), label: &str) {
+    cr.set_source_rgba(r, g, b, a);
+    cr.rectangle(rect.x, rect.y, rect.w, rect.h);
+    let _ = cr.fill();
+    cr.set_source_rgba(1., 1., 1., 1.);
+    cr.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+    cr.set_font_size(12.);
+    if let Ok(extents) = cr.text_extents(label) {
+        let (x, y) = rect.center();
+        cr.move_to(
+            x - extents.width() / 2. - extents.x_bearing(),
+            y - extents.height() / 2. - extents.y_bearing(),
+        );
+        let _ = cr.show_text(label);
+    }
+}
@@ -0,0 +1,49 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use gtk::glib;
+use gtk::glib::subclass::Signal;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+
+use lan_mouse_ipc::ClientHandle;
+
+use super::LayoutClient;
+
+#[derive(Default)]
+pub struct LayoutCanvas {
+    pub clients: RefCell<BTreeMap<ClientHandle, LayoutClient>>,
+    /// the client being dragged and how far it was moved
+    pub drag: Cell<Option<(ClientHandle, f64, f64)>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for LayoutCanvas {
+    const NAME: &'static str = "LayoutCanvas";
+    const ABSTRACT: bool = false;
+
+    type Type = super::LayoutCanvas;
+    type ParentType = gtk::DrawingArea;
+}
+
+impl ObjectImpl for LayoutCanvas {
+    fn constructed(&self) {
+        self.parent_constructed();
+        self.obj().setup();
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                Signal::builder("request-position-change")
+                    .param_types([u64::static_type(), String::static_type()])
+                    .build(),
+            ]
+        })
+    }
+}
+
+impl WidgetImpl for LayoutCanvas {}
+impl DrawingAreaImpl for LayoutCanvas {}
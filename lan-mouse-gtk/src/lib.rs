@@ -1,16 +1,31 @@
 mod authorization_window;
 mod client_object;
 mod client_row;
+mod diagnostics_window;
 mod fingerprint_window;
 mod key_object;
 mod key_row;
+mod layout_canvas;
+mod passphrase_window;
+mod release_bind_window;
+mod tray;
 mod window;
 
-use std::{env, process, str};
+use std::{
+    cmp, env, process,
+    rc::Rc,
+    str,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
 
+use tray::{Tray, TrayAction};
 use window::Window;
 
-use lan_mouse_ipc::FrontendEvent;
+use lan_mouse_ipc::{
+    FrontendEvent, FrontendMessage, FrontendRequest, FrontendRequestWriter, FrontendResponse,
+};
 
 use adw::Application;
 use gtk::{IconTheme, gdk::Display, glib::clone, prelude::*};
@@ -21,6 +36,22 @@ use self::key_object::KeyObject;
 
 use thiserror::Error;
 
+/// how long to wait for the service before showing it as not running
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// delay before reconnecting to the service, doubled after every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// maximum delay between attempts to reconnect to the service
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// reported by the thread maintaining the connection to the service
+enum ServiceMessage {
+    Connected(FrontendRequestWriter),
+    Message(FrontendMessage),
+    Disconnected,
+}
+
 #[derive(Error, Debug)]
 pub enum GtkError {
     #[error("gtk frontend exited with non zero exit code: {0}")]
@@ -97,32 +128,80 @@ fn setup_menu(app: &adw::Application) {
     app.set_menubar(Some(&menu))
 }
 
-fn build_ui(app: &Application) {
-    log::debug!("connecting to lan-mouse-socket");
-    let (mut frontend_rx, frontend_tx) = match lan_mouse_ipc::connect() {
-        Ok(conn) => conn,
-        Err(e) => {
-            log::error!("{e}");
-            process::exit(1);
+/// keep connected to the service, reconnecting whenever the connection is lost.
+/// Failed attempts are retried with exponential backoff or right away
+/// once something is sent on `reconnect` (e.g. the service was started).
+fn maintain_connection(
+    sender: async_channel::Sender<ServiceMessage>,
+    reconnect: mpsc::Receiver<()>,
+) {
+    let mut connected = true;
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        log::debug!("connecting to lan-mouse-socket");
+        let (mut frontend_rx, frontend_tx) = match lan_mouse_ipc::connect(Some(CONNECT_TIMEOUT)) {
+            Ok(conn) => conn,
+            Err(e) => {
+                if connected {
+                    log::warn!("{e}");
+                    connected = false;
+                    if sender.send_blocking(ServiceMessage::Disconnected).is_err() {
+                        return;
+                    }
+                }
+                log::debug!("reconnecting in {delay:?}");
+                if let Err(RecvTimeoutError::Disconnected) = reconnect.recv_timeout(delay) {
+                    thread::sleep(delay);
+                }
+                delay = cmp::min(delay * 2, MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+        log::debug!("connected to lan-mouse-socket");
+        connected = true;
+        delay = RECONNECT_DELAY;
+        if sender
+            .send_blocking(ServiceMessage::Connected(frontend_tx))
+            .is_err()
+        {
+            return;
         }
-    };
-    log::debug!("connected to lan-mouse-socket");
-
-    let (sender, receiver) = async_channel::bounded(10);
-
-    gio::spawn_blocking(move || {
-        while let Some(e) = frontend_rx.next_event() {
+        while let Some(e) = frontend_rx.next_message() {
             match e {
-                Ok(e) => sender.send_blocking(e).unwrap(),
+                Ok(e) => {
+                    if sender.send_blocking(ServiceMessage::Message(e)).is_err() {
+                        return;
+                    }
+                }
                 Err(e) => {
                     log::error!("{e}");
                     break;
                 }
             }
         }
-    });
+        log::warn!("lost connection to the service");
+        connected = false;
+        if sender.send_blocking(ServiceMessage::Disconnected).is_err() {
+            return;
+        }
+    }
+}
 
-    let window = Window::new(app, frontend_tx);
+fn build_ui(app: &Application) {
+    let (sender, receiver) = async_channel::bounded(10);
+    let (reconnect, reconnect_rx) = mpsc::channel();
+
+    gio::spawn_blocking(move || maintain_connection(sender, reconnect_rx));
+
+    let window = Window::new(app);
+    window.set_reconnect(reconnect);
+    /* like the service started along with the frontend */
+    app.connect_shutdown(clone!(
+        #[strong]
+        window,
+        move |_| window.stop_service()
+    ));
+    let tray = setup_tray(&window);
 
     glib::spawn_future_local(clone!(
         #[weak]
@@ -130,21 +209,57 @@ fn build_ui(app: &Application) {
         async move {
             loop {
                 let notify = receiver.recv().await.unwrap_or_else(|_| process::exit(1));
-                match notify {
-                    FrontendEvent::Created(handle, client, state) => {
-                        window.new_client(handle, client, state)
+                let notify = match notify {
+                    ServiceMessage::Connected(frontend_tx) => {
+                        window.set_connection(Some(frontend_tx));
+                        continue;
+                    }
+                    ServiceMessage::Disconnected => {
+                        window.set_connection(None);
+                        continue;
+                    }
+                    ServiceMessage::Message(message) => message,
+                };
+                let notify = match notify {
+                    FrontendMessage::Event(e) => e,
+                    FrontendMessage::Response { result: Err(e), .. } => {
+                        window.show_toast(&e.to_string());
+                        continue;
+                    }
+                    FrontendMessage::Response {
+                        result: Ok(FrontendResponse::Stats { clients, incoming }),
+                        ..
+                    } => {
+                        window.update_stats(clients, incoming);
+                        continue;
                     }
+                    /* changes are reported as events */
+                    FrontendMessage::Response { result: Ok(_), .. } => continue,
+                };
+                if let Some(tray) = &tray {
+                    tray.handle_event(&notify);
+                }
+                match notify {
+                    FrontendEvent::Created {
+                        handle,
+                        config,
+                        state,
+                    } => window.new_client(handle, config, state),
                     FrontendEvent::Deleted(client) => window.delete_client(client),
-                    FrontendEvent::State(handle, config, state) => {
+                    FrontendEvent::State {
+                        handle,
+                        config,
+                        state,
+                    } => {
                         window.update_client_config(handle, config);
                         window.update_client_state(handle, state);
                     }
-                    FrontendEvent::NoSuchClient(_) => {}
                     FrontendEvent::Error(e) => window.show_toast(e.as_str()),
                     FrontendEvent::Enumerate(clients) => window.update_client_list(clients),
-                    FrontendEvent::PortChanged(port, msg) => window.update_port(port, msg),
+                    FrontendEvent::PortChanged { port, error } => window.update_port(port, error),
                     FrontendEvent::CaptureStatus(s) => window.set_capture(s.into()),
                     FrontendEvent::EmulationStatus(s) => window.set_emulation(s.into()),
+                    FrontendEvent::Controlling(_) => {}
                     FrontendEvent::AuthorizedUpdated(keys) => window.set_authorized_keys(keys),
                     FrontendEvent::PublicKeyFingerprint(fp) => window.set_pk_fp(&fp),
                     FrontendEvent::ConnectionAttempt { fingerprint } => {
@@ -166,6 +281,12 @@ fn build_ui(app: &Application) {
                     FrontendEvent::IncomingDisconnected(addr) => {
                         window.show_toast(format!("{addr} disconnected").as_str());
                     }
+                    FrontendEvent::PassphraseRequired => window.request_passphrase(),
+                    FrontendEvent::BlockedUpdated { .. } => {}
+                    FrontendEvent::ProfileChanged { active, profiles } => {
+                        window.update_profiles(active, profiles)
+                    }
+                    FrontendEvent::ReleaseBind(keys) => window.set_release_bind(keys),
                 }
             }
         }
@@ -173,3 +294,57 @@ fn build_ui(app: &Application) {
 
     window.present();
 }
+
+/// show a tray icon if the desktop has a tray, the window is then hidden to the tray when closed
+fn setup_tray(window: &Window) -> Option<Rc<Tray>> {
+    let (sender, receiver) = async_channel::unbounded();
+    let tray = Rc::new(Tray::new(sender)?);
+    /* registering waits for the session bus, keep the ui responsive meanwhile */
+    glib::spawn_future_local(clone!(
+        #[weak]
+        window,
+        #[weak]
+        tray,
+        async move {
+            if tray.register().await {
+                window.set_hide_on_close(true);
+            }
+        }
+    ));
+    tray.set_window_visible(window.is_visible());
+    window.connect_visible_notify(clone!(
+        #[weak]
+        tray,
+        move |window| tray.set_window_visible(window.is_visible())
+    ));
+
+    glib::spawn_future_local(clone!(
+        #[weak]
+        window,
+        async move {
+            while let Ok(action) = receiver.recv().await {
+                match action {
+                    TrayAction::Activate(handle, active) => {
+                        window.request(FrontendRequest::Activate { handle, active })
+                    }
+                    TrayAction::EnableCapture => window.request(FrontendRequest::EnableCapture),
+                    TrayAction::EnableEmulation => window.request(FrontendRequest::EnableEmulation),
+                    TrayAction::Authorize(fingerprint) => {
+                        window.present();
+                        window.request_authorization(&fingerprint);
+                    }
+                    TrayAction::ToggleWindow => match window.is_visible() {
+                        true => window.set_visible(false),
+                        false => window.present(),
+                    },
+                    TrayAction::Quit => {
+                        if let Some(app) = window.application() {
+                            app.quit();
+                        }
+                    }
+                }
+            }
+        }
+    ));
+    Some(tray)
+}
@@ -0,0 +1,17 @@
+mod imp;
+
+use glib::Object;
+use gtk::{gio, glib};
+
+glib::wrapper! {
+    pub struct PassphraseWindow(ObjectSubclass<imp::PassphraseWindow>)
+    @extends adw::Window, gtk::Window, gtk::Widget,
+    @implements gio::ActionGroup, gio::ActionMap, gtk::Accessible, gtk::Buildable,
+                gtk::ConstraintTarget, gtk::Native, gtk::Root, gtk::ShortcutManager;
+}
+
+impl PassphraseWindow {
+    pub(crate) fn new() -> Self {
+        Object::builder().build()
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::OnceLock;
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use glib::subclass::InitializingObject;
+use gtk::{
+    Button, CompositeTemplate, PasswordEntry,
+    glib::{self, subclass::Signal},
+    template_callbacks,
+};
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/feschber/LanMouse/passphrase_window.ui")]
+pub struct PassphraseWindow {
+    #[template_child]
+    pub passphrase: TemplateChild<PasswordEntry>,
+    #[template_child]
+    pub confirm_button: TemplateChild<Button>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PassphraseWindow {
+    const NAME: &'static str = "PassphraseWindow";
+    const ABSTRACT: bool = false;
+
+    type Type = super::PassphraseWindow;
+    type ParentType = adw::Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+        klass.bind_template_callbacks();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+#[template_callbacks]
+impl PassphraseWindow {
+    #[template_callback]
+    fn handle_confirm(&self, _button: Button) {
+        self.confirm();
+    }
+
+    #[template_callback]
+    fn handle_activate(&self, _entry: PasswordEntry) {
+        self.confirm();
+    }
+
+    fn confirm(&self) {
+        let passphrase = self.passphrase.text().as_str().to_owned();
+        self.obj().emit_by_name("confirm-clicked", &[&passphrase])
+    }
+}
+
+impl ObjectImpl for PassphraseWindow {
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                Signal::builder("confirm-clicked")
+                    .param_types([String::static_type()])
+                    .build(),
+            ]
+        })
+    }
+}
+
+impl WidgetImpl for PassphraseWindow {}
+impl WindowImpl for PassphraseWindow {}
+impl ApplicationWindowImpl for PassphraseWindow {}
+impl AdwWindowImpl for PassphraseWindow {}
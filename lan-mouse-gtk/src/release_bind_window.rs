@@ -0,0 +1,106 @@
+mod imp;
+
+use adw::subclass::prelude::*;
+use glib::Object;
+use gtk::{gdk, gio, glib, prelude::*};
+
+use input_event::scancode::Linux;
+
+glib::wrapper! {
+    pub struct ReleaseBindWindow(ObjectSubclass<imp::ReleaseBindWindow>)
+    @extends adw::Window, gtk::Window, gtk::Widget,
+    @implements gio::ActionGroup, gio::ActionMap, gtk::Accessible, gtk::Buildable,
+                gtk::ConstraintTarget, gtk::Native, gtk::Root, gtk::ShortcutManager;
+}
+
+impl ReleaseBindWindow {
+    /// `current` is the release bind in use, named like in the config file
+    pub(crate) fn new(current: &[String]) -> Self {
+        let window: Self = Object::builder().build();
+        let current = current.iter().map(|k| label(k)).collect::<Vec<_>>();
+        window.imp().keys_label.set_label(&current.join(" + "));
+        window
+    }
+
+    fn setup(&self) {
+        let controller = gtk::EventControllerKey::new();
+        controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        controller.connect_key_pressed(glib::clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_, _, keycode, _| {
+                window.key_pressed(keycode);
+                glib::Propagation::Stop
+            }
+        ));
+        controller.connect_key_released(glib::clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_, _, keycode, _| window.key_released(keycode)
+        ));
+        self.add_controller(controller);
+    }
+
+    fn key_pressed(&self, keycode: u32) {
+        {
+            let mut pressed = self.imp().pressed.borrow_mut();
+            let mut keys = self.imp().keys.borrow_mut();
+            /* a new combination starts once all keys were released */
+            if pressed.is_empty() {
+                keys.clear();
+            }
+            if !pressed.contains(&keycode) {
+                pressed.push(keycode);
+            }
+            match to_linux(keycode) {
+                Some(key) if !keys.contains(&key) => keys.push(key),
+                Some(_) => {}
+                None => log::warn!("unknown keycode {keycode}"),
+            }
+        }
+        self.update();
+    }
+
+    fn key_released(&self, keycode: u32) {
+        self.imp().pressed.borrow_mut().retain(|&k| k != keycode);
+    }
+
+    /// show the recorded combination
+    fn update(&self) {
+        let imp = self.imp();
+        let keys = imp.keys.borrow();
+        let names = keys.iter().map(|k| name(*k)).collect::<Vec<_>>();
+        let labels = names.iter().map(|n| label(n)).collect::<Vec<_>>();
+        imp.keys_label.set_label(&labels.join(" + "));
+        /* the service rejects combinations it can not use */
+        imp.confirm_button.set_sensitive(!keys.is_empty());
+    }
+
+    /// the recorded keys, named like in the config file
+    fn keys(&self) -> Vec<String> {
+        self.imp().keys.borrow().iter().map(|k| name(*k)).collect()
+    }
+}
+
+/// the linux key code of a hardware keycode
+fn to_linux(keycode: u32) -> Option<Linux> {
+    /* X11 and Wayland keycodes are offset by 8 from evdev */
+    let code = match gdk::Display::default().map(|d| d.backend()) {
+        Some(gdk::Backend::X11 | gdk::Backend::Wayland) => keycode.checked_sub(8)?,
+        _ => return None,
+    };
+    Linux::try_from(code).ok()
+}
+
+/// the name of a key as used in the config file
+fn name(key: Linux) -> String {
+    /* variants are (de)serialized by their name */
+    format!("{key:?}")
+}
+
+/// a key name for display
+pub(crate) fn label(name: &str) -> &str {
+    name.strip_prefix("Key").unwrap_or(name)
+}
@@ -0,0 +1,83 @@
+use std::{cell::RefCell, sync::OnceLock};
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use glib::subclass::InitializingObject;
+use gtk::{
+    Button, CompositeTemplate, Label,
+    glib::{self, subclass::Signal},
+    template_callbacks,
+};
+
+use input_event::scancode::Linux;
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/feschber/LanMouse/release_bind_window.ui")]
+pub struct ReleaseBindWindow {
+    #[template_child]
+    pub keys_label: TemplateChild<Label>,
+    #[template_child]
+    pub cancel_button: TemplateChild<Button>,
+    #[template_child]
+    pub confirm_button: TemplateChild<Button>,
+    /// hardware keycodes currently held down
+    pub pressed: RefCell<Vec<u32>>,
+    /// the recorded combination
+    pub keys: RefCell<Vec<Linux>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ReleaseBindWindow {
+    const NAME: &'static str = "ReleaseBindWindow";
+    const ABSTRACT: bool = false;
+
+    type Type = super::ReleaseBindWindow;
+    type ParentType = adw::Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+        klass.bind_template_callbacks();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+#[template_callbacks]
+impl ReleaseBindWindow {
+    #[template_callback]
+    fn handle_confirm(&self, _button: Button) {
+        /* key names do not contain spaces */
+        let keys = self.obj().keys().join(" ");
+        self.obj().emit_by_name("confirm-clicked", &[&keys])
+    }
+
+    #[template_callback]
+    fn handle_cancel(&self, _: Button) {
+        self.obj().close();
+    }
+}
+
+impl ObjectImpl for ReleaseBindWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+        self.obj().setup();
+    }
+
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                Signal::builder("confirm-clicked")
+                    .param_types([String::static_type()])
+                    .build(),
+            ]
+        })
+    }
+}
+
+impl WidgetImpl for ReleaseBindWindow {}
+impl WindowImpl for ReleaseBindWindow {}
+impl ApplicationWindowImpl for ReleaseBindWindow {}
+impl AdwWindowImpl for ReleaseBindWindow {}
@@ -0,0 +1,40 @@
+#[cfg(all(unix, not(target_os = "macos")))]
+mod status_notifier;
+
+use lan_mouse_ipc::ClientHandle;
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+use lan_mouse_ipc::FrontendEvent;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) use status_notifier::Tray;
+
+/// actions triggered from the tray
+#[derive(Clone, Debug)]
+pub(crate) enum TrayAction {
+    Activate(ClientHandle, bool),
+    EnableCapture,
+    EnableEmulation,
+    /// open the authorization dialog for a fingerprint
+    Authorize(String),
+    ToggleWindow,
+    Quit,
+}
+
+/// No-op tray (StatusNotifierItems are only available on linux and the BSDs)
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub(crate) struct Tray;
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+impl Tray {
+    pub(crate) fn new(_actions: async_channel::Sender<TrayAction>) -> Option<Self> {
+        None
+    }
+
+    pub(crate) async fn register(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn handle_event(&self, _event: &FrontendEvent) {}
+
+    pub(crate) fn set_window_visible(&self, _visible: bool) {}
+}
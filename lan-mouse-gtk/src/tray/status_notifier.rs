@@ -0,0 +1,562 @@
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    io, process,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use futures::StreamExt;
+use lan_mouse_ipc::{ClientConfig, ClientHandle, FrontendEvent};
+use tokio::runtime::Runtime;
+use zbus::{
+    Connection, connection,
+    fdo::DBusProxy,
+    interface,
+    object_server::SignalEmitter,
+    zvariant::{OwnedObjectPath, OwnedValue, Str, Value},
+};
+
+use super::TrayAction;
+
+/// object path of the StatusNotifierItem
+const ITEM_PATH: &str = "/StatusNotifierItem";
+/// object path of the menu
+const MENU_PATH: &str = "/MenuBar";
+/// name of the tray of the desktop
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+
+/// the state shown in the tray, kept up to date by frontend events
+#[derive(Default)]
+struct TrayState {
+    /// name, position and activation state of the clients
+    clients: BTreeMap<ClientHandle, (String, String, bool)>,
+    controlling: Option<ClientHandle>,
+    capture: bool,
+    emulation: bool,
+    /// fingerprints of devices waiting for authorization
+    pending: Vec<String>,
+    window_visible: bool,
+    /// revision of the menu layout
+    revision: u32,
+}
+
+impl TrayState {
+    /// returns whether the tray changed
+    fn handle_event(&mut self, event: &FrontendEvent) -> bool {
+        let client = |handle: &ClientHandle, config: &ClientConfig, active| {
+            let name = config
+                .hostname
+                .clone()
+                .unwrap_or_else(|| format!("client {handle}"));
+            (name, config.pos.to_string(), active)
+        };
+        match event {
+            FrontendEvent::Created {
+                handle,
+                config,
+                state,
+            }
+            | FrontendEvent::State {
+                handle,
+                config,
+                state,
+            } => {
+                let c = client(handle, config, state.active);
+                self.clients.insert(*handle, c);
+            }
+            FrontendEvent::Deleted(handle) => {
+                self.clients.remove(handle);
+            }
+            FrontendEvent::Enumerate(clients) => {
+                self.clients = clients
+                    .iter()
+                    .map(|(h, c, s)| (*h, client(h, c, s.active)))
+                    .collect();
+            }
+            FrontendEvent::CaptureStatus(s) => self.capture = (*s).into(),
+            FrontendEvent::EmulationStatus(s) => self.emulation = (*s).into(),
+            FrontendEvent::Controlling(handle) => self.controlling = *handle,
+            FrontendEvent::ConnectionAttempt { fingerprint } => {
+                if self.pending.contains(fingerprint) {
+                    return false;
+                }
+                self.pending.push(fingerprint.clone());
+            }
+            FrontendEvent::AuthorizedUpdated(keys) => {
+                self.pending.retain(|f| !keys.contains_key(f));
+            }
+            FrontendEvent::BlockedUpdated { fingerprints, .. } => {
+                self.pending.retain(|f| !fingerprints.contains(f));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn controlling(&self) -> Option<&str> {
+        self.controlling
+            .and_then(|h| self.clients.get(&h))
+            .map(|(name, ..)| name.as_str())
+    }
+
+    fn status(&self) -> &'static str {
+        if self.pending.is_empty() {
+            "Active"
+        } else {
+            "NeedsAttention"
+        }
+    }
+
+    /// the entries of the menu, an entry's id is its index + 1
+    fn items(&self) -> Vec<Item> {
+        let mut items = vec![];
+        let controlling = match self.controlling() {
+            Some(name) => format!("Controlling {name}"),
+            None => "Not controlling a device".to_owned(),
+        };
+        items.push(Item::Label(controlling));
+        if !self.clients.is_empty() {
+            items.push(Item::Separator);
+        }
+        for (&handle, (name, pos, active)) in &self.clients {
+            let label = format!("{name} ({pos})");
+            items.push(Item::Toggle(
+                label,
+                *active,
+                TrayAction::Activate(handle, !active),
+            ));
+        }
+        if !self.capture || !self.emulation || !self.pending.is_empty() {
+            items.push(Item::Separator);
+        }
+        if !self.capture {
+            items.push(Item::Button(
+                "Reenable Input Capture".into(),
+                TrayAction::EnableCapture,
+            ));
+        }
+        if !self.emulation {
+            items.push(Item::Button(
+                "Reenable Input Emulation".into(),
+                TrayAction::EnableEmulation,
+            ));
+        }
+        for fingerprint in &self.pending {
+            let short = fingerprint.get(..23).unwrap_or(fingerprint);
+            items.push(Item::Button(
+                format!("Authorize {short}…"),
+                TrayAction::Authorize(fingerprint.clone()),
+            ));
+        }
+        items.push(Item::Separator);
+        let window = if self.window_visible {
+            "Hide Window"
+        } else {
+            "Show Window"
+        };
+        items.push(Item::Button(window.into(), TrayAction::ToggleWindow));
+        items.push(Item::Button("Quit".into(), TrayAction::Quit));
+        items
+    }
+}
+
+/// an entry of the tray menu
+enum Item {
+    Label(String),
+    Separator,
+    Toggle(String, bool, TrayAction),
+    Button(String, TrayAction),
+}
+
+impl Item {
+    fn action(&self) -> Option<&TrayAction> {
+        match self {
+            Item::Toggle(_, _, a) | Item::Button(_, a) => Some(a),
+            Item::Label(_) | Item::Separator => None,
+        }
+    }
+
+    /// com.canonical.dbusmenu properties of the entry
+    fn properties(&self) -> HashMap<String, OwnedValue> {
+        /* underscores are mnemonics */
+        let label = |l: &str| OwnedValue::from(Str::from(l.replace('_', "__")));
+        let mut properties = HashMap::new();
+        match self {
+            Item::Label(l) => {
+                properties.insert("label".into(), label(l));
+                properties.insert("enabled".into(), OwnedValue::from(false));
+            }
+            Item::Separator => {
+                let separator = Str::from_static("separator");
+                properties.insert("type".into(), OwnedValue::from(separator));
+            }
+            Item::Toggle(l, active, _) => {
+                let checkmark = Str::from_static("checkmark");
+                properties.insert("label".into(), label(l));
+                properties.insert("toggle-type".into(), OwnedValue::from(checkmark));
+                properties.insert("toggle-state".into(), OwnedValue::from(*active as i32));
+            }
+            Item::Button(l, _) => {
+                properties.insert("label".into(), label(l));
+            }
+        }
+        properties
+    }
+}
+
+/// a menu entry as `(id, properties, children)`
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+/// state shared with the D-Bus interfaces
+#[derive(Clone)]
+struct Shared {
+    state: Arc<Mutex<TrayState>>,
+    actions: async_channel::Sender<TrayAction>,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, TrayState> {
+        self.state.lock().expect("lock")
+    }
+
+    async fn trigger(&self, action: TrayAction) {
+        /* the receiver is only closed when the application exits */
+        let _ = self.actions.send(action).await;
+    }
+}
+
+/// `org.kde.StatusNotifierItem` interface
+struct StatusNotifierItem(Shared);
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl StatusNotifierItem {
+    async fn activate(&self, _x: i32, _y: i32) {
+        self.0.trigger(TrayAction::ToggleWindow).await;
+    }
+
+    async fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    async fn context_menu(&self, _x: i32, _y: i32) {}
+
+    async fn scroll(&self, _delta: i32, _orientation: &str) {}
+
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> &str {
+        "lan-mouse"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> &str {
+        "Lan Mouse"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        self.0.state().status()
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> &str {
+        "de.feschber.LanMouse"
+    }
+
+    #[zbus(property)]
+    fn attention_icon_name(&self) -> &str {
+        "dialog-warning"
+    }
+
+    /// (icon name, icon pixmaps, title, description)
+    #[zbus(property)]
+    #[allow(clippy::type_complexity)]
+    fn tool_tip(&self) -> (String, Vec<(i32, i32, Vec<u8>)>, String, String) {
+        let state = self.0.state();
+        let description = match state.controlling() {
+            Some(name) => format!("controlling {name}"),
+            None => "not controlling a device".to_owned(),
+        };
+        (String::new(), vec![], "Lan Mouse".into(), description)
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(MENU_PATH).expect("valid path")
+    }
+
+    #[zbus(signal)]
+    async fn new_status(emitter: &SignalEmitter<'_>, status: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+/// `com.canonical.dbusmenu` interface, a flat menu rebuilt from the [`TrayState`]
+struct DbusMenu(Shared);
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl DbusMenu {
+    async fn get_layout(
+        &self,
+        parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> zbus::fdo::Result<(u32, Layout)> {
+        let state = self.0.state();
+        let items = state.items();
+        if parent_id != 0 {
+            let item = entry(&items, parent_id)?;
+            return Ok((state.revision, (parent_id, item.properties(), vec![])));
+        }
+        let mut children = vec![];
+        for (i, item) in items.iter().enumerate() {
+            let child = Value::from((i as i32 + 1, item.properties(), Vec::<Value>::new()));
+            children.push(child.try_to_owned().map_err(zbus::Error::from)?);
+        }
+        let mut properties = HashMap::new();
+        let submenu = Str::from_static("submenu");
+        properties.insert("children-display".into(), OwnedValue::from(submenu));
+        Ok((state.revision, (0, properties, children)))
+    }
+
+    async fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        let items = self.0.state().items();
+        ids.into_iter()
+            .filter_map(|id| entry(&items, id).ok().map(|i| (id, i.properties())))
+            .collect()
+    }
+
+    async fn get_property(&self, id: i32, name: String) -> zbus::fdo::Result<OwnedValue> {
+        let items = self.0.state().items();
+        entry(&items, id)?
+            .properties()
+            .remove(&name)
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("no property {name}")))
+    }
+
+    async fn event(&self, id: i32, event_id: String, _data: OwnedValue, _timestamp: u32) {
+        if event_id != "clicked" {
+            return;
+        }
+        let action = {
+            let items = self.0.state().items();
+            entry(&items, id).ok().and_then(Item::action).cloned()
+        };
+        if let Some(action) = action {
+            self.0.trigger(action).await;
+        }
+    }
+
+    /// returns the ids that were not found
+    async fn event_group(&self, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        let count = self.0.state().items().len() as i32;
+        let mut not_found = vec![];
+        for (id, event_id, data, timestamp) in events {
+            if id < 1 || id > count {
+                not_found.push(id);
+            } else {
+                self.event(id, event_id, data, timestamp).await;
+            }
+        }
+        not_found
+    }
+
+    async fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    /// returns the ids needing an update and the ids that were not found
+    async fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (vec![], vec![])
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(
+        emitter: &SignalEmitter<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+}
+
+fn entry(items: &[Item], id: i32) -> zbus::fdo::Result<&Item> {
+    usize::try_from(id - 1)
+        .ok()
+        .and_then(|i| items.get(i))
+        .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("no such item: {id}")))
+}
+
+/// a StatusNotifierItem registered with the tray of the desktop
+pub(crate) struct Tray {
+    shared: Shared,
+    /// drives the D-Bus connection, zbus needs a tokio runtime when
+    /// built with its `tokio` feature (as it is for the service)
+    runtime: Runtime,
+    /// set once the item is registered
+    conn: OnceCell<Connection>,
+}
+
+impl Tray {
+    pub(crate) fn new(actions: async_channel::Sender<TrayAction>) -> Option<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tray")
+            .enable_all()
+            .build();
+        let runtime = match runtime {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::warn!("failed to start the tray: {e}");
+                return None;
+            }
+        };
+        let shared = Shared {
+            state: Default::default(),
+            actions,
+        };
+        Some(Self {
+            shared,
+            runtime,
+            conn: OnceCell::new(),
+        })
+    }
+
+    /// register the tray icon, returns `false` if the desktop has no tray
+    pub(crate) async fn register(&self) -> bool {
+        let registered = match self.runtime.spawn(register(self.shared.clone())).await {
+            Ok(r) => r,
+            Err(e) => Err(io::Error::from(e).into()),
+        };
+        match registered {
+            Ok(conn) => {
+                let _ = self.conn.set(conn);
+                self.changed();
+                true
+            }
+            Err(e) => {
+                log::info!("no system tray available: {e}");
+                false
+            }
+        }
+    }
+
+    pub(crate) fn handle_event(&self, event: &FrontendEvent) {
+        if self.shared.state().handle_event(event) {
+            self.changed();
+        }
+    }
+
+    pub(crate) fn set_window_visible(&self, visible: bool) {
+        self.shared.state().window_visible = visible;
+        self.changed();
+    }
+
+    /// notify the tray about a changed state
+    fn changed(&self) {
+        let (revision, status) = {
+            let mut state = self.shared.state();
+            state.revision = state.revision.wrapping_add(1);
+            (state.revision, state.status())
+        };
+        let Some(conn) = self.conn.get().cloned() else {
+            return;
+        };
+        self.runtime.spawn(async move {
+            if let Err(e) = emit_changed(&conn, revision, status).await {
+                log::warn!("failed to update the tray: {e}");
+            }
+        });
+    }
+}
+
+/// serve the item and register it with the tray,
+/// registering it again whenever the tray is restarted
+async fn register(shared: Shared) -> zbus::Result<Connection> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", process::id());
+    let conn = connection::Builder::session()?
+        .name(name.as_str())?
+        .serve_at(ITEM_PATH, StatusNotifierItem(shared.clone()))?
+        .serve_at(MENU_PATH, DbusMenu(shared))?
+        .build()
+        .await?;
+    let dbus = DBusProxy::new(&conn).await?;
+    let mut owner_changed = dbus
+        .receive_name_owner_changed_with_args(&[(0, WATCHER)])
+        .await?;
+    register_item(&conn, &name).await?;
+    let c = conn.clone();
+    tokio::spawn(async move {
+        while let Some(signal) = owner_changed.next().await {
+            let Ok(args) = signal.args() else {
+                continue;
+            };
+            if args.new_owner().is_none() {
+                continue;
+            }
+            log::info!("the system tray was restarted, registering again");
+            if let Err(e) = register_item(&c, &name).await {
+                log::warn!("failed to register with the system tray: {e}");
+            }
+        }
+    });
+    Ok(conn)
+}
+
+async fn register_item(conn: &Connection, name: &str) -> zbus::Result<()> {
+    conn.call_method(
+        Some(WATCHER),
+        "/StatusNotifierWatcher",
+        Some(WATCHER),
+        "RegisterStatusNotifierItem",
+        &name,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn emit_changed(conn: &Connection, revision: u32, status: &str) -> zbus::Result<()> {
+    const ITEM: &str = "org.kde.StatusNotifierItem";
+    conn.emit_signal(
+        None::<()>,
+        MENU_PATH,
+        "com.canonical.dbusmenu",
+        "LayoutUpdated",
+        &(revision, 0i32),
+    )
+    .await?;
+    conn.emit_signal(None::<()>, ITEM_PATH, ITEM, "NewStatus", &status)
+        .await?;
+    conn.emit_signal(None::<()>, ITEM_PATH, ITEM, "NewToolTip", &())
+        .await
+}
@@ -1,6 +1,10 @@
 mod imp;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    env, process,
+    sync::mpsc,
+};
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
@@ -11,13 +15,19 @@ use gtk::{
 };
 
 use lan_mouse_ipc::{
-    ClientConfig, ClientHandle, ClientState, DEFAULT_PORT, FrontendRequest, FrontendRequestWriter,
-    Position,
+    ClientConfig, ClientHandle, ClientState, ConnectionStats, DEFAULT_PORT, FrontendRequest,
+    FrontendRequestWriter, MacAddress, Position,
 };
 
 use crate::{
-    authorization_window::AuthorizationWindow, fingerprint_window::FingerprintWindow,
-    key_object::KeyObject, key_row::KeyRow,
+    authorization_window::AuthorizationWindow,
+    diagnostics_window::DiagnosticsWindow,
+    fingerprint_window::FingerprintWindow,
+    key_object::KeyObject,
+    key_row::KeyRow,
+    layout_canvas::LayoutCanvas,
+    passphrase_window::PassphraseWindow,
+    release_bind_window::{self, ReleaseBindWindow},
 };
 
 use super::{client_object::ClientObject, client_row::ClientRow};
@@ -30,14 +40,82 @@ glib::wrapper! {
 }
 
 impl Window {
-    pub(super) fn new(app: &adw::Application, conn: FrontendRequestWriter) -> Self {
-        let window: Self = Object::builder().property("application", app).build();
-        window
-            .imp()
-            .frontend_request_writer
-            .borrow_mut()
-            .replace(conn);
-        window
+    pub(super) fn new(app: &adw::Application) -> Self {
+        Object::builder().property("application", app).build()
+    }
+
+    /// set the connection to the service, `None` while it is not running
+    pub(super) fn set_connection(&self, conn: Option<FrontendRequestWriter>) {
+        let connected = conn.is_some();
+        self.imp().frontend_request_writer.replace(conn);
+        self.imp().service_status_group.set_visible(!connected);
+        self.imp().start_service_button.set_sensitive(!connected);
+        if connected {
+            /* the service may have been restarted with a different state */
+            self.request(FrontendRequest::Sync);
+        }
+    }
+
+    /// wake up the connection to the service with `reconnect` when it should be retried
+    pub(super) fn set_reconnect(&self, reconnect: mpsc::Sender<()>) {
+        self.imp().reconnect.replace(Some(reconnect));
+    }
+
+    /// start the service in the background, stopped by [`Self::stop_service`]
+    fn start_service(&self) {
+        let child = env::current_exe().and_then(|exe| {
+            process::Command::new(exe)
+                .args(env::args().skip(1))
+                .arg("daemon")
+                .spawn()
+        });
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => return self.show_toast(&format!("failed to start the service: {e}")),
+        };
+        self.imp().start_service_button.set_sensitive(false);
+        self.imp().service.replace(Some(child));
+        if let Some(reconnect) = self.imp().reconnect.borrow().as_ref() {
+            let _ = reconnect.send(());
+        }
+        /* reap the service once it exits */
+        glib::timeout_add_seconds_local(
+            1,
+            clone!(
+                #[weak(rename_to = window)]
+                self,
+                #[upgrade_or]
+                glib::ControlFlow::Break,
+                move || {
+                    let mut service = window.imp().service.borrow_mut();
+                    match service.as_mut().map(|c| c.try_wait()) {
+                        Some(Ok(None)) => glib::ControlFlow::Continue,
+                        _ => {
+                            service.take();
+                            glib::ControlFlow::Break
+                        }
+                    }
+                }
+            ),
+        );
+    }
+
+    /// stop the service started by [`Self::start_service`], if it is still running
+    pub(super) fn stop_service(&self) {
+        let Some(mut service) = self.imp().service.take() else {
+            return;
+        };
+        #[cfg(unix)]
+        {
+            // on unix we give the service a chance to terminate gracefully
+            let pid = service.id() as libc::pid_t;
+            unsafe {
+                libc::kill(pid, libc::SIGINT);
+            }
+            let _ = service.wait();
+        }
+        let _ = service.kill();
+        let _ = service.wait();
     }
 
     fn clients(&self) -> gio::ListStore {
@@ -133,10 +211,10 @@ impl Window {
                                     let hostname = Some(hostname).filter(|s| !s.is_empty());
                                     /* changed in response to FrontendEvent
                                      * -> do not request additional update */
-                                    window.request(FrontendRequest::UpdateHostname(
-                                        client.handle(),
+                                    window.request(FrontendRequest::UpdateHostname {
+                                        handle: client.handle(),
                                         hostname,
-                                    ));
+                                    });
                                 }
                             }
                         ),
@@ -149,14 +227,39 @@ impl Window {
                             window,
                             move |row: ClientRow, port: u32| {
                                 if let Some(client) = window.client_by_idx(row.index() as u32) {
-                                    window.request(FrontendRequest::UpdatePort(
-                                        client.handle(),
-                                        port as u16,
-                                    ));
+                                    window.request(FrontendRequest::UpdatePort {
+                                        handle: client.handle(),
+                                        port: port as u16,
+                                    });
                                 }
                             }
                         ),
                     );
+                    row.connect_closure(
+                        "request-mac-change",
+                        false,
+                        closure_local!(
+                            #[strong]
+                            window,
+                            move |row: ClientRow, mac: String| {
+                                let Some(client) = window.client_by_idx(row.index() as u32) else {
+                                    return;
+                                };
+                                /* only request valid (or cleared) addresses */
+                                let mac = match mac.trim() {
+                                    "" => None,
+                                    mac => match mac.parse::<MacAddress>() {
+                                        Ok(mac) => Some(mac),
+                                        Err(_) => return,
+                                    },
+                                };
+                                window.request(FrontendRequest::UpdateMacAddress {
+                                    handle: client.handle(),
+                                    mac,
+                                });
+                            }
+                        ),
+                    );
                     row.connect_closure(
                         "request-activate",
                         false,
@@ -169,10 +272,10 @@ impl Window {
                                         "request: {} client",
                                         if active { "activating" } else { "deactivating" }
                                     );
-                                    window.request(FrontendRequest::Activate(
-                                        client.handle(),
+                                    window.request(FrontendRequest::Activate {
+                                        handle: client.handle(),
                                         active,
-                                    ));
+                                    });
                                 }
                             }
                         ),
@@ -219,10 +322,10 @@ impl Window {
                                         2 => Position::Top,
                                         _ => Position::Bottom,
                                     };
-                                    window.request(FrontendRequest::UpdatePosition(
-                                        client.handle(),
+                                    window.request(FrontendRequest::UpdatePosition {
+                                        handle: client.handle(),
                                         position,
-                                    ));
+                                    });
                                 }
                             }
                         ),
@@ -233,6 +336,22 @@ impl Window {
         );
     }
 
+    fn setup_layout(&self) {
+        self.imp().layout_canvas.connect_closure(
+            "request-position-change",
+            false,
+            closure_local!(
+                #[weak(rename_to = window)]
+                self,
+                move |_: LayoutCanvas, handle: u64, position: String| {
+                    if let Ok(position) = position.parse::<Position>() {
+                        window.request(FrontendRequest::UpdatePosition { handle, position });
+                    }
+                }
+            ),
+        );
+    }
+
     fn setup_icon(&self) {
         self.set_icon_name(Some("de.feschber.LanMouse"));
     }
@@ -276,6 +395,9 @@ impl Window {
         client: ClientConfig,
         state: ClientState,
     ) {
+        let canvas = &self.imp().layout_canvas;
+        canvas.set_client(handle, client.hostname.clone(), client.pos);
+        canvas.set_client_active(handle, state.active);
         let client = ClientObject::new(handle, client, state.clone());
         self.clients().append(&client);
         self.update_placeholder_visibility();
@@ -286,6 +408,17 @@ impl Window {
         &self,
         clients: Vec<(ClientHandle, ClientConfig, ClientState)>,
     ) {
+        /* clients deleted while disconnected from the service */
+        let handles = clients.iter().map(|(h, _, _)| *h).collect::<HashSet<_>>();
+        let removed = self
+            .clients()
+            .iter::<ClientObject>()
+            .filter_map(|c| c.ok().map(|c| c.handle()))
+            .filter(|h| !handles.contains(h))
+            .collect::<Vec<_>>();
+        for handle in removed {
+            self.delete_client(handle);
+        }
         for (handle, client, state) in clients {
             if self.client_idx(handle).is_some() {
                 self.update_client_config(handle, client);
@@ -316,22 +449,30 @@ impl Window {
         };
 
         self.clients().remove(idx as u32);
+        self.imp().layout_canvas.remove_client(handle);
         if self.clients().n_items() == 0 {
             self.update_placeholder_visibility();
         }
     }
 
     pub(super) fn update_client_config(&self, handle: ClientHandle, client: ClientConfig) {
+        self.imp()
+            .layout_canvas
+            .set_client(handle, client.hostname.clone(), client.pos);
         let Some(row) = self.row_for_handle(handle) else {
             log::warn!("could not find row for handle {handle}");
             return;
         };
         row.set_hostname(client.hostname);
         row.set_port(client.port);
+        row.set_mac(client.mac);
         row.set_position(client.pos);
     }
 
     pub(super) fn update_client_state(&self, handle: ClientHandle, state: ClientState) {
+        self.imp()
+            .layout_canvas
+            .set_client_active(handle, state.active);
         let Some(row) = self.row_for_handle(handle) else {
             log::warn!("could not find row for handle {handle}");
             return;
@@ -415,16 +556,35 @@ impl Window {
     }
 
     fn request_fingerprint_add(&self, desc: String, fp: String) {
-        self.request(FrontendRequest::AuthorizeKey(desc, fp));
+        self.request(FrontendRequest::AuthorizeKey {
+            description: desc,
+            fingerprint: fp,
+        });
     }
 
     fn request_fingerprint_remove(&self, fp: String) {
         self.request(FrontendRequest::RemoveAuthorizedKey(fp));
     }
 
-    fn request(&self, request: FrontendRequest) {
+    pub(super) fn update_profiles(&self, active: String, profiles: Vec<String>) {
+        let imp = self.imp();
+        imp.updating_profiles.set(true);
+        let names = profiles.iter().map(String::as_str).collect::<Vec<_>>();
+        imp.profiles.splice(0, imp.profiles.n_items(), &names);
+        if let Some(i) = profiles.iter().position(|p| *p == active) {
+            imp.profile_row.set_selected(i as u32);
+        }
+        imp.updating_profiles.set(false);
+        /* only the default profile */
+        imp.profile_row.set_visible(profiles.len() > 1);
+    }
+
+    pub(super) fn request(&self, request: FrontendRequest) {
         let mut requester = self.imp().frontend_request_writer.borrow_mut();
-        let requester = requester.as_mut().unwrap();
+        let Some(requester) = requester.as_mut() else {
+            self.show_toast("the service is not running");
+            return;
+        };
         if let Err(e) = requester.request(request) {
             log::error!("error sending message: {e}");
         };
@@ -472,6 +632,36 @@ impl Window {
         self.imp().fingerprint_row.set_subtitle(fingerprint);
     }
 
+    pub(super) fn set_release_bind(&self, keys: Vec<String>) {
+        let labels = keys
+            .iter()
+            .map(|k| release_bind_window::label(k))
+            .collect::<Vec<_>>();
+        self.imp()
+            .release_bind_row
+            .set_subtitle(&labels.join(" + "));
+        self.imp().release_bind.replace(keys);
+    }
+
+    fn open_release_bind_dialog(&self) {
+        let window = ReleaseBindWindow::new(&self.imp().release_bind.borrow());
+        window.set_transient_for(Some(self));
+        window.connect_closure(
+            "confirm-clicked",
+            false,
+            closure_local!(
+                #[strong(rename_to = parent)]
+                self,
+                move |w: ReleaseBindWindow, keys: String| {
+                    let keys = keys.split_whitespace().map(str::to_owned).collect();
+                    parent.request(FrontendRequest::SetReleaseBind(keys));
+                    w.close();
+                }
+            ),
+        );
+        window.present();
+    }
+
     pub(super) fn request_authorization(&self, fingerprint: &str) {
         if let Some(w) = self.imp().authorization_window.borrow_mut().take() {
             w.close();
@@ -490,6 +680,18 @@ impl Window {
                 }
             ),
         );
+        window.connect_closure(
+            "block-clicked",
+            false,
+            closure_local!(
+                #[strong(rename_to = parent)]
+                self,
+                move |w: AuthorizationWindow, fp: String| {
+                    w.close();
+                    parent.request(FrontendRequest::BlockKey(fp));
+                }
+            ),
+        );
         window.connect_closure(
             "cancel-clicked",
             false,
@@ -500,4 +702,98 @@ impl Window {
         window.present();
         self.imp().authorization_window.replace(Some(window));
     }
+
+    fn open_diagnostics(&self) {
+        if let Some(w) = self.imp().diagnostics_window.borrow().as_ref() {
+            w.present();
+            return;
+        }
+        let window = DiagnosticsWindow::new();
+        window.set_transient_for(Some(self));
+        window.connect_close_request(clone!(
+            #[weak(rename_to = parent)]
+            self,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_| {
+                parent.imp().diagnostics_window.take();
+                glib::Propagation::Proceed
+            }
+        ));
+        window.present();
+        self.imp().diagnostics_window.replace(Some(window));
+
+        /* poll statistics while the window is open */
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to = parent)]
+            self,
+            async move {
+                while parent.imp().diagnostics_window.borrow().is_some() {
+                    parent.request(FrontendRequest::GetStats);
+                    glib::timeout_future_seconds(1).await;
+                }
+            }
+        ));
+    }
+
+    pub(super) fn update_stats(
+        &self,
+        clients: Vec<(ClientHandle, ConnectionStats)>,
+        incoming: Vec<(String, ConnectionStats)>,
+    ) {
+        let Some(window) = self.imp().diagnostics_window.borrow().clone() else {
+            return;
+        };
+        let mut stats = vec![];
+        for (handle, s) in clients {
+            let title = self
+                .client_object_for_handle(handle)
+                .and_then(|c| c.hostname())
+                .unwrap_or_else(|| format!("client {handle}"));
+            stats.push((format!("client {handle}"), title, s));
+        }
+        for (fingerprint, s) in incoming {
+            let title = (0..self.authorized().n_items())
+                .filter_map(|i| self.authorized_by_idx(i))
+                .find(|k| k.get_fingerprint() == fingerprint)
+                .map(|k| format!("incoming: {}", k.get_description()))
+                .unwrap_or_else(|| format!("incoming: {fingerprint}"));
+            stats.push((fingerprint, title, s));
+        }
+        window.set_stats(stats);
+    }
+
+    pub(super) fn request_passphrase(&self) {
+        /* the service asks again after reconnecting or a wrong passphrase */
+        if let Some(w) = self.imp().passphrase_window.borrow().as_ref() {
+            w.present();
+            return;
+        }
+        let window = PassphraseWindow::new();
+        window.set_transient_for(Some(self));
+        window.connect_close_request(clone!(
+            #[weak(rename_to = parent)]
+            self,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_| {
+                parent.imp().passphrase_window.take();
+                glib::Propagation::Proceed
+            }
+        ));
+        window.connect_closure(
+            "confirm-clicked",
+            false,
+            closure_local!(
+                #[strong(rename_to = parent)]
+                self,
+                move |w: PassphraseWindow, passphrase: String| {
+                    parent.request(FrontendRequest::Unlock(passphrase));
+                    w.close();
+                }
+            ),
+        );
+        window.present();
+        self.imp().passphrase_window.replace(Some(window));
+    }
 }
@@ -1,14 +1,24 @@
-use std::cell::{Cell, RefCell};
+use std::{
+    cell::{Cell, RefCell},
+    process::Child,
+    sync::mpsc,
+};
 
 use adw::subclass::prelude::*;
-use adw::{ActionRow, PreferencesGroup, ToastOverlay, prelude::*};
+use adw::{ActionRow, ComboRow, PreferencesGroup, ToastOverlay, prelude::*};
 use glib::subclass::InitializingObject;
 use gtk::glib::clone;
-use gtk::{Button, CompositeTemplate, Entry, Image, Label, ListBox, gdk, gio, glib};
+use gtk::{
+    Button, CompositeTemplate, Entry, Image, Label, ListBox, StringList, StringObject, gdk, gio,
+    glib,
+};
 
-use lan_mouse_ipc::{DEFAULT_PORT, FrontendRequestWriter};
+use lan_mouse_ipc::{DEFAULT_PORT, FrontendRequest, FrontendRequestWriter};
 
 use crate::authorization_window::AuthorizationWindow;
+use crate::diagnostics_window::DiagnosticsWindow;
+use crate::layout_canvas::LayoutCanvas;
+use crate::passphrase_window::PassphraseWindow;
 
 #[derive(CompositeTemplate, Default)]
 #[template(resource = "/de/feschber/LanMouse/window.ui")]
@@ -18,10 +28,14 @@ pub struct Window {
     #[template_child]
     pub fingerprint_row: TemplateChild<ActionRow>,
     #[template_child]
+    pub release_bind_row: TemplateChild<ActionRow>,
+    #[template_child]
     pub port_edit_apply: TemplateChild<Button>,
     #[template_child]
     pub port_edit_cancel: TemplateChild<Button>,
     #[template_child]
+    pub layout_canvas: TemplateChild<LayoutCanvas>,
+    #[template_child]
     pub client_list: TemplateChild<ListBox>,
     #[template_child]
     pub client_placeholder: TemplateChild<ActionRow>,
@@ -34,6 +48,10 @@ pub struct Window {
     #[template_child]
     pub toast_overlay: TemplateChild<ToastOverlay>,
     #[template_child]
+    pub service_status_group: TemplateChild<PreferencesGroup>,
+    #[template_child]
+    pub start_service_button: TemplateChild<Button>,
+    #[template_child]
     pub capture_emulation_group: TemplateChild<PreferencesGroup>,
     #[template_child]
     pub capture_status_row: TemplateChild<ActionRow>,
@@ -45,13 +63,27 @@ pub struct Window {
     pub input_capture_button: TemplateChild<Button>,
     #[template_child]
     pub authorized_list: TemplateChild<ListBox>,
+    #[template_child]
+    pub profile_row: TemplateChild<ComboRow>,
+    #[template_child]
+    pub profiles: TemplateChild<StringList>,
+    /// set while the profile list is updated from the service
+    pub updating_profiles: Cell<bool>,
     pub clients: RefCell<Option<gio::ListStore>>,
     pub authorized: RefCell<Option<gio::ListStore>>,
     pub frontend_request_writer: RefCell<Option<FrontendRequestWriter>>,
     pub port: Cell<u16>,
     pub capture_active: Cell<bool>,
     pub emulation_active: Cell<bool>,
+    /// the release bind reported by the service
+    pub release_bind: RefCell<Vec<String>>,
     pub authorization_window: RefCell<Option<AuthorizationWindow>>,
+    pub diagnostics_window: RefCell<Option<DiagnosticsWindow>>,
+    pub passphrase_window: RefCell<Option<PassphraseWindow>>,
+    /// the service started from the window, if it is still running
+    pub service: RefCell<Option<Child>>,
+    /// retries connecting to the service when sent to
+    pub reconnect: RefCell<Option<mpsc::Sender<()>>>,
 }
 
 #[glib::object_subclass]
@@ -64,6 +96,7 @@ impl ObjectSubclass for Window {
     type ParentType = adw::ApplicationWindow;
 
     fn class_init(klass: &mut Self::Class) {
+        LayoutCanvas::ensure_type();
         klass.bind_template();
         klass.bind_template_callbacks();
     }
@@ -140,6 +173,27 @@ impl Window {
         self.port_edit_cancel.set_visible(false);
     }
 
+    #[template_callback]
+    fn handle_profile_selected(&self) {
+        if self.updating_profiles.get() {
+            return;
+        }
+        let Some(profile) = self
+            .profile_row
+            .selected_item()
+            .and_downcast::<StringObject>()
+        else {
+            return;
+        };
+        self.obj()
+            .request(FrontendRequest::SwitchProfile(profile.string().into()));
+    }
+
+    #[template_callback]
+    fn handle_start_service(&self) {
+        self.obj().start_service();
+    }
+
     #[template_callback]
     fn handle_emulation(&self) {
         self.obj().request_emulation();
@@ -150,11 +204,21 @@ impl Window {
         self.obj().request_capture();
     }
 
+    #[template_callback]
+    fn handle_diagnostics(&self, _button: &Button) {
+        self.obj().open_diagnostics();
+    }
+
     #[template_callback]
     fn handle_add_cert_fingerprint(&self, _button: &Button) {
         self.obj().open_fingerprint_dialog(None);
     }
 
+    #[template_callback]
+    fn handle_change_release_bind(&self, _button: &Button) {
+        self.obj().open_release_bind_dialog();
+    }
+
     pub fn set_port(&self, port: u16) {
         self.port.set(port);
         if port == DEFAULT_PORT {
@@ -178,6 +242,7 @@ impl ObjectImpl for Window {
         let obj = self.obj();
         obj.setup_icon();
         obj.setup_clients();
+        obj.setup_layout();
         obj.setup_authorized();
     }
 }
@@ -0,0 +1,413 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{Stream, StreamExt, stream};
+use thiserror::Error;
+
+use crate::{
+    AsyncFrontendEventReader, AsyncFrontendRequestWriter, ClientConfig, ClientHandle, ClientState,
+    ConnectionError, ConnectionStats, FrontendError, FrontendEvent, FrontendMessage,
+    FrontendRequest, FrontendResponse, IpcError, PROTOCOL_VERSION, connect_async,
+};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("could not connect: `{0}` - is the service running?")]
+    Connection(#[from] ConnectionError),
+    #[error("error communicating with service: {0}")]
+    Ipc(#[from] IpcError),
+    #[error(transparent)]
+    Request(#[from] FrontendError),
+    #[error("the service does not support protocol version {PROTOCOL_VERSION}: {0}")]
+    Handshake(String),
+    #[error("unexpected response: {0:?}")]
+    UnexpectedResponse(FrontendResponse),
+    #[error("connection to the service closed")]
+    Closed,
+}
+
+/// connection statistics of clients and incoming devices (by fingerprint)
+pub type Stats = (
+    Vec<(ClientHandle, ConnectionStats)>,
+    Vec<(String, ConnectionStats)>,
+);
+
+/// a connection to the lan-mouse service speaking [`PROTOCOL_VERSION`]
+pub struct LanMouseClient {
+    rx: AsyncFrontendEventReader,
+    tx: AsyncFrontendRequestWriter,
+    /// events received while waiting for a response
+    events: VecDeque<FrontendEvent>,
+}
+
+impl LanMouseClient {
+    /// connect to the service, waiting at most `timeout` for it to come up,
+    /// and make sure it speaks the same protocol version
+    pub async fn connect(timeout: Option<Duration>) -> Result<Self, ClientError> {
+        let (rx, tx) = connect_async(timeout).await?;
+        let mut client = Self {
+            rx,
+            tx,
+            events: Default::default(),
+        };
+        let hello = FrontendRequest::Hello {
+            version: PROTOCOL_VERSION,
+        };
+        let id = client.tx.request(hello).await?;
+        /* a service not knowing `Hello` reports an error instead of answering */
+        let response = loop {
+            match client.next_message().await? {
+                FrontendMessage::Response { id: r, result } if r == id => break result?,
+                FrontendMessage::Response { .. } => {}
+                FrontendMessage::Event(FrontendEvent::Error(e)) => {
+                    return Err(ClientError::Handshake(e));
+                }
+                FrontendMessage::Event(e) => client.events.push_back(e),
+            }
+        };
+        match response {
+            FrontendResponse::Hello { version } if version == PROTOCOL_VERSION => Ok(client),
+            FrontendResponse::Hello { version } => Err(FrontendError::UnsupportedVersion {
+                version: PROTOCOL_VERSION,
+                supported: version,
+            }
+            .into()),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    /// send `request` and wait for its response.
+    /// Events received in the meantime are kept for [`Self::next_event`].
+    pub async fn request(
+        &mut self,
+        request: FrontendRequest,
+    ) -> Result<FrontendResponse, ClientError> {
+        let id = self.tx.request(request).await?;
+        loop {
+            match self.next_message().await? {
+                FrontendMessage::Response { id: r, result } if r == id => return Ok(result?),
+                FrontendMessage::Response { .. } => {}
+                FrontendMessage::Event(e) => self.events.push_back(e),
+            }
+        }
+    }
+
+    /// the next event sent by the service
+    pub async fn next_event(&mut self) -> Result<FrontendEvent, ClientError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            if let FrontendMessage::Event(e) = self.next_message().await? {
+                return Ok(e);
+            }
+        }
+    }
+
+    /// all events sent by the service, starting with the
+    /// complete state reported when connecting
+    pub fn subscribe(&mut self) -> impl Stream<Item = Result<FrontendEvent, ClientError>> + '_ {
+        stream::unfold(self, |client| async move {
+            match client.next_event().await {
+                Err(ClientError::Closed) => None,
+                event => Some((event, client)),
+            }
+        })
+    }
+
+    /// request the complete state of the service.
+    /// Returns all events received so far, ending with the state.
+    pub async fn sync(&mut self) -> Result<Vec<FrontendEvent>, ClientError> {
+        self.expect_done(FrontendRequest::Sync).await?;
+        Ok(self.events.drain(..).collect())
+    }
+
+    /// create a client with the given configuration, returns its handle
+    pub async fn create_client(
+        &mut self,
+        config: ClientConfig,
+    ) -> Result<ClientHandle, ClientError> {
+        let handle = match self.request(FrontendRequest::Create).await? {
+            FrontendResponse::Created(handle) => handle,
+            r => return Err(ClientError::UnexpectedResponse(r)),
+        };
+        let update = self
+            .update_client(handle, &ClientConfig::default(), config)
+            .await;
+        /* do not leave a partially configured client behind */
+        if let Err(e) = update {
+            if let Err(e) = self.remove_client(handle).await {
+                log::warn!("could not remove client {handle}: {e}");
+            }
+            return Err(e);
+        }
+        Ok(handle)
+    }
+
+    /// change the configuration of a client from `old` to `new`
+    pub async fn update_client(
+        &mut self,
+        handle: ClientHandle,
+        old: &ClientConfig,
+        new: ClientConfig,
+    ) -> Result<(), ClientError> {
+        if old.hostname != new.hostname {
+            let hostname = new.hostname;
+            self.expect_done(FrontendRequest::UpdateHostname { handle, hostname })
+                .await?;
+        }
+        if old.port != new.port {
+            let port = new.port;
+            self.expect_done(FrontendRequest::UpdatePort { handle, port })
+                .await?;
+        }
+        if old.fix_ips != new.fix_ips {
+            let ips = new.fix_ips;
+            self.expect_done(FrontendRequest::UpdateFixIps { handle, ips })
+                .await?;
+        }
+        if old.pos != new.pos {
+            let position = new.pos;
+            self.expect_done(FrontendRequest::UpdatePosition { handle, position })
+                .await?;
+        }
+        if old.cmd != new.cmd {
+            let enter_hook = new.cmd;
+            self.expect_done(FrontendRequest::UpdateEnterHook { handle, enter_hook })
+                .await?;
+        }
+        if old.mac != new.mac {
+            let mac = new.mac;
+            self.expect_done(FrontendRequest::UpdateMacAddress { handle, mac })
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn remove_client(&mut self, handle: ClientHandle) -> Result<(), ClientError> {
+        self.expect_done(FrontendRequest::Delete(handle)).await
+    }
+
+    pub async fn set_active(
+        &mut self,
+        handle: ClientHandle,
+        active: bool,
+    ) -> Result<(), ClientError> {
+        self.expect_done(FrontendRequest::Activate { handle, active })
+            .await
+    }
+
+    /// all clients with their configuration and state
+    pub async fn clients(
+        &mut self,
+    ) -> Result<Vec<(ClientHandle, ClientConfig, ClientState)>, ClientError> {
+        match self.request(FrontendRequest::Enumerate).await? {
+            FrontendResponse::Clients(clients) => Ok(clients),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    pub async fn stats(&mut self) -> Result<Stats, ClientError> {
+        match self.request(FrontendRequest::GetStats).await? {
+            FrontendResponse::Stats { clients, incoming } => Ok((clients, incoming)),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    /// change the listen port, returns the new port once it is in use
+    pub async fn change_port(&mut self, port: u16) -> Result<u16, ClientError> {
+        match self.request(FrontendRequest::ChangePort(port)).await? {
+            FrontendResponse::Port(port) => Ok(port),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    /// switch to a layout profile, returns the active profile
+    pub async fn switch_profile(&mut self, profile: String) -> Result<String, ClientError> {
+        self.expect_profile(FrontendRequest::SwitchProfile(profile))
+            .await
+    }
+
+    /// report the current network, returns the profile selected for it
+    pub async fn set_network(&mut self, network: String) -> Result<String, ClientError> {
+        self.expect_profile(FrontendRequest::SetNetwork(network))
+            .await
+    }
+
+    /// change the key combination releasing the captured input
+    pub async fn set_release_bind(&mut self, keys: Vec<String>) -> Result<(), ClientError> {
+        self.expect_done(FrontendRequest::SetReleaseBind(keys))
+            .await
+    }
+
+    /// unlock the private key of a service waiting for its passphrase
+    pub async fn unlock(&mut self, passphrase: String) -> Result<(), ClientError> {
+        self.expect_done(FrontendRequest::Unlock(passphrase)).await
+    }
+
+    async fn expect_done(&mut self, request: FrontendRequest) -> Result<(), ClientError> {
+        match self.request(request).await? {
+            FrontendResponse::Done => Ok(()),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    async fn expect_profile(&mut self, request: FrontendRequest) -> Result<String, ClientError> {
+        match self.request(request).await? {
+            FrontendResponse::Profile(profile) => Ok(profile),
+            r => Err(ClientError::UnexpectedResponse(r)),
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<FrontendMessage, ClientError> {
+        Ok(self.rx.next().await.ok_or(ClientError::Closed)??)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use futures::StreamExt;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+        task::JoinHandle,
+    };
+    use tokio_stream::wrappers::LinesStream;
+
+    use super::{ClientError, LanMouseClient};
+    use crate::{
+        ClientConfig, FrontendError, FrontendEvent, FrontendMessage, FrontendRequest,
+        FrontendResponse, RequestEnvelope, RequestId, connect_async::split,
+    };
+
+    /// a client connected to a service answering requests with `respond`,
+    /// the service returns the requests it received once the client disconnects
+    fn connect(
+        mut respond: impl FnMut(RequestId, &FrontendRequest) -> Vec<FrontendMessage> + Send + 'static,
+    ) -> (LanMouseClient, JoinHandle<Vec<FrontendRequest>>) {
+        let (client, mut service) = UnixStream::pair().unwrap();
+        let (rx, tx) = split(client);
+        let client = LanMouseClient {
+            rx,
+            tx,
+            events: Default::default(),
+        };
+        let service = tokio::spawn(async move {
+            let (rx, mut tx) = service.split();
+            let mut lines = LinesStream::new(BufReader::new(rx).lines());
+            let mut requests = vec![];
+            while let Some(Ok(line)) = lines.next().await {
+                let envelope = RequestEnvelope::parse(&line).unwrap();
+                for message in respond(envelope.id.unwrap(), &envelope.request) {
+                    let json = serde_json::to_string(&message).unwrap() + "\n";
+                    tx.write_all(json.as_bytes()).await.unwrap();
+                }
+                requests.push(envelope.request);
+            }
+            requests
+        });
+        (client, service)
+    }
+
+    fn done(id: RequestId) -> FrontendMessage {
+        FrontendMessage::Response {
+            id,
+            result: Ok(FrontendResponse::Done),
+        }
+    }
+
+    fn event(port: u16) -> FrontendMessage {
+        FrontendMessage::Event(FrontendEvent::PortChanged { port, error: None })
+    }
+
+    #[tokio::test]
+    async fn responses_are_matched_by_id() {
+        let (mut client, service) = connect(|id, _| {
+            vec![
+                event(1),
+                /* a response to a request of an earlier connection */
+                FrontendMessage::Response {
+                    id: id + 100,
+                    result: Err(FrontendError::NoSuchClient(0)),
+                },
+                event(2),
+                FrontendMessage::Response {
+                    id,
+                    result: Ok(FrontendResponse::Port(id as u16)),
+                },
+            ]
+        });
+        assert_eq!(client.change_port(1).await.unwrap(), 0);
+        assert_eq!(client.change_port(1).await.unwrap(), 1);
+
+        /* the events received while waiting are kept in order */
+        for port in [1, 2, 1, 2] {
+            let event = client.next_event().await.unwrap();
+            assert!(
+                matches!(event, FrontendEvent::PortChanged { port: p, .. } if p == port),
+                "{event:?}"
+            );
+        }
+        drop(client);
+        assert_eq!(service.await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn sync_returns_buffered_events() {
+        let (mut client, _service) = connect(|id, request| match request {
+            FrontendRequest::Sync => vec![event(2), done(id)],
+            _ => vec![event(1), done(id)],
+        });
+        client.set_active(0, true).await.unwrap();
+        let events = client.sync().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            events[0],
+            FrontendEvent::PortChanged { port: 1, .. }
+        ));
+        assert!(matches!(
+            events[1],
+            FrontendEvent::PortChanged { port: 2, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_create_is_rolled_back() {
+        let (mut client, service) = connect(|id, request| {
+            let result = match request {
+                FrontendRequest::Create => Ok(FrontendResponse::Created(5)),
+                FrontendRequest::UpdatePort { .. } => Err(FrontendError::ReadOnly),
+                _ => Ok(FrontendResponse::Done),
+            };
+            vec![FrontendMessage::Response { id, result }]
+        });
+        let config = ClientConfig {
+            hostname: Some("a".to_owned()),
+            port: 1,
+            ..Default::default()
+        };
+        let e = client.create_client(config).await.unwrap_err();
+        assert!(matches!(e, ClientError::Request(FrontendError::ReadOnly)));
+        drop(client);
+        let requests = service.await.unwrap();
+        assert!(matches!(
+            requests.as_slice(),
+            [
+                FrontendRequest::Create,
+                FrontendRequest::UpdateHostname { handle: 5, .. },
+                FrontendRequest::UpdatePort { handle: 5, port: 1 },
+                FrontendRequest::Delete(5),
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn closed_connection() {
+        let (mut client, service) = connect(|_, _| vec![]);
+        service.abort();
+        let _ = service.await;
+        assert!(matches!(
+            client.next_event().await,
+            Err(ClientError::Closed)
+        ));
+    }
+}
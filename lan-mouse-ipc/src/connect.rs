@@ -1,9 +1,11 @@
-use crate::{ConnectionError, FrontendEvent, FrontendRequest, IpcError};
+use crate::{
+    ConnectionError, FrontendMessage, FrontendRequest, IpcError, RequestEnvelope, RequestId,
+};
 use std::{
     cmp::min,
     io::{self, BufReader, LineWriter, Lines, prelude::*},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 #[cfg(unix)]
@@ -24,47 +26,72 @@ pub struct FrontendRequestWriter {
     line_writer: LineWriter<UnixStream>,
     #[cfg(windows)]
     line_writer: LineWriter<TcpStream>,
+    next_id: RequestId,
 }
 
 impl FrontendEventReader {
-    pub fn next_event(&mut self) -> Option<Result<FrontendEvent, IpcError>> {
-        match self.lines.next()? {
-            Err(e) => Some(Err(e.into())),
-            Ok(l) => Some(serde_json::from_str(l.as_str()).map_err(|e| e.into())),
+    /// the next message of the service, skipping events unknown to this version
+    pub fn next_message(&mut self) -> Option<Result<FrontendMessage, IpcError>> {
+        loop {
+            match self.lines.next()? {
+                Err(e) => return Some(Err(e.into())),
+                Ok(l) => match FrontendMessage::parse(&l) {
+                    Ok(Some(message)) => return Some(Ok(message)),
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                },
+            }
         }
     }
 }
 
 impl FrontendRequestWriter {
-    pub fn request(&mut self, request: FrontendRequest) -> Result<(), io::Error> {
-        let mut json = serde_json::to_string(&request).unwrap();
+    /// send a request, returns the id of the response to expect
+    pub fn request(&mut self, request: FrontendRequest) -> Result<RequestId, io::Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let envelope = RequestEnvelope {
+            id: Some(id),
+            request,
+        };
+        let mut json = serde_json::to_string(&envelope).unwrap();
         log::debug!("requesting: {json}");
         json.push('\n');
         self.line_writer.write_all(json.as_bytes())?;
-        Ok(())
+        Ok(id)
     }
 }
 
-pub fn connect() -> Result<(FrontendEventReader, FrontendRequestWriter), ConnectionError> {
-    let rx = wait_for_service()?;
+/// connect to the service, waiting at most `timeout` for it to come up
+pub fn connect(
+    timeout: Option<Duration>,
+) -> Result<(FrontendEventReader, FrontendRequestWriter), ConnectionError> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let rx = wait_for_service(deadline)?;
     let tx = rx.try_clone()?;
     let buf_reader = BufReader::new(rx);
     let lines = buf_reader.lines();
     let line_writer = LineWriter::new(tx);
     let reader = FrontendEventReader { lines };
-    let writer = FrontendRequestWriter { line_writer };
+    let writer = FrontendRequestWriter {
+        line_writer,
+        next_id: 0,
+    };
     Ok((reader, writer))
 }
 
 /// wait for the lan-mouse socket to come online
 #[cfg(unix)]
-fn wait_for_service() -> Result<UnixStream, ConnectionError> {
+fn wait_for_service(deadline: Option<Instant>) -> Result<UnixStream, ConnectionError> {
     let socket_path = crate::default_socket_path()?;
     let mut duration = Duration::from_millis(10);
     loop {
         if let Ok(stream) = UnixStream::connect(&socket_path) {
             break Ok(stream);
         }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break Err(ConnectionError::Timeout);
+        }
         // a signaling mechanism or inotify could be used to
         // improve this
         thread::sleep(exponential_back_off(&mut duration));
@@ -72,12 +99,15 @@ fn wait_for_service() -> Result<UnixStream, ConnectionError> {
 }
 
 #[cfg(windows)]
-fn wait_for_service() -> Result<TcpStream, ConnectionError> {
+fn wait_for_service(deadline: Option<Instant>) -> Result<TcpStream, ConnectionError> {
     let mut duration = Duration::from_millis(10);
     loop {
         if let Ok(stream) = TcpStream::connect("127.0.0.1:5252") {
             break Ok(stream);
         }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            break Err(ConnectionError::Timeout);
+        }
         thread::sleep(exponential_back_off(&mut duration));
     }
 }
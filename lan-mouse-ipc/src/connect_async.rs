@@ -1,4 +1,6 @@
-use crate::{ConnectionError, FrontendEvent, FrontendRequest, IpcError};
+use crate::{
+    ConnectionError, FrontendMessage, FrontendRequest, IpcError, RequestEnvelope, RequestId,
+};
 use std::{
     cmp::min,
     task::{Poll, ready},
@@ -27,31 +29,46 @@ pub struct AsyncFrontendRequestWriter {
     tx: WriteHalf<UnixStream>,
     #[cfg(windows)]
     tx: WriteHalf<TcpStream>,
+    next_id: RequestId,
 }
 
 impl Stream for AsyncFrontendEventReader {
-    type Item = Result<FrontendEvent, IpcError>;
+    type Item = Result<FrontendMessage, IpcError>;
 
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        let line = ready!(self.lines_stream.poll_next_unpin(cx));
-        let event = line.map(|l| {
-            l.map_err(Into::<IpcError>::into)
-                .and_then(|l| serde_json::from_str(l.as_str()).map_err(|e| e.into()))
-        });
-        Poll::Ready(event)
+        /* events unknown to this version are skipped */
+        loop {
+            let message = match ready!(self.lines_stream.poll_next_unpin(cx)) {
+                None => None,
+                Some(Err(e)) => Some(Err(e.into())),
+                Some(Ok(l)) => match FrontendMessage::parse(&l) {
+                    Ok(Some(message)) => Some(Ok(message)),
+                    Ok(None) => continue,
+                    Err(e) => Some(Err(e)),
+                },
+            };
+            return Poll::Ready(message);
+        }
     }
 }
 
 impl AsyncFrontendRequestWriter {
-    pub async fn request(&mut self, request: FrontendRequest) -> Result<(), IpcError> {
-        let mut json = serde_json::to_string(&request).unwrap();
+    /// send a request, returns the id of the response to expect
+    pub async fn request(&mut self, request: FrontendRequest) -> Result<RequestId, IpcError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let envelope = RequestEnvelope {
+            id: Some(id),
+            request,
+        };
+        let mut json = serde_json::to_string(&envelope).unwrap();
         log::debug!("requesting: {json}");
         json.push('\n');
         self.tx.write_all(json.as_bytes()).await?;
-        Ok(())
+        Ok(id)
     }
 }
 
@@ -66,16 +83,23 @@ pub async fn connect_async(
     } else {
         wait_for_service().await?
     };
-    #[cfg(unix)]
-    let (rx, tx): (ReadHalf<UnixStream>, WriteHalf<UnixStream>) = tokio::io::split(stream);
-    #[cfg(windows)]
-    let (rx, tx): (ReadHalf<TcpStream>, WriteHalf<TcpStream>) = tokio::io::split(stream);
+    Ok(split(stream))
+}
+
+#[cfg(unix)]
+type Connection = UnixStream;
+#[cfg(windows)]
+type Connection = TcpStream;
+
+/// the reader and writer of a connection to the service
+pub(crate) fn split(stream: Connection) -> (AsyncFrontendEventReader, AsyncFrontendRequestWriter) {
+    let (rx, tx): (ReadHalf<Connection>, WriteHalf<Connection>) = tokio::io::split(stream);
     let buf_reader = BufReader::new(rx);
     let lines = buf_reader.lines();
     let lines_stream = LinesStream::new(lines);
     let reader = AsyncFrontendEventReader { lines_stream };
-    let writer = AsyncFrontendRequestWriter { tx };
-    Ok((reader, writer))
+    let writer = AsyncFrontendRequestWriter { tx, next_id: 0 };
+    (reader, writer)
 }
 
 /// wait for the lan-mouse socket to come online
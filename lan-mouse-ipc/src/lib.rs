@@ -1,10 +1,42 @@
+//! Communication between the lan-mouse service and its frontends.
+//!
+//! The service listens on a unix socket at `$XDG_RUNTIME_DIR/lan-mouse-socket.sock`
+//! (`~/Library/Caches/lan-mouse-socket.sock` on macOS, `127.0.0.1:5252` on Windows).
+//! Every message is a single line of JSON.
+//! Enums are externally tagged: variants without data are plain strings (`"Sync"`),
+//! all others are objects with the name of the variant as their only key
+//! (`{"Delete": 3}`, `{"Activate": {"handle": 3, "active": true}}`).
+//!
+//! Frontends send a [`RequestEnvelope`] per request and should start by
+//! announcing the [`PROTOCOL_VERSION`] they speak:
+//!
+//! ```json
+//! {"id": 0, "request": {"Hello": {"version": 4}}}
+//! ```
+//!
+//! The service sends [`FrontendMessage`]s: a response for every request with an `id`
+//! and events, either broadcast to all frontends or in answer to [`FrontendRequest::Sync`].
+//! Right after connecting, the service sends its complete state as events.
+//! Frontends should ignore events they do not know, which newer services may send.
+//!
+//! ```json
+//! {"Response": {"id": 0, "result": {"Ok": {"Hello": {"version": 4}}}}}
+//! {"Response": {"id": 1, "result": {"Err": {"NoSuchClient": 3}}}}
+//! {"Event": {"PortChanged": {"port": 4242, "error": null}}}
+//! ```
+//!
+//! A service speaking another version answers `Hello` with
+//! [`FrontendError::UnsupportedVersion`].
+//! [`LanMouseClient`] implements the protocol for Rust frontends.
+
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     env::VarError,
     fmt::Display,
     io,
     net::{IpAddr, SocketAddr},
     str::FromStr,
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -16,13 +48,15 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+mod client;
 mod connect;
 mod connect_async;
 mod listen;
 
+pub use client::{ClientError, LanMouseClient, Stats};
 pub use connect::{FrontendEventReader, FrontendRequestWriter, connect};
 pub use connect_async::{AsyncFrontendEventReader, AsyncFrontendRequestWriter, connect_async};
-pub use listen::AsyncFrontendListener;
+pub use listen::{AsyncFrontendListener, FrontendId};
 
 #[derive(Debug, Error)]
 pub enum ConnectionError {
@@ -42,6 +76,10 @@ pub enum IpcListenerCreationError {
     AlreadyRunning,
     #[error("failed to bind lan-mouse socket: `{0}`")]
     Bind(io::Error),
+    #[error("failed to set permissions of lan-mouse socket: `{0}`")]
+    Permissions(io::Error),
+    #[error("no such group: `{0}`")]
+    NoSuchGroup(String),
 }
 
 #[derive(Debug, Error)]
@@ -58,6 +96,10 @@ pub enum IpcError {
 
 pub const DEFAULT_PORT: u16 = 4242;
 
+/// version of the protocol spoken on the socket,
+/// incremented on every change to the messages, including new requests, events and errors
+pub const PROTOCOL_VERSION: u32 = 4;
+
 #[derive(Debug, Default, Eq, Hash, PartialEq, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Position {
@@ -128,6 +170,59 @@ impl TryFrom<&str> for Position {
     }
 }
 
+/// hardware address of a network interface, used for Wake-on-LAN
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MacAddress(pub [u8; 6]);
+
+#[derive(Debug, Error)]
+#[error("not a valid mac address: {mac}")]
+pub struct MacAddressParseError {
+    mac: String,
+}
+
+impl FromStr for MacAddress {
+    type Err = MacAddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MacAddressParseError { mac: s.into() };
+        let mut mac = [0u8; 6];
+        let mut octets = s.split([':', '-']);
+        for b in mac.iter_mut() {
+            let octet = octets.next().ok_or_else(err)?;
+            if octet.len() != 2 {
+                return Err(err());
+            }
+            *b = u8::from_str_radix(octet, 16).map_err(|_| err())?;
+        }
+        match octets.next() {
+            Some(_) => Err(err()),
+            None => Ok(Self(mac)),
+        }
+    }
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl TryFrom<String> for MacAddress {
+    type Error = MacAddressParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MacAddress> for String {
+    fn from(mac: MacAddress) -> Self {
+        mac.to_string()
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     /// hostname of this client
@@ -140,6 +235,8 @@ pub struct ClientConfig {
     pub pos: Position,
     /// enter hook
     pub cmd: Option<String>,
+    /// mac address for Wake-on-LAN
+    pub mac: Option<MacAddress>,
 }
 
 impl Default for ClientConfig {
@@ -150,6 +247,7 @@ impl Default for ClientConfig {
             fix_ips: Default::default(),
             pos: Default::default(),
             cmd: None,
+            mac: None,
         }
     }
 }
@@ -178,19 +276,69 @@ pub struct ClientState {
     pub resolving: bool,
 }
 
+/// state of a connection to a client or an incoming device
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Disconnected => write!(f, "disconnected"),
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+        }
+    }
+}
+
+/// traffic and health statistics of a connection
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    /// current state of the connection
+    pub state: ConnectionState,
+    /// address of the remote end (if connected)
+    pub addr: Option<SocketAddr>,
+    /// number of sent events by event type
+    pub events_sent: BTreeMap<String, u64>,
+    /// number of received events by event type
+    pub events_received: BTreeMap<String, u64>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    /// number of failed send attempts
+    pub send_errors: u64,
+    /// number of times the connection was reestablished
+    pub reconnects: u64,
+    /// time since the last event was sent or received
+    pub since_last_event: Option<Duration>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FrontendEvent {
     /// a client was created
-    Created(ClientHandle, ClientConfig, ClientState),
-    /// no such client
-    NoSuchClient(ClientHandle),
+    Created {
+        handle: ClientHandle,
+        config: ClientConfig,
+        state: ClientState,
+    },
     /// state changed
-    State(ClientHandle, ClientConfig, ClientState),
+    State {
+        handle: ClientHandle,
+        config: ClientConfig,
+        state: ClientState,
+    },
     /// the client was deleted
     Deleted(ClientHandle),
     /// new port, reason of failure (if failed)
-    PortChanged(u16, Option<String>),
-    /// list of all clients, used for initial state synchronization
+    PortChanged { port: u16, error: Option<String> },
+    /// list of all clients as `[handle, config, state]`,
+    /// used for initial state synchronization
     Enumerate(Vec<(ClientHandle, ClientConfig, ClientState)>),
     /// an error occured
     Error(String),
@@ -198,6 +346,8 @@ pub enum FrontendEvent {
     CaptureStatus(Status),
     /// emulation status
     EmulationStatus(Status),
+    /// the client currently receiving the captured input, if any
+    Controlling(Option<ClientHandle>),
     /// authorized public key fingerprints have been updated
     AuthorizedUpdated(HashMap<String, String>),
     /// public key fingerprint of this device
@@ -217,12 +367,29 @@ pub enum FrontendEvent {
     IncomingDisconnected(SocketAddr),
     /// failed connection attempt (approval for fingerprint required)
     ConnectionAttempt { fingerprint: String },
+    /// the private key is encrypted and the service waits for its passphrase
+    PassphraseRequired,
+    /// blocked public key fingerprints and ip addresses have been updated
+    BlockedUpdated {
+        fingerprints: HashSet<String>,
+        ips: HashSet<IpAddr>,
+    },
+    /// the active layout profile and the names of all profiles
+    ProfileChanged {
+        active: String,
+        profiles: Vec<String>,
+    },
+    /// the key combination releasing the captured input
+    ReleaseBind(Vec<String>),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum FrontendRequest {
+    /// announce the protocol version spoken by the frontend,
+    /// answered by [`FrontendResponse::Hello`]
+    Hello { version: u32 },
     /// activate/deactivate client
-    Activate(ClientHandle, bool),
+    Activate { handle: ClientHandle, active: bool },
     /// add a new client
     Create,
     /// change the listen port (recreate udp listener)
@@ -230,29 +397,185 @@ pub enum FrontendRequest {
     /// remove a client
     Delete(ClientHandle),
     /// request an enumeration of all clients
-    Enumerate(),
+    Enumerate,
     /// resolve dns
     ResolveDns(ClientHandle),
     /// update hostname
-    UpdateHostname(ClientHandle, Option<String>),
+    UpdateHostname {
+        handle: ClientHandle,
+        hostname: Option<String>,
+    },
     /// update port
-    UpdatePort(ClientHandle, u16),
+    UpdatePort { handle: ClientHandle, port: u16 },
     /// update position
-    UpdatePosition(ClientHandle, Position),
+    UpdatePosition {
+        handle: ClientHandle,
+        position: Position,
+    },
     /// update fix-ips
-    UpdateFixIps(ClientHandle, Vec<IpAddr>),
+    UpdateFixIps {
+        handle: ClientHandle,
+        ips: Vec<IpAddr>,
+    },
     /// request reenabling input capture
     EnableCapture,
     /// request reenabling input emulation
     EnableEmulation,
     /// synchronize all state
     Sync,
-    /// authorize fingerprint (description, fingerprint)
-    AuthorizeKey(String, String),
+    /// authorize a public key fingerprint
+    AuthorizeKey {
+        description: String,
+        fingerprint: String,
+    },
     /// remove fingerprint (fingerprint)
     RemoveAuthorizedKey(String),
     /// change the hook command
-    UpdateEnterHook(u64, Option<String>),
+    UpdateEnterHook {
+        handle: ClientHandle,
+        enter_hook: Option<String>,
+    },
+    /// update the mac address used for Wake-on-LAN
+    UpdateMacAddress {
+        handle: ClientHandle,
+        mac: Option<MacAddress>,
+    },
+    /// unlock the private key (passphrase)
+    Unlock(String),
+    /// never accept connections using this key (fingerprint)
+    BlockKey(String),
+    /// remove fingerprint from the blocklist (fingerprint)
+    UnblockKey(String),
+    /// never accept connections from this ip address
+    BlockIp(IpAddr),
+    /// remove ip address from the blocklist
+    UnblockIp(IpAddr),
+    /// request connection statistics
+    GetStats,
+    /// switch to the layout profile with the given name
+    SwitchProfile(String),
+    /// the current network (gateway mac address or SSID) changed,
+    /// selects the profile configured for it
+    SetNetwork(String),
+    /// change the key combination releasing the captured input,
+    /// keys are named like in `release_bind` of the config file (e.g. `KeyLeftCtrl`)
+    SetReleaseBind(Vec<String>),
+}
+
+impl FrontendRequest {
+    /// whether the request leaves the service unchanged,
+    /// only these are permitted for read-only frontends
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Hello { .. } | Self::Enumerate | Self::GetStats | Self::Sync
+        )
+    }
+}
+
+/// identifies a request of a frontend, chosen by the frontend
+pub type RequestId = u64;
+
+/// a request sent to the service.
+/// Requests with an `id` are answered by a [`FrontendMessage::Response`]
+/// sent only to the requesting frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestEnvelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<RequestId>,
+    pub request: FrontendRequest,
+}
+
+impl RequestEnvelope {
+    /// parse a request, which may be sent without an envelope
+    pub fn parse(line: &str) -> Result<Self, IpcError> {
+        /* not via `serde_json::Value`, which fails on empty tuple variants */
+        match serde_json::from_str::<Self>(line) {
+            Ok(envelope) => Ok(envelope),
+            Err(e) => match serde_json::from_str::<FrontendRequest>(line) {
+                Ok(request) => Ok(Self { id: None, request }),
+                /* report the error of the form the frontend used */
+                Err(_) if line.contains("\"request\"") => Err(e.into()),
+                Err(e) => Err(e.into()),
+            },
+        }
+    }
+}
+
+/// a message sent by the service to a frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FrontendMessage {
+    /// the result of the request with the given id
+    Response {
+        id: RequestId,
+        result: Result<FrontendResponse, FrontendError>,
+    },
+    /// an event, either broadcast to all frontends or
+    /// sent to a single one in response to [`FrontendRequest::Sync`]
+    Event(FrontendEvent),
+}
+
+impl FrontendMessage {
+    /// parse a message of the service.
+    /// Returns `None` for events unknown to this version of the protocol,
+    /// which a newer service may send.
+    pub fn parse(line: &str) -> Result<Option<Self>, IpcError> {
+        match serde_json::from_str::<Self>(line) {
+            Ok(message) => Ok(Some(message)),
+            Err(e) => match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::Object(o)) if o.len() == 1 && o.contains_key("Event") => {
+                    log::debug!("ignoring unknown event: {line}");
+                    Ok(None)
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+/// the result of a successful request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FrontendResponse {
+    /// the request was carried out
+    Done,
+    /// the protocol version spoken by the service
+    Hello { version: u32 },
+    /// handle of the newly created client
+    Created(ClientHandle),
+    /// all clients, in response to [`FrontendRequest::Enumerate`]
+    Clients(Vec<(ClientHandle, ClientConfig, ClientState)>),
+    /// the port the service listens on after [`FrontendRequest::ChangePort`]
+    Port(u16),
+    /// connection statistics of clients and incoming devices (by fingerprint)
+    Stats {
+        clients: Vec<(ClientHandle, ConnectionStats)>,
+        incoming: Vec<(String, ConnectionStats)>,
+    },
+    /// the active layout profile after switching profiles
+    Profile(String),
+}
+
+/// reason a request failed
+#[derive(Debug, Clone, Error, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FrontendError {
+    #[error("no such client: {0}")]
+    NoSuchClient(ClientHandle),
+    #[error("no such profile: `{0}`")]
+    NoSuchProfile(String),
+    #[error("no such authorized key: {0}")]
+    NoSuchKey(String),
+    #[error("failed to change port: {0}")]
+    PortChange(String),
+    #[error("invalid release bind: {0}")]
+    InvalidReleaseBind(String),
+    #[error("the private key is locked")]
+    Locked,
+    #[error("permission denied: read-only access")]
+    ReadOnly,
+    #[error("invalid passphrase")]
+    InvalidPassphrase,
+    #[error("unsupported protocol version {version}, the service speaks version {supported}")]
+    UnsupportedVersion { version: u32, supported: u32 },
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
@@ -297,3 +620,122 @@ pub fn default_socket_path() -> Result<PathBuf, SocketPathError> {
         .join("Caches")
         .join(LAN_MOUSE_SOCKET_NAME))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FrontendError, FrontendEvent, FrontendMessage, FrontendRequest, FrontendResponse, IpcError,
+        RequestEnvelope,
+    };
+
+    #[test]
+    fn request_with_id() {
+        let envelope = RequestEnvelope::parse(r#"{"id": 7, "request": {"Delete": 3}}"#).unwrap();
+        assert_eq!(envelope.id, Some(7));
+        assert!(matches!(envelope.request, FrontendRequest::Delete(3)));
+    }
+
+    #[test]
+    fn request_without_id() {
+        let envelope = RequestEnvelope::parse(r#"{"request": "Sync"}"#).unwrap();
+        assert_eq!(envelope.id, None);
+        assert!(matches!(envelope.request, FrontendRequest::Sync));
+        /* requests may be sent without an envelope */
+        let envelope =
+            RequestEnvelope::parse(r#"{"Activate": {"handle": 3, "active": true}}"#).unwrap();
+        assert_eq!(envelope.id, None);
+        assert!(matches!(
+            envelope.request,
+            FrontendRequest::Activate {
+                handle: 3,
+                active: true
+            }
+        ));
+        /* and are serialized without an id */
+        let envelope = RequestEnvelope {
+            id: None,
+            request: FrontendRequest::Enumerate,
+        };
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"request":"Enumerate"}"#
+        );
+    }
+
+    #[test]
+    fn invalid_request() {
+        for line in [
+            r#"{"id": 1, "request": {"Delete": "x"}}"#,
+            r#"{"Delete": "x"}"#,
+            r#"{"id": 1}"#,
+            "Sync",
+        ] {
+            assert!(
+                matches!(RequestEnvelope::parse(line), Err(IpcError::Json(_))),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn response_id() {
+        let message = FrontendMessage::parse(
+            r#"{"Response": {"id": 1, "result": {"Err": {"NoSuchClient": 3}}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            message,
+            Some(FrontendMessage::Response {
+                id: 1,
+                result: Err(FrontendError::NoSuchClient(3))
+            })
+        ));
+        let message = FrontendMessage::parse(
+            r#"{"Response": {"id": 0, "result": {"Ok": {"Hello": {"version": 4}}}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            message,
+            Some(FrontendMessage::Response {
+                id: 0,
+                result: Ok(FrontendResponse::Hello { version: 4 })
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_events_are_skipped() {
+        let message =
+            FrontendMessage::parse(r#"{"Event": {"PortChanged": {"port": 4242, "error": null}}}"#)
+                .unwrap();
+        assert!(matches!(
+            message,
+            Some(FrontendMessage::Event(FrontendEvent::PortChanged {
+                port: 4242,
+                ..
+            }))
+        ));
+        let message = FrontendMessage::parse(r#"{"Event": {"Teleported": {"to": 1}}}"#).unwrap();
+        assert!(message.is_none());
+        assert!(
+            FrontendMessage::parse(r#"{"Event": "Teleported"}"#)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn unknown_responses_are_errors() {
+        for line in [
+            r#"{"Response": {"id": 1, "result": {"Ok": "Teleported"}}}"#,
+            r#"{"Notification": {}}"#,
+            r#"{"Event": {}, "id": 1}"#,
+            "not json",
+        ] {
+            assert!(
+                matches!(FrontendMessage::parse(line), Err(IpcError::Json(_))),
+                "{line}"
+            );
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use futures::{Stream, StreamExt, stream::SelectAll};
-#[cfg(unix)]
-use std::path::PathBuf;
+use futures::{
+    Stream, StreamExt,
+    stream::{BoxStream, SelectAll},
+};
 use std::{
-    io::ErrorKind,
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, ErrorKind},
     pin::Pin,
     task::{Context, Poll},
 };
+#[cfg(unix)]
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf};
 use tokio_stream::wrappers::LinesStream;
 
 #[cfg(unix)]
@@ -20,7 +29,16 @@ use tokio::net::TcpListener;
 #[cfg(windows)]
 use tokio::net::TcpStream;
 
-use crate::{FrontendEvent, FrontendRequest, IpcError, IpcListenerCreationError};
+use crate::{
+    FrontendEvent, FrontendMessage, FrontendRequest, IpcError, IpcListenerCreationError,
+    RequestEnvelope,
+};
+
+/// identifies a frontend connected to the [`AsyncFrontendListener`]
+pub type FrontendId = u64;
+
+/// lines received from a frontend
+type Lines = BoxStream<'static, (FrontendId, io::Result<String>)>;
 
 pub struct AsyncFrontendListener {
     #[cfg(windows)]
@@ -29,18 +47,41 @@ pub struct AsyncFrontendListener {
     listener: UnixListener,
     #[cfg(unix)]
     socket_path: PathBuf,
+    line_streams: SelectAll<Lines>,
     #[cfg(unix)]
-    line_streams: SelectAll<LinesStream<BufReader<ReadHalf<UnixStream>>>>,
+    tx_streams: HashMap<FrontendId, WriteHalf<UnixStream>>,
     #[cfg(windows)]
-    line_streams: SelectAll<LinesStream<BufReader<ReadHalf<TcpStream>>>>,
+    tx_streams: HashMap<FrontendId, WriteHalf<TcpStream>>,
+    next_id: FrontendId,
+    /// frontends that connected and need to be synchronized
+    connected: VecDeque<FrontendId>,
+    /// frontends that may only receive events
+    read_only: HashSet<FrontendId>,
+    /// the user running the service, the only one with full access
     #[cfg(unix)]
-    tx_streams: Vec<WriteHalf<UnixStream>>,
-    #[cfg(windows)]
-    tx_streams: Vec<WriteHalf<TcpStream>>,
+    uid: u32,
+    /// members of this group may connect with read-only access
+    #[cfg(unix)]
+    read_only_group: Option<Group>,
 }
 
 impl AsyncFrontendListener {
-    pub async fn new() -> Result<Self, IpcListenerCreationError> {
+    /// listen for frontends of the current user and,
+    /// with read-only access, members of `read_only_group`
+    pub async fn new(read_only_group: Option<String>) -> Result<Self, IpcListenerCreationError> {
+        #[cfg(unix)]
+        let read_only_group = match read_only_group {
+            Some(name) => match Group::lookup(&name) {
+                Some(group) => Some(group),
+                None => return Err(IpcListenerCreationError::NoSuchGroup(name)),
+            },
+            None => None,
+        };
+        #[cfg(windows)]
+        if read_only_group.is_some() {
+            log::warn!("a read-only group is not supported on windows");
+        }
+
         #[cfg(unix)]
         let (socket_path, listener) = {
             let socket_path = crate::default_socket_path()?;
@@ -67,6 +108,18 @@ impl AsyncFrontendListener {
                 }
                 Err(e) => return Err(IpcListenerCreationError::Bind(e)),
             };
+            /* independent of the umask; connections are checked regardless,
+             * as the socket is accessible with the umask until now */
+            let mode = match &read_only_group {
+                Some(group) => {
+                    std::os::unix::fs::chown(&socket_path, None, Some(group.gid))
+                        .map_err(IpcListenerCreationError::Permissions)?;
+                    0o660 /* rw- rw- --- */
+                }
+                None => 0o600, /* rw- --- --- */
+            };
+            fs::set_permissions(&socket_path, Permissions::from_mode(mode))
+                .map_err(IpcListenerCreationError::Permissions)?;
             (socket_path, listener)
         };
 
@@ -85,32 +138,130 @@ impl AsyncFrontendListener {
             #[cfg(unix)]
             socket_path,
             line_streams: SelectAll::new(),
-            tx_streams: vec![],
+            tx_streams: Default::default(),
+            next_id: 0,
+            connected: Default::default(),
+            read_only: Default::default(),
+            #[cfg(unix)]
+            uid: unsafe { libc::geteuid() },
+            #[cfg(unix)]
+            read_only_group,
         };
 
         Ok(adapter)
     }
 
+    /// send an event to all frontends
     pub async fn broadcast(&mut self, notify: FrontendEvent) {
-        // encode event
-        let mut json = serde_json::to_string(&notify).unwrap();
-        json.push('\n');
-
-        let mut keep = vec![];
+        let json = encode(&FrontendMessage::Event(notify));
+        let mut closed = vec![];
         // TODO do simultaneously
-        for tx in self.tx_streams.iter_mut() {
-            // write len + payload
-            if tx.write(json.as_bytes()).await.is_err() {
-                keep.push(false);
-                continue;
+        for (id, tx) in self.tx_streams.iter_mut() {
+            if tx.write_all(json.as_bytes()).await.is_err() {
+                closed.push(*id);
             }
-            keep.push(true);
         }
+        for id in closed {
+            self.tx_streams.remove(&id);
+            self.read_only.remove(&id);
+        }
+    }
 
-        // could not find a better solution because async
-        let mut keep = keep.into_iter();
-        self.tx_streams.retain(|_| keep.next().unwrap());
+    /// whether `frontend` is limited to [read-only requests](FrontendRequest::is_read_only)
+    pub fn is_read_only(&self, frontend: FrontendId) -> bool {
+        self.read_only.contains(&frontend)
     }
+
+    /// send a message to a single frontend
+    pub async fn send(&mut self, frontend: FrontendId, message: FrontendMessage) {
+        let Some(tx) = self.tx_streams.get_mut(&frontend) else {
+            return;
+        };
+        if tx.write_all(encode(&message).as_bytes()).await.is_err() {
+            self.tx_streams.remove(&frontend);
+            self.read_only.remove(&frontend);
+        }
+    }
+
+    /// the access granted to the peer of `stream`, if any
+    #[cfg(unix)]
+    fn access(&self, stream: &UnixStream) -> Option<Access> {
+        let cred = match stream.peer_cred() {
+            Ok(cred) => cred,
+            Err(e) => {
+                log::warn!("rejected frontend: failed to get peer credentials: {e}");
+                return None;
+            }
+        };
+        if cred.uid() == self.uid {
+            return Some(Access::Full);
+        }
+        if let Some(group) = &self.read_only_group {
+            if group.contains(cred.uid(), cred.gid()) {
+                log::info!("frontend of uid {} connected (read-only)", cred.uid());
+                return Some(Access::ReadOnly);
+            }
+        }
+        let pid = cred.pid().map(|p| p.to_string());
+        log::warn!(
+            "rejected frontend of uid {} (pid {})",
+            cred.uid(),
+            pid.as_deref().unwrap_or("unknown")
+        );
+        None
+    }
+}
+
+enum Access {
+    Full,
+    ReadOnly,
+}
+
+/// a group and the users belonging to it
+#[cfg(unix)]
+struct Group {
+    gid: u32,
+    /// uids of the supplementary members
+    members: Vec<u32>,
+}
+
+#[cfg(unix)]
+impl Group {
+    fn lookup(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        /* SAFETY: the returned entries are copied before the next lookup */
+        let (gid, member_names) = unsafe {
+            let group = libc::getgrnam(name.as_ptr());
+            if group.is_null() {
+                return None;
+            }
+            let mut names = vec![];
+            let mut member = (*group).gr_mem;
+            while !member.is_null() && !(*member).is_null() {
+                names.push(CStr::from_ptr(*member).to_owned());
+                member = member.add(1);
+            }
+            ((*group).gr_gid, names)
+        };
+        let members = member_names
+            .iter()
+            .filter_map(|name| unsafe {
+                let user = libc::getpwnam(name.as_ptr());
+                (!user.is_null()).then(|| (*user).pw_uid)
+            })
+            .collect();
+        Some(Self { gid, members })
+    }
+
+    fn contains(&self, uid: u32, gid: u32) -> bool {
+        gid == self.gid || self.members.contains(&uid)
+    }
+}
+
+fn encode(message: &FrontendMessage) -> String {
+    let mut json = serde_json::to_string(message).unwrap();
+    json.push('\n');
+    json
 }
 
 #[cfg(unix)]
@@ -122,26 +273,44 @@ impl Drop for AsyncFrontendListener {
 }
 
 impl Stream for AsyncFrontendListener {
-    type Item = Result<FrontendRequest, IpcError>;
+    type Item = (FrontendId, Result<RequestEnvelope, IpcError>);
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if let Poll::Ready(Some(Ok(l))) = self.line_streams.poll_next_unpin(cx) {
-            let request = serde_json::from_str(l.as_str()).map_err(|e| e.into());
-            return Poll::Ready(Some(request));
-        }
-        let mut sync = false;
         while let Poll::Ready(Ok((stream, _))) = self.listener.poll_accept(cx) {
+            #[cfg(unix)]
+            let Some(access) = self.access(&stream) else {
+                continue;
+            };
+            #[cfg(windows)]
+            let access = Access::Full;
+            let id = self.next_id;
+            self.next_id += 1;
+            if let Access::ReadOnly = access {
+                self.read_only.insert(id);
+            }
             let (rx, tx) = tokio::io::split(stream);
             let buf_reader = BufReader::new(rx);
             let lines = buf_reader.lines();
-            let lines = LinesStream::new(lines);
+            let lines = LinesStream::new(lines).map(move |l| (id, l)).boxed();
             self.line_streams.push(lines);
-            self.tx_streams.push(tx);
-            sync = true;
+            self.tx_streams.insert(id, tx);
+            self.connected.push_back(id);
         }
-        if sync {
-            Poll::Ready(Some(Ok(FrontendRequest::Sync)))
-        } else {
-            Poll::Pending
+        /* a new connection is synchronized without being asked */
+        if let Some(id) = self.connected.pop_front() {
+            let sync = RequestEnvelope {
+                id: None,
+                request: FrontendRequest::Sync,
+            };
+            return Poll::Ready(Some((id, Ok(sync))));
+        }
+        match self.line_streams.poll_next_unpin(cx) {
+            Poll::Ready(Some((id, line))) => {
+                let request = line
+                    .map_err(IpcError::from)
+                    .and_then(|l| RequestEnvelope::parse(&l));
+                Poll::Ready(Some((id, request)))
+            }
+            _ => Poll::Pending,
         }
     }
 }
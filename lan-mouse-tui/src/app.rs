@@ -0,0 +1,534 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    time::Duration,
+};
+
+use lan_mouse_ipc::{
+    ClientConfig, ClientHandle, ClientState, ConnectionState, ConnectionStats, FrontendEvent,
+    FrontendMessage, FrontendRequest, FrontendResponse, Position,
+};
+
+use crate::terminal::{Key, Line, Style};
+
+/// what to do after a key was pressed
+pub(crate) enum Action {
+    Request(FrontendRequest),
+    Quit,
+}
+
+/// a client setting edited in the prompt
+#[derive(Clone, Copy)]
+enum Field {
+    Hostname,
+    Port,
+    Ips,
+    EnterHook,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::Hostname => "hostname",
+            Field::Port => "port",
+            Field::Ips => "ips",
+            Field::EnterHook => "enter hook",
+        }
+    }
+}
+
+/// input requested from the user
+enum Prompt {
+    Edit {
+        handle: ClientHandle,
+        field: Field,
+        input: String,
+    },
+    Delete(ClientHandle),
+    /// description of a fingerprint to authorize
+    Authorize {
+        fingerprint: String,
+        input: String,
+    },
+    Passphrase(String),
+}
+
+const HELP: &str = "↑/↓ select  space activate  n new  d delete  h host  p port  o position  \
+                    i ips  e enter hook  r resolve  c capture  m emulation  q quit";
+const HELP_PENDING: &str = "a approve  b block  x dismiss";
+
+#[derive(Default)]
+pub(crate) struct App {
+    clients: BTreeMap<ClientHandle, (ClientConfig, ClientState)>,
+    client_stats: HashMap<ClientHandle, ConnectionStats>,
+    incoming: Vec<(String, ConnectionStats)>,
+    capture: bool,
+    emulation: bool,
+    /// the client currently receiving input
+    controlling: Option<ClientHandle>,
+    port: Option<u16>,
+    fingerprint: String,
+    authorized: HashMap<String, String>,
+    /// fingerprints of devices waiting for authorization
+    pending: Vec<String>,
+    profile: String,
+    release_bind: Vec<String>,
+    /// index of the selected client
+    selected: usize,
+    /// select the next client created
+    select_created: bool,
+    prompt: Option<Prompt>,
+    /// last notification or error
+    message: Option<String>,
+}
+
+impl App {
+    pub(crate) fn handle_message(&mut self, message: FrontendMessage) {
+        match message {
+            FrontendMessage::Event(event) => self.handle_event(event),
+            FrontendMessage::Response { result: Err(e), .. } => self.message = Some(e.to_string()),
+            FrontendMessage::Response {
+                result: Ok(FrontendResponse::Stats { clients, incoming }),
+                ..
+            } => {
+                self.client_stats = clients.into_iter().collect();
+                self.incoming = incoming;
+            }
+            /* changes are reported as events */
+            FrontendMessage::Response { result: Ok(_), .. } => {}
+        }
+    }
+
+    fn handle_event(&mut self, event: FrontendEvent) {
+        match event {
+            FrontendEvent::Created {
+                handle,
+                config,
+                state,
+            } => {
+                self.clients.insert(handle, (config, state));
+                if self.select_created {
+                    self.select_created = false;
+                    self.selected = self.clients.keys().position(|h| *h == handle).unwrap_or(0);
+                }
+            }
+            FrontendEvent::State {
+                handle,
+                config,
+                state,
+            } => {
+                self.clients.insert(handle, (config, state));
+            }
+            FrontendEvent::Deleted(handle) => {
+                self.clients.remove(&handle);
+                self.selected = self.selected.min(self.clients.len().saturating_sub(1));
+            }
+            FrontendEvent::Enumerate(clients) => {
+                self.clients = clients
+                    .into_iter()
+                    .map(|(handle, config, state)| (handle, (config, state)))
+                    .collect();
+                self.selected = self.selected.min(self.clients.len().saturating_sub(1));
+            }
+            FrontendEvent::PortChanged { port, error } => {
+                self.port = Some(port);
+                if let Some(e) = error {
+                    self.message = Some(format!("failed to change port: {e}"));
+                }
+            }
+            FrontendEvent::Error(e) => self.message = Some(e),
+            FrontendEvent::CaptureStatus(s) => self.capture = s.into(),
+            FrontendEvent::EmulationStatus(s) => self.emulation = s.into(),
+            FrontendEvent::Controlling(handle) => self.controlling = handle,
+            FrontendEvent::AuthorizedUpdated(keys) => {
+                self.pending.retain(|f| !keys.contains_key(f));
+                self.authorized = keys;
+            }
+            FrontendEvent::PublicKeyFingerprint(fingerprint) => self.fingerprint = fingerprint,
+            FrontendEvent::DeviceConnected { addr, fingerprint } => {
+                self.message = Some(format!("{} connected ({addr})", self.device(&fingerprint)));
+            }
+            FrontendEvent::DeviceEntered {
+                fingerprint,
+                addr,
+                pos,
+            } => {
+                let device = self.device(&fingerprint);
+                self.message = Some(format!("{device} entered ({addr}, {pos})"));
+            }
+            FrontendEvent::IncomingDisconnected(addr) => {
+                self.message = Some(format!("{addr} disconnected"));
+            }
+            FrontendEvent::ConnectionAttempt { fingerprint } => {
+                if !self.pending.contains(&fingerprint) {
+                    self.pending.push(fingerprint);
+                }
+            }
+            FrontendEvent::PassphraseRequired => {
+                self.prompt = Some(Prompt::Passphrase(String::new()));
+            }
+            FrontendEvent::BlockedUpdated { fingerprints, .. } => {
+                self.pending.retain(|f| !fingerprints.contains(f));
+            }
+            FrontendEvent::ProfileChanged { active, .. } => self.profile = active,
+            FrontendEvent::ReleaseBind(keys) => self.release_bind = keys,
+        }
+    }
+
+    /// description of an authorized device or its fingerprint
+    fn device(&self, fingerprint: &str) -> String {
+        match self.authorized.get(fingerprint) {
+            Some(description) => description.clone(),
+            None => fingerprint.to_owned(),
+        }
+    }
+
+    fn selected(&self) -> Option<(ClientHandle, &ClientConfig, &ClientState)> {
+        self.clients
+            .iter()
+            .nth(self.selected)
+            .map(|(handle, (config, state))| (*handle, config, state))
+    }
+
+    pub(crate) fn handle_key(&mut self, key: Key) -> Option<Action> {
+        if key == Key::CtrlC {
+            return Some(Action::Quit);
+        }
+        match self.prompt.take() {
+            Some(prompt) => self.handle_prompt_key(prompt, key),
+            None => self.handle_normal_key(key),
+        }
+    }
+
+    fn handle_normal_key(&mut self, key: Key) -> Option<Action> {
+        self.message = None;
+        let request = match key {
+            Key::Char('q') => return Some(Action::Quit),
+            Key::Up | Key::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                return None;
+            }
+            Key::Down | Key::Char('j') => {
+                self.selected = (self.selected + 1).min(self.clients.len().saturating_sub(1));
+                return None;
+            }
+            Key::Char('n') => {
+                self.select_created = true;
+                FrontendRequest::Create
+            }
+            Key::Char('c') => FrontendRequest::EnableCapture,
+            Key::Char('m') => FrontendRequest::EnableEmulation,
+            Key::Char('a') => {
+                let fingerprint = self.pending.first()?.clone();
+                self.prompt = Some(Prompt::Authorize {
+                    fingerprint,
+                    input: String::new(),
+                });
+                return None;
+            }
+            Key::Char('b') => FrontendRequest::BlockKey(self.pending.first()?.clone()),
+            Key::Char('x') => {
+                if !self.pending.is_empty() {
+                    self.pending.remove(0);
+                }
+                return None;
+            }
+            key => return self.handle_client_key(key),
+        };
+        Some(Action::Request(request))
+    }
+
+    /// keys acting on the selected client
+    fn handle_client_key(&mut self, key: Key) -> Option<Action> {
+        let (handle, config, state) = self.selected()?;
+        let edit = |field: Field, input: String| Prompt::Edit {
+            handle,
+            field,
+            input,
+        };
+        let request = match key {
+            Key::Char(' ') | Key::Enter => FrontendRequest::Activate {
+                handle,
+                active: !state.active,
+            },
+            Key::Char('o') => FrontendRequest::UpdatePosition {
+                handle,
+                position: match config.pos {
+                    Position::Left => Position::Top,
+                    Position::Top => Position::Right,
+                    Position::Right => Position::Bottom,
+                    Position::Bottom => Position::Left,
+                },
+            },
+            Key::Char('r') => FrontendRequest::ResolveDns(handle),
+            Key::Char('d') => {
+                self.prompt = Some(Prompt::Delete(handle));
+                return None;
+            }
+            Key::Char('h') => {
+                let input = config.hostname.clone().unwrap_or_default();
+                self.prompt = Some(edit(Field::Hostname, input));
+                return None;
+            }
+            Key::Char('p') => {
+                self.prompt = Some(edit(Field::Port, config.port.to_string()));
+                return None;
+            }
+            Key::Char('i') => {
+                let ips = config.fix_ips.iter().map(|ip| ip.to_string());
+                self.prompt = Some(edit(Field::Ips, ips.collect::<Vec<_>>().join(" ")));
+                return None;
+            }
+            Key::Char('e') => {
+                let input = config.cmd.clone().unwrap_or_default();
+                self.prompt = Some(edit(Field::EnterHook, input));
+                return None;
+            }
+            _ => return None,
+        };
+        Some(Action::Request(request))
+    }
+
+    fn handle_prompt_key(&mut self, mut prompt: Prompt, key: Key) -> Option<Action> {
+        let input = match &mut prompt {
+            Prompt::Delete(handle) => {
+                let handle = *handle;
+                return match key {
+                    Key::Char('y') => Some(Action::Request(FrontendRequest::Delete(handle))),
+                    _ => None,
+                };
+            }
+            Prompt::Edit { input, .. } => input,
+            Prompt::Authorize { input, .. } => input,
+            Prompt::Passphrase(input) => input,
+        };
+        match key {
+            Key::Esc => return None,
+            Key::Enter => {}
+            Key::Backspace => {
+                input.pop();
+                self.prompt = Some(prompt);
+                return None;
+            }
+            Key::Char(c) => {
+                input.push(c);
+                self.prompt = Some(prompt);
+                return None;
+            }
+            _ => {
+                self.prompt = Some(prompt);
+                return None;
+            }
+        }
+        let request = match prompt {
+            Prompt::Delete(_) => unreachable!(),
+            Prompt::Edit {
+                handle,
+                field,
+                input,
+            } => match edit_request(handle, field, input.trim()) {
+                Ok(request) => request,
+                Err(e) => {
+                    self.message = Some(e);
+                    return None;
+                }
+            },
+            Prompt::Authorize { fingerprint, input } => FrontendRequest::AuthorizeKey {
+                description: input,
+                fingerprint,
+            },
+            Prompt::Passphrase(passphrase) => FrontendRequest::Unlock(passphrase),
+        };
+        Some(Action::Request(request))
+    }
+
+    pub(crate) fn render(&self, height: usize) -> Vec<Line> {
+        let enabled = |b| if b { "enabled" } else { "disabled" };
+        let port = self.port.map(|p| p.to_string()).unwrap_or_default();
+        let mut header = format!(
+            " lan-mouse   port {port}   capture {}   emulation {}   profile {}",
+            enabled(self.capture),
+            enabled(self.emulation),
+            self.profile
+        );
+        if let Some((config, _)) = self.controlling.and_then(|h| self.clients.get(&h)) {
+            let host = config.hostname.as_deref().unwrap_or("<none>");
+            header.push_str(&format!("   controlling {host}"));
+        }
+        let mut lines = vec![
+            Line::new(header, Style::Bold),
+            Line::new(
+                format!(
+                    " fingerprint {}   release bind {}",
+                    self.fingerprint,
+                    self.release_bind.join(" + ")
+                ),
+                Style::Dim,
+            ),
+            Line::default(),
+            Line::new(" Clients", Style::Bold),
+            Line::new(
+                format!(
+                    "   {:<4} {:<20} {:<6} {:<8} {:<8} {:<13} {:<12} ips",
+                    "id", "host", "port", "position", "active", "connection", "last event"
+                ),
+                Style::Dim,
+            ),
+        ];
+
+        let mut rest = self.render_incoming();
+        rest.extend(self.render_pending());
+        rest.push(Line::default());
+        rest.push(self.render_prompt());
+        let help = if self.pending.is_empty() {
+            HELP.to_owned()
+        } else {
+            format!("{HELP_PENDING}  {HELP}")
+        };
+        rest.push(Line::new(format!(" {help}"), Style::Dim));
+
+        /* scroll the client list to keep the selection visible */
+        let rows = height.saturating_sub(lines.len() + rest.len()).max(1);
+        let first = self.selected.saturating_sub(rows - 1);
+        let clients = self.clients.iter().enumerate().skip(first).take(rows);
+        for (i, (handle, (config, state))) in clients {
+            let stats = self.client_stats.get(handle);
+            let connection = stats.map(|s| s.state).unwrap_or_default();
+            let last_event = last_event(stats.and_then(|s| s.since_last_event));
+            let ips = state
+                .ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>();
+            let text = format!(
+                " {} {:<4} {:<20} {:<6} {:<8} {:<8} {:<13} {:<12} {}",
+                if i == self.selected { ">" } else { " " },
+                handle,
+                config.hostname.as_deref().unwrap_or("<none>"),
+                config.port,
+                config.pos.to_string(),
+                if state.active { "yes" } else { "no" },
+                connection.to_string(),
+                last_event,
+                ips.join(", "),
+            );
+            let style = if i == self.selected {
+                Style::Reverse
+            } else {
+                Style::Normal
+            };
+            lines.push(Line::new(text, style));
+        }
+        if self.clients.is_empty() {
+            lines.push(Line::new("   no clients, press n to add one", Style::Dim));
+        }
+        lines.extend(rest);
+        lines
+    }
+
+    fn render_incoming(&self) -> Vec<Line> {
+        let mut lines = vec![Line::default(), Line::new(" Incoming", Style::Bold)];
+        let connected = self
+            .incoming
+            .iter()
+            .filter(|(_, s)| s.state == ConnectionState::Connected);
+        for (fingerprint, stats) in connected {
+            let addr = stats.addr.map(|a| a.to_string()).unwrap_or_default();
+            lines.push(Line::new(
+                format!(
+                    "   {:<20} {:<22} {:<12} {fingerprint}",
+                    self.authorized.get(fingerprint).map_or("", |d| d.as_str()),
+                    addr,
+                    last_event(stats.since_last_event),
+                ),
+                Style::Normal,
+            ));
+        }
+        if lines.len() == 2 {
+            lines.push(Line::new("   no devices connected", Style::Dim));
+        }
+        lines
+    }
+
+    fn render_pending(&self) -> Vec<Line> {
+        if self.pending.is_empty() {
+            return vec![];
+        }
+        let mut lines = vec![
+            Line::default(),
+            Line::new(" Authorization requests", Style::Bold),
+        ];
+        for fingerprint in &self.pending {
+            lines.push(Line::new(format!("   {fingerprint}"), Style::Normal));
+        }
+        lines
+    }
+
+    fn render_prompt(&self) -> Line {
+        let text = match &self.prompt {
+            Some(Prompt::Edit {
+                handle,
+                field,
+                input,
+            }) => format!(" {} of client {handle}: {input}_", field.name()),
+            Some(Prompt::Delete(handle)) => format!(" delete client {handle}? (y/n)"),
+            Some(Prompt::Authorize { fingerprint, input }) => {
+                format!(" description of {fingerprint}: {input}_")
+            }
+            Some(Prompt::Passphrase(input)) => {
+                format!(
+                    " passphrase of the private key: {}_",
+                    "*".repeat(input.len())
+                )
+            }
+            None => match &self.message {
+                Some(message) => format!(" {message}"),
+                None => String::new(),
+            },
+        };
+        Line::new(text, Style::Bold)
+    }
+}
+
+fn edit_request(
+    handle: ClientHandle,
+    field: Field,
+    input: &str,
+) -> Result<FrontendRequest, String> {
+    let optional = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+    let request = match field {
+        Field::Hostname => FrontendRequest::UpdateHostname {
+            handle,
+            hostname: optional(input),
+        },
+        Field::Port => FrontendRequest::UpdatePort {
+            handle,
+            port: input
+                .parse()
+                .map_err(|_| format!("invalid port: {input}"))?,
+        },
+        Field::Ips => FrontendRequest::UpdateFixIps {
+            handle,
+            ips: input
+                .split([' ', ','])
+                .filter(|s| !s.is_empty())
+                .map(|ip| {
+                    ip.parse::<IpAddr>()
+                        .map_err(|_| format!("invalid ip: {ip}"))
+                })
+                .collect::<Result<_, _>>()?,
+        },
+        Field::EnterHook => FrontendRequest::UpdateEnterHook {
+            handle,
+            enter_hook: optional(input),
+        },
+    };
+    Ok(request)
+}
+
+fn last_event(since: Option<Duration>) -> String {
+    match since {
+        Some(d) => format!("{:.1}s ago", d.as_secs_f64()),
+        None => "never".to_owned(),
+    }
+}
@@ -0,0 +1,66 @@
+mod app;
+mod terminal;
+
+use std::{io, time::Duration};
+
+use futures::StreamExt;
+use thiserror::Error;
+use tokio::signal::unix::{SignalKind, signal};
+
+use lan_mouse_ipc::{ConnectionError, FrontendRequest, IpcError, PROTOCOL_VERSION, connect_async};
+
+use app::{Action, App};
+use terminal::Terminal;
+
+/// how often connection statistics are requested
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum TuiError {
+    #[error("could not connect: `{0}` - is the service running?")]
+    Connection(#[from] ConnectionError),
+    #[error("error communicating with service: {0}")]
+    Ipc(#[from] IpcError),
+    #[error("terminal: {0}")]
+    Terminal(#[from] io::Error),
+    #[error("connection to the service closed")]
+    Closed,
+}
+
+pub async fn run() -> Result<(), TuiError> {
+    let (mut rx, mut tx) = connect_async(Some(Duration::from_millis(500))).await?;
+    tx.request(FrontendRequest::Hello {
+        version: PROTOCOL_VERSION,
+    })
+    .await?;
+
+    let terminal = Terminal::enter()?;
+    let mut resize = signal(SignalKind::window_change())?;
+    let mut stats = tokio::time::interval(STATS_INTERVAL);
+    let mut app = App::default();
+    loop {
+        let (_, height) = terminal.size();
+        terminal.draw(&app.render(height))?;
+        tokio::select! {
+            message = rx.next() => match message {
+                Some(message) => app.handle_message(message?),
+                None => return Err(TuiError::Closed),
+            },
+            keys = terminal.keys() => {
+                for key in keys? {
+                    match app.handle_key(key) {
+                        Some(Action::Request(request)) => {
+                            tx.request(request).await?;
+                        }
+                        Some(Action::Quit) => return Ok(()),
+                        None => {}
+                    }
+                }
+            }
+            _ = stats.tick() => {
+                tx.request(FrontendRequest::GetStats).await?;
+            }
+            _ = resize.recv() => {}
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use tokio::io::unix::AsyncFd;
+
+/// a key pressed by the user
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    CtrlC,
+}
+
+/// how a line is displayed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Style {
+    #[default]
+    Normal,
+    Bold,
+    Dim,
+    /// the selected line
+    Reverse,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Line {
+    pub(crate) text: String,
+    pub(crate) style: Style,
+}
+
+impl Line {
+    pub(crate) fn new(text: impl Into<String>, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style,
+        }
+    }
+}
+
+/// the controlling terminal in raw mode, showing the alternate screen.
+/// The previous state is restored when dropped.
+pub(crate) struct Terminal {
+    tty: AsyncFd<File>,
+    termios: libc::termios,
+}
+
+impl Terminal {
+    pub(crate) fn enter() -> io::Result<Self> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let termios = unsafe {
+            let mut termios = mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios
+        };
+        let mut raw = termios;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let terminal = Self {
+            tty: AsyncFd::new(tty)?,
+            termios,
+        };
+        /* alternate screen, hide cursor */
+        terminal.write(b"\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    /// number of columns and rows
+    pub(crate) fn size(&self) -> (usize, usize) {
+        let mut size = unsafe { mem::zeroed::<libc::winsize>() };
+        match unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24),
+        }
+    }
+
+    /// wait for keys pressed by the user
+    pub(crate) async fn keys(&self) -> io::Result<Vec<Key>> {
+        loop {
+            let mut guard = self.tty.readable().await?;
+            let mut buf = [0u8; 64];
+            match guard.try_io(|tty| {
+                let fd = tty.as_raw_fd();
+                match unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } {
+                    n if n < 0 => Err(io::Error::last_os_error()),
+                    n => Ok(n as usize),
+                }
+            }) {
+                Ok(Ok(0)) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(Ok(n)) => return Ok(parse_keys(&buf[..n])),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// replace the contents of the screen with `lines`
+    pub(crate) fn draw(&self, lines: &[Line]) -> io::Result<()> {
+        let (width, height) = self.size();
+        let mut screen = String::from("\x1b[H");
+        for (i, line) in lines.iter().take(height).enumerate() {
+            if i > 0 {
+                screen.push_str("\r\n");
+            }
+            let text = line.text.chars().take(width).collect::<String>();
+            match line.style {
+                Style::Normal => screen.push_str(&text),
+                Style::Bold => screen.push_str(&format!("\x1b[1m{text}\x1b[0m")),
+                Style::Dim => screen.push_str(&format!("\x1b[2m{text}\x1b[0m")),
+                Style::Reverse => {
+                    let text = format!("{text:width$}");
+                    screen.push_str(&format!("\x1b[7m{text}\x1b[0m"));
+                }
+            }
+            screen.push_str("\x1b[K");
+        }
+        screen.push_str("\x1b[J");
+        self.write(screen.as_bytes())
+    }
+
+    fn write(&self, mut buf: &[u8]) -> io::Result<()> {
+        /* the terminal is non-blocking but output is expected to be drained quickly */
+        while !buf.is_empty() {
+            match self.tty.get_ref().write(buf) {
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write(b"\x1b[?25h\x1b[?1049l");
+        unsafe {
+            libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.termios);
+        }
+    }
+}
+
+fn parse_keys(buf: &[u8]) -> Vec<Key> {
+    let input = String::from_utf8_lossy(buf);
+    let mut chars = input.chars().peekable();
+    let mut keys = vec![];
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match chars.peek() {
+                Some('[' | 'O') => {
+                    chars.next();
+                    /* skip parameters up to the final character */
+                    match chars.find(|c| ('@'..='~').contains(c)) {
+                        Some('A') => Key::Up,
+                        Some('B') => Key::Down,
+                        /* other escape sequences are ignored */
+                        _ => continue,
+                    }
+                }
+                _ => Key::Esc,
+            },
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x03' => Key::CtrlC,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
@@ -76,7 +76,8 @@ in {
     ];
 
     xdg.configFile."lan-mouse/config.toml" = lib.mkIf (cfg.settings != {}) {
-      source = tomlFormat.generate "config.toml" cfg.settings;
+      # the config is managed declaratively, do not write runtime changes back
+      source = tomlFormat.generate "config.toml" ({persist_changes = false;} // cfg.settings);
     };
   };
 }
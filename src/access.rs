@@ -0,0 +1,347 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// window in which handshakes and rejections are counted
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// number of tracked addresses after which stale entries are pruned
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Error)]
+pub(crate) enum Denied {
+    #[error("not in an allowed network")]
+    NotAllowed,
+    #[error("address is blocked")]
+    Blocked,
+    #[error("temporarily banned")]
+    Banned,
+    #[error("too many handshakes")]
+    RateLimited,
+}
+
+/// decides which peers may attempt a handshake
+pub(crate) struct AccessControl {
+    /// networks that may attempt to connect (any if empty)
+    allowed_networks: Vec<IpNet>,
+    /// fingerprints that are never accepted
+    blocked_fingerprints: HashSet<String>,
+    /// addresses that are never accepted
+    blocked_ips: HashSet<IpAddr>,
+    /// maximum number of handshakes per address and minute
+    max_handshakes: usize,
+    /// number of rejected handshakes per minute resulting in a ban
+    max_rejections: usize,
+    /// duration of a temporary ban
+    ban_duration: Duration,
+    /// recent handshakes per address
+    handshakes: HashMap<IpAddr, VecDeque<Instant>>,
+    /// recent rejected handshakes per address
+    rejections: HashMap<IpAddr, VecDeque<Instant>>,
+    /// temporarily banned addresses
+    bans: HashMap<IpAddr, Instant>,
+}
+
+impl AccessControl {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            allowed_networks: config.allowed_networks(),
+            blocked_fingerprints: config.blocked_fingerprints(),
+            blocked_ips: config.blocked_ips(),
+            max_handshakes: config.max_handshakes_per_minute(),
+            max_rejections: config.max_rejections_per_minute(),
+            ban_duration: config.ban_duration(),
+            handshakes: Default::default(),
+            rejections: Default::default(),
+            bans: Default::default(),
+        }
+    }
+
+    /// apply a reloaded configuration, keeping track of recent handshakes and bans
+    pub(crate) fn update(&mut self, config: &Config) {
+        self.allowed_networks = config.allowed_networks();
+        self.blocked_fingerprints = config.blocked_fingerprints();
+        self.blocked_ips = config.blocked_ips();
+        self.max_handshakes = config.max_handshakes_per_minute();
+        self.max_rejections = config.max_rejections_per_minute();
+        self.ban_duration = config.ban_duration();
+    }
+
+    /// check whether `ip` may start a handshake and record the attempt
+    pub(crate) fn check_handshake(&mut self, ip: IpAddr) -> Result<(), Denied> {
+        self.check_handshake_at(ip, Instant::now())
+    }
+
+    fn check_handshake_at(&mut self, ip: IpAddr, now: Instant) -> Result<(), Denied> {
+        if !self.allowed_networks.is_empty()
+            && !self.allowed_networks.iter().any(|n| n.contains(&ip))
+        {
+            return Err(Denied::NotAllowed);
+        }
+        if self.blocked_ips.contains(&ip) {
+            return Err(Denied::Blocked);
+        }
+        if let Some(until) = self.bans.get(&ip) {
+            if *until > now {
+                return Err(Denied::Banned);
+            }
+            self.bans.remove(&ip);
+        }
+        self.prune(now);
+        if !record(&mut self.handshakes, ip, now, self.max_handshakes) {
+            return Err(Denied::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// record a rejected handshake, returns true if `ip` is banned as a result
+    pub(crate) fn record_rejection(&mut self, ip: IpAddr) -> bool {
+        self.record_rejection_at(ip, Instant::now())
+    }
+
+    fn record_rejection_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let times = self.rejections.entry(ip).or_default();
+        expire(times, now);
+        times.push_back(now);
+        if times.len() < self.max_rejections {
+            return false;
+        }
+        log::warn!(
+            "{ip}: too many rejected handshakes - banned for {:?}",
+            self.ban_duration
+        );
+        self.rejections.remove(&ip);
+        self.bans.insert(ip, now + self.ban_duration);
+        true
+    }
+
+    pub(crate) fn is_blocked_fingerprint(&self, fingerprint: &str) -> bool {
+        self.blocked_fingerprints.contains(fingerprint)
+    }
+
+    pub(crate) fn block_fingerprint(&mut self, fingerprint: String) {
+        self.blocked_fingerprints.insert(fingerprint);
+    }
+
+    pub(crate) fn unblock_fingerprint(&mut self, fingerprint: &str) {
+        self.blocked_fingerprints.remove(fingerprint);
+    }
+
+    pub(crate) fn block_ip(&mut self, ip: IpAddr) {
+        self.blocked_ips.insert(ip);
+    }
+
+    pub(crate) fn unblock_ip(&mut self, ip: IpAddr) {
+        self.blocked_ips.remove(&ip);
+        self.bans.remove(&ip);
+    }
+
+    pub(crate) fn blocked_fingerprints(&self) -> HashSet<String> {
+        self.blocked_fingerprints.clone()
+    }
+
+    pub(crate) fn blocked_ips(&self) -> HashSet<IpAddr> {
+        self.blocked_ips.clone()
+    }
+
+    /// forget about addresses without recent activity
+    fn prune(&mut self, now: Instant) {
+        if self.handshakes.len() + self.rejections.len() + self.bans.len() < PRUNE_THRESHOLD {
+            return;
+        }
+        for times in self
+            .handshakes
+            .values_mut()
+            .chain(self.rejections.values_mut())
+        {
+            expire(times, now);
+        }
+        self.handshakes.retain(|_, t| !t.is_empty());
+        self.rejections.retain(|_, t| !t.is_empty());
+        self.bans.retain(|_, until| *until > now);
+    }
+}
+
+/// record an event for `ip` unless `max` events already happened within [`WINDOW`]
+fn record(
+    events: &mut HashMap<IpAddr, VecDeque<Instant>>,
+    ip: IpAddr,
+    now: Instant,
+    max: usize,
+) -> bool {
+    let times = events.entry(ip).or_default();
+    expire(times, now);
+    if times.len() >= max {
+        return false;
+    }
+    times.push_back(now);
+    true
+}
+
+fn expire(times: &mut VecDeque<Instant>, now: Instant) {
+    while times
+        .front()
+        .is_some_and(|t| now.duration_since(*t) > WINDOW)
+    {
+        times.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::{AccessControl, Denied, PRUNE_THRESHOLD, WINDOW};
+
+    const BAN: Duration = Duration::from_secs(300);
+
+    fn access_control(allowed_networks: &[&str]) -> AccessControl {
+        AccessControl {
+            allowed_networks: allowed_networks
+                .iter()
+                .map(|n| n.parse().unwrap())
+                .collect(),
+            blocked_fingerprints: Default::default(),
+            blocked_ips: Default::default(),
+            max_handshakes: 3,
+            max_rejections: 2,
+            ban_duration: BAN,
+            handshakes: Default::default(),
+            rejections: Default::default(),
+            bans: Default::default(),
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, last))
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut access = access_control(&[]);
+        let now = Instant::now();
+        for i in 0..3 {
+            assert!(
+                access
+                    .check_handshake_at(ip(1), now + Duration::from_secs(i))
+                    .is_ok()
+            );
+        }
+        let t = now + Duration::from_secs(10);
+        assert!(matches!(
+            access.check_handshake_at(ip(1), t),
+            Err(Denied::RateLimited)
+        ));
+        /* counted per address */
+        assert!(access.check_handshake_at(ip(2), t).is_ok());
+        /* the first attempt leaves the window */
+        let t = now + WINDOW + Duration::from_millis(500);
+        assert!(access.check_handshake_at(ip(1), t).is_ok());
+        assert!(matches!(
+            access.check_handshake_at(ip(1), t),
+            Err(Denied::RateLimited)
+        ));
+    }
+
+    #[test]
+    fn ban_and_expiry() {
+        let mut access = access_control(&[]);
+        let now = Instant::now();
+        assert!(!access.record_rejection_at(ip(1), now));
+        assert!(access.record_rejection_at(ip(1), now + Duration::from_secs(1)));
+        let banned_until = now + Duration::from_secs(1) + BAN;
+        assert!(matches!(
+            access.check_handshake_at(ip(1), banned_until - Duration::from_secs(1)),
+            Err(Denied::Banned)
+        ));
+        assert!(access.check_handshake_at(ip(2), now).is_ok());
+        assert!(access.check_handshake_at(ip(1), banned_until).is_ok());
+        assert!(access.bans.is_empty());
+    }
+
+    #[test]
+    fn rejections_expire() {
+        let mut access = access_control(&[]);
+        let now = Instant::now();
+        assert!(!access.record_rejection_at(ip(1), now));
+        /* outside the window of the first rejection */
+        let later = now + WINDOW + Duration::from_secs(1);
+        assert!(!access.record_rejection_at(ip(1), later));
+        assert!(access.check_handshake_at(ip(1), later).is_ok());
+    }
+
+    #[test]
+    fn unblock_lifts_ban() {
+        let mut access = access_control(&[]);
+        let now = Instant::now();
+        access.record_rejection_at(ip(1), now);
+        access.record_rejection_at(ip(1), now);
+        access.block_ip(ip(1));
+        assert!(matches!(
+            access.check_handshake_at(ip(1), now),
+            Err(Denied::Blocked)
+        ));
+        access.unblock_ip(ip(1));
+        assert!(access.check_handshake_at(ip(1), now).is_ok());
+    }
+
+    #[test]
+    fn prune_stale_entries() {
+        let mut access = access_control(&[]);
+        let now = Instant::now();
+        for i in 0..PRUNE_THRESHOLD as u32 {
+            let ip = IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i));
+            assert!(access.check_handshake_at(ip, now).is_ok());
+        }
+        access.record_rejection_at(ip(1), now);
+        access.record_rejection_at(ip(1), now);
+        assert_eq!(access.handshakes.len(), PRUNE_THRESHOLD);
+        assert_eq!(access.bans.len(), 1);
+
+        /* recent entries are kept */
+        let t = now + Duration::from_secs(1);
+        assert!(access.check_handshake_at(ip(2), t).is_ok());
+        assert_eq!(access.handshakes.len(), PRUNE_THRESHOLD + 1);
+
+        let t = now + BAN + WINDOW;
+        assert!(access.check_handshake_at(ip(3), t).is_ok());
+        assert_eq!(access.handshakes.len(), 1);
+        assert!(access.rejections.is_empty());
+        assert!(access.bans.is_empty());
+    }
+
+    #[test]
+    fn allowed_networks() {
+        let mut access = access_control(&["192.168.1.0/24", "fd00::/8"]);
+        let now = Instant::now();
+        assert!(access.check_handshake_at(ip(7), now).is_ok());
+        assert!(
+            access
+                .check_handshake_at("fd12::1".parse().unwrap(), now)
+                .is_ok()
+        );
+        for denied in ["192.168.2.1", "10.0.0.1", "fe80::1"] {
+            assert!(matches!(
+                access.check_handshake_at(denied.parse().unwrap(), now),
+                Err(Denied::NotAllowed)
+            ));
+        }
+        /* any network if none is configured */
+        let mut access = access_control(&[]);
+        assert!(
+            access
+                .check_handshake_at("10.0.0.1".parse().unwrap(), now)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use crate::cert::{self, CertError};
+use crate::config::{self, Config};
+use crate::crypto;
+use clap::Args;
+use rustls::pki_types::CertificateDer;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use webrtc_dtls::crypto::{Certificate, CryptoPrivateKey};
+
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+pub struct BackupArgs {
+    /// archive to create
+    path: PathBuf,
+    /// do not encrypt the archive with a passphrase.
+    /// The archive then contains the private key in plain text!
+    #[arg(long)]
+    unencrypted: bool,
+    /// replace an existing archive
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+pub struct RestoreArgs {
+    /// archive created by `lan-mouse backup`
+    path: PathBuf,
+    /// replace an existing certificate and config file
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error(transparent)]
+    Cert(#[from] CertError),
+    #[error(transparent)]
+    Crypto(#[from] crypto::Error),
+    #[error("{path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{0:?} already exists - use `--force` to replace it")]
+    Exists(PathBuf),
+    #[error("invalid backup archive: {0}")]
+    InvalidArchive(String),
+    #[error("passphrases do not match")]
+    PassphraseMismatch,
+}
+
+trait Context<T> {
+    fn context(self, path: &Path) -> Result<T, BackupError>;
+}
+
+impl<T> Context<T> for Result<T, io::Error> {
+    fn context(self, path: &Path) -> Result<T, BackupError> {
+        self.map_err(|source| BackupError::Io {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+const PRIVATE_KEY_TAG: &str = "PRIVATE KEY";
+const CERTIFICATE_TAG: &str = "CERTIFICATE";
+const CONFIG_TAG: &str = "LAN-MOUSE CONFIG";
+const ENCRYPTED_BACKUP_TAG: &str = "ENCRYPTED LAN-MOUSE BACKUP";
+
+/// contents of a backup archive
+struct Archive {
+    certificate: Certificate,
+    /// the user config file, including the authorized fingerprints
+    config: Option<Vec<u8>>,
+}
+
+impl Archive {
+    fn encode(&self) -> String {
+        let mut pems = vec![pem::Pem::new(
+            PRIVATE_KEY_TAG,
+            self.certificate.private_key.serialized_der.clone(),
+        )];
+        pems.extend(
+            self.certificate
+                .certificate
+                .iter()
+                .map(|c| pem::Pem::new(CERTIFICATE_TAG, c.as_ref())),
+        );
+        if let Some(config) = &self.config {
+            pems.push(pem::Pem::new(CONFIG_TAG, config.clone()));
+        }
+        pem::encode_many(&pems)
+    }
+
+    fn decode(s: &str) -> Result<Self, BackupError> {
+        let pems = pem::parse_many(s).map_err(|e| BackupError::InvalidArchive(e.to_string()))?;
+        let (mut key, mut certificate, mut config) = (None, vec![], None);
+        for p in pems {
+            match p.tag() {
+                PRIVATE_KEY_TAG => key = Some(p.into_contents()),
+                CERTIFICATE_TAG => certificate.push(CertificateDer::from(p.into_contents())),
+                CONFIG_TAG => config = Some(p.into_contents()),
+                tag => {
+                    return Err(BackupError::InvalidArchive(format!(
+                        "unexpected tag '{tag}'"
+                    )));
+                }
+            }
+        }
+        let key = key.ok_or(BackupError::InvalidArchive("private key missing".into()))?;
+        if certificate.is_empty() {
+            return Err(BackupError::InvalidArchive("certificate missing".into()));
+        }
+        let key_pair = rcgen::KeyPair::try_from(key.as_slice())
+            .map_err(|e| BackupError::InvalidArchive(e.to_string()))?;
+        let private_key = CryptoPrivateKey::from_key_pair(&key_pair)
+            .map_err(|e| BackupError::InvalidArchive(e.to_string()))?;
+        Ok(Self {
+            certificate: Certificate {
+                certificate,
+                private_key,
+            },
+            config,
+        })
+    }
+}
+
+/// bundle certificate, private key and config file into a single archive
+pub async fn backup(config: Config, args: BackupArgs) -> Result<(), BackupError> {
+    if args.path.exists() && !args.force {
+        return Err(BackupError::Exists(args.path));
+    }
+    let mut key_passphrase = config.key_passphrase().context(config.cert_path())?;
+    let certificate = cert::load(config.cert_path(), &mut key_passphrase).await?;
+    let config_path = config.config_path();
+    let config_file = match fs::read(config_path) {
+        Ok(c) => Some(c),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).context(config_path),
+    };
+    let has_config = config_file.is_some();
+    let archive = Archive {
+        certificate,
+        config: config_file,
+    }
+    .encode();
+
+    let archive = match args.unencrypted {
+        true => archive,
+        false => {
+            let passphrase = new_passphrase(&args.path)?;
+            let encrypted = crypto::encrypt_data(archive.as_bytes(), &passphrase)?;
+            pem::encode(&pem::Pem::new(ENCRYPTED_BACKUP_TAG, encrypted))
+        }
+    };
+    write_private(&args.path, archive.as_bytes()).context(&args.path)?;
+
+    println!("certificate: {}", config.cert_path().display());
+    if has_config {
+        println!("config file: {}", config_path.display());
+    }
+    println!("written to {}", args.path.display());
+    if args.unencrypted {
+        println!("warning: the archive contains the private key unencrypted");
+    }
+    Ok(())
+}
+
+/// restore certificate, private key and config file from an archive
+pub async fn restore(config: Config, args: RestoreArgs) -> Result<(), BackupError> {
+    let s = fs::read_to_string(&args.path).context(&args.path)?;
+    let pems = pem::parse_many(&s).map_err(|e| BackupError::InvalidArchive(e.to_string()))?;
+    let archive = match pems.as_slice() {
+        [p] if p.tag() == ENCRYPTED_BACKUP_TAG => {
+            let passphrase =
+                rpassword::prompt_password(format!("passphrase for {}: ", args.path.display()))
+                    .context(&args.path)?;
+            let decrypted = crypto::decrypt_data(p.contents(), &passphrase)?;
+            let decrypted = String::from_utf8(decrypted)
+                .map_err(|e| BackupError::InvalidArchive(e.to_string()))?;
+            Archive::decode(&decrypted)?
+        }
+        _ => {
+            warn_if_exposed(&args.path);
+            Archive::decode(&s)?
+        }
+    };
+
+    let cert_path = config.cert_path();
+    let config_path = config.config_path();
+    if !args.force {
+        if cert_path.exists() {
+            return Err(BackupError::Exists(cert_path.to_owned()));
+        }
+        if archive.config.is_some() && config_path.exists() {
+            return Err(BackupError::Exists(config_path.to_owned()));
+        }
+    }
+
+    /* everything that may fail is checked before anything is replaced */
+    if let Some(contents) = &archive.config {
+        let contents = std::str::from_utf8(contents)
+            .map_err(|e| BackupError::InvalidArchive(format!("config file: {e}")))?;
+        contents
+            .parse::<toml::Table>()
+            .map_err(|e| BackupError::InvalidArchive(format!("config file: {e}")))?;
+    }
+
+    /* the private key is stored as configured, with the permissions it was generated with */
+    let storage = config.key_storage();
+    let mut passphrase = config.key_passphrase().context(cert_path)?;
+    cert::prompt_new_passphrase(storage, &mut passphrase)?;
+    crypto::store_certificate(
+        cert_path,
+        &archive.certificate,
+        storage,
+        passphrase.as_deref(),
+    )
+    .await?;
+    println!("restored {}", cert_path.display());
+
+    if let Some(contents) = &archive.config {
+        #[cfg(unix)]
+        let permissions = Some(fs::Permissions::from_mode(0o600)); /* rw- --- --- */
+        /* FIXME windows permissions */
+        #[cfg(not(unix))]
+        let permissions = None;
+        config::write_atomic_with_permissions(config_path, contents, permissions)
+            .context(config_path)?;
+        println!("restored {}", config_path.display());
+    }
+    println!(
+        "fingerprint: {}",
+        crypto::certificate_fingerprint(&archive.certificate)
+    );
+    println!("restart the service to use the restored configuration");
+    Ok(())
+}
+
+/// prompt for the passphrase protecting the archive
+fn new_passphrase(path: &Path) -> Result<String, BackupError> {
+    let p = rpassword::prompt_password(format!("new passphrase for {}: ", path.display()))
+        .context(path)?;
+    if p != rpassword::prompt_password("confirm passphrase: ").context(path)? {
+        return Err(BackupError::PassphraseMismatch);
+    }
+    Ok(p)
+}
+
+/// write `contents` to a file readable only by the owner
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let _ = fs::remove_file(path);
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600); /* rw- --- --- */
+    /* FIXME windows permissions */
+    let mut f = options.open(path)?;
+    f.write_all(contents)
+}
+
+/// warn if an unencrypted archive is accessible by other users
+fn warn_if_exposed(path: &Path) {
+    #[cfg(unix)]
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            log::warn!(
+                "{} contains an unencrypted private key but is accessible by other users",
+                path.display()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
@@ -21,6 +21,8 @@ pub(crate) struct Capture {
     request_tx: Sender<CaptureRequest>,
     task: JoinHandle<()>,
     event_rx: Receiver<ICaptureEvent>,
+    /// shared with the capture task
+    release_bind: Rc<RefCell<Vec<scancode::Linux>>>,
 }
 
 pub(crate) enum ICaptureEvent {
@@ -37,6 +39,8 @@ pub(crate) enum ICaptureEvent {
     /// either the remote client leaving its device region,
     /// a new device entering the screen or the release bind.
     ClientEntered(u64),
+    /// the capture of the entered client was released
+    ClientLeft(u64),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -70,6 +74,7 @@ impl Capture {
         let (request_tx, request_rx) = channel();
         let (event_tx, event_rx) = channel();
         let cancellation_token = CancellationToken::new();
+        let release_bind = Rc::new(RefCell::new(release_bind));
         let capture_task = CaptureTask {
             active_client: None,
             backend,
@@ -78,7 +83,7 @@ impl Capture {
             conn,
             event_tx,
             request_rx,
-            release_bind: Rc::new(RefCell::new(release_bind)),
+            release_bind: release_bind.clone(),
             state: Default::default(),
         };
         let task = spawn_local(capture_task.run());
@@ -87,9 +92,18 @@ impl Capture {
             request_tx,
             task,
             event_rx,
+            release_bind,
         }
     }
 
+    pub(crate) fn set_release_bind(&self, release_bind: Vec<scancode::Linux>) {
+        self.release_bind.replace(release_bind);
+    }
+
+    pub(crate) fn release_bind(&self) -> Vec<scancode::Linux> {
+        self.release_bind.borrow().clone()
+    }
+
     pub(crate) fn reenable(&self) {
         self.request_tx
             .send(CaptureRequest::Reenable)
@@ -233,6 +247,11 @@ impl CaptureTask {
         }
 
         let r = self.do_capture_session(&mut capture).await;
+        if let Some(handle) = self.active_client.take() {
+            self.event_tx
+                .send(ICaptureEvent::ClientLeft(handle))
+                .expect("channel closed");
+        }
 
         // FIXME replace with async drop when stabilized
         capture.terminate().await?;
@@ -362,7 +381,11 @@ impl CaptureTask {
     }
 
     async fn release_capture(&mut self, capture: &mut InputCapture) -> Result<(), CaptureError> {
-        self.active_client.take();
+        if let Some(handle) = self.active_client.take() {
+            self.event_tx
+                .send(ICaptureEvent::ClientLeft(handle))
+                .expect("channel closed");
+        }
         capture.release().await
     }
 }
@@ -0,0 +1,176 @@
+use crate::config::{Config, KeyStorage};
+use crate::crypto;
+use clap::{Args, Subcommand};
+use rustls::pki_types::CertificateDer;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+use webrtc_dtls::crypto::Certificate;
+
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+pub struct CertArgs {
+    #[command(subcommand)]
+    command: CertCommand,
+}
+
+#[derive(Subcommand, Clone, Debug, Eq, PartialEq)]
+enum CertCommand {
+    /// show the certificate fingerprint and key rotation status
+    Show,
+    /// generate a new key and certificate, replacing the current one
+    Regenerate {
+        /// number of days during which peers that authorized the
+        /// current key automatically accept the new one
+        #[arg(long, default_value = "30")]
+        transition_days: u64,
+        /// do not sign a rotation statement with the current key
+        /// (e.g. because it was compromised)
+        #[arg(long)]
+        no_rotation: bool,
+    },
+    /// export the public certificate in PEM format
+    Export {
+        /// output file (defaults to stdout)
+        path: Option<PathBuf>,
+    },
+    /// import a private key and certificate in PEM format
+    Import {
+        path: PathBuf,
+        /// replace an existing certificate
+        #[arg(long)]
+        force: bool,
+    },
+    /// store the private key as configured by `key_storage`
+    Migrate,
+}
+
+#[derive(Debug, Error)]
+pub enum CertError {
+    #[error(transparent)]
+    Crypto(#[from] crypto::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{0:?} already exists - use `--force` to replace it")]
+    Exists(PathBuf),
+    #[error("passphrases do not match")]
+    PassphraseMismatch,
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub async fn run(config: Config, args: CertArgs) -> Result<(), CertError> {
+    let path = config.cert_path().to_owned();
+    let storage = config.key_storage();
+    let mut passphrase = config.key_passphrase()?;
+    match args.command {
+        CertCommand::Show => {
+            let chain = crypto::load_certificate_chain(&path)?;
+            let der = chain.first().expect("certificate missing");
+            println!("certificate: {}", path.display());
+            println!("fingerprint: {}", crypto::generate_fingerprint(der));
+            println!("key storage: {}", crypto::key_storage(&path)?);
+            if let Some(rotation) = crypto::parse_key_rotation(der) {
+                let status = match rotation.not_after.duration_since(SystemTime::now()) {
+                    Ok(d) => format!(
+                        "transition window ends in {} days",
+                        d.as_secs() / SECS_PER_DAY
+                    ),
+                    Err(_) => "transition window ended".to_owned(),
+                };
+                println!("rotated from: {} ({status})", rotation.old_fingerprint);
+            }
+        }
+        CertCommand::Regenerate {
+            transition_days,
+            no_rotation,
+        } => {
+            let cert = if path.is_file() && !no_rotation {
+                let old = load(&path, &mut passphrase).await?;
+                let window = Duration::from_secs(transition_days * SECS_PER_DAY);
+                let cert = crypto::generate_rotated_key_and_cert(&old, SystemTime::now() + window)?;
+                prompt_new_passphrase(storage, &mut passphrase)?;
+                crypto::store_certificate(&path, &cert, storage, passphrase.as_deref()).await?;
+                println!("replaced {}", crypto::certificate_fingerprint(&old));
+                println!("peers that authorized it accept the new key for {transition_days} days");
+                cert
+            } else {
+                prompt_new_passphrase(storage, &mut passphrase)?;
+                crypto::generate_key_and_cert(&path, storage, passphrase.as_deref()).await?
+            };
+            println!("fingerprint: {}", crypto::certificate_fingerprint(&cert));
+            println!("restart the service to use the new certificate");
+        }
+        CertCommand::Export { path: out } => {
+            let chain = crypto::load_certificate_chain(&path)?;
+            let pem = public_pem(&chain);
+            match out {
+                Some(out) => fs::write(out, pem)?,
+                None => print!("{pem}"),
+            }
+        }
+        CertCommand::Import { path: input, force } => {
+            if path.exists() && !force {
+                return Err(CertError::Exists(path));
+            }
+            let cert = load(&input, &mut passphrase).await?;
+            prompt_new_passphrase(storage, &mut passphrase)?;
+            crypto::store_certificate(&path, &cert, storage, passphrase.as_deref()).await?;
+            println!("imported {}", crypto::certificate_fingerprint(&cert));
+        }
+        CertCommand::Migrate => {
+            let cert = load(&path, &mut passphrase).await?;
+            if crypto::key_storage(&path)? == storage {
+                println!("private key already uses `{storage}` storage");
+                return Ok(());
+            }
+            prompt_new_passphrase(storage, &mut passphrase)?;
+            crypto::store_certificate(&path, &cert, storage, passphrase.as_deref()).await?;
+            println!("private key migrated to `{storage}` storage");
+        }
+    }
+    Ok(())
+}
+
+/// load a certificate, prompting for the passphrase if required
+pub(crate) async fn load(
+    path: &Path,
+    passphrase: &mut Option<String>,
+) -> Result<Certificate, CertError> {
+    match crypto::load_certificate(path, passphrase.as_deref()).await {
+        Err(crypto::Error::PassphraseRequired) => {
+            let p = rpassword::prompt_password(format!("passphrase for {}: ", path.display()))?;
+            let cert = crypto::load_certificate(path, Some(&p)).await?;
+            passphrase.replace(p);
+            Ok(cert)
+        }
+        r => Ok(r?),
+    }
+}
+
+/// prompt for a new passphrase if the key is to be encrypted and none is known
+pub(crate) fn prompt_new_passphrase(
+    storage: KeyStorage,
+    passphrase: &mut Option<String>,
+) -> Result<(), CertError> {
+    if storage != KeyStorage::Passphrase || passphrase.is_some() {
+        return Ok(());
+    }
+    let p = rpassword::prompt_password("new passphrase: ")?;
+    if p != rpassword::prompt_password("confirm passphrase: ")? {
+        return Err(CertError::PassphraseMismatch);
+    }
+    passphrase.replace(p);
+    Ok(())
+}
+
+/// PEM encoding of the certificate chain without the private key
+fn public_pem(chain: &[CertificateDer]) -> String {
+    let pems = chain
+        .iter()
+        .map(|c| pem::Pem::new("CERTIFICATE", c.as_ref()))
+        .collect::<Vec<_>>();
+    pem::encode_many(&pems)
+}
@@ -7,7 +7,7 @@ use std::{
 
 use slab::Slab;
 
-use lan_mouse_ipc::{ClientConfig, ClientHandle, ClientState, Position};
+use lan_mouse_ipc::{ClientConfig, ClientHandle, ClientState, MacAddress, Position};
 
 #[derive(Clone, Default)]
 pub struct ClientManager {
@@ -206,6 +206,13 @@ impl ClientManager {
         }
     }
 
+    /// update the mac address used to wake the client
+    pub(crate) fn set_mac(&self, handle: ClientHandle, mac: Option<MacAddress>) {
+        if let Some((c, _s)) = self.clients.borrow_mut().get_mut(handle as usize) {
+            c.mac = mac;
+        }
+    }
+
     /// set resolving status of the client
     pub(crate) fn set_resolving(&self, handle: ClientHandle, status: bool) {
         if let Some((_, s)) = self.clients.borrow_mut().get_mut(handle as usize) {
@@ -265,6 +272,13 @@ impl ClientManager {
             .map(|(c, _)| c.port)
     }
 
+    pub(crate) fn get_mac(&self, handle: ClientHandle) -> Option<MacAddress> {
+        self.clients
+            .borrow()
+            .get(handle as usize)
+            .and_then(|(c, _)| c.mac)
+    }
+
     pub(crate) fn get_ips(&self, handle: ClientHandle) -> Option<HashSet<IpAddr>> {
         self.clients
             .borrow()
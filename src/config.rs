@@ -1,19 +1,24 @@
+use crate::backup::{BackupArgs, RestoreArgs};
 use crate::capture_test::TestCaptureArgs;
+use crate::cert::CertArgs;
+use crate::config::check::ConfigArgs;
 use crate::emulation_test::TestEmulationArgs;
 use clap::{Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env::{self, VarError};
 use std::fmt::Display;
 use std::fs;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{collections::HashSet, io};
 use thiserror::Error;
 use toml;
 
 use lan_mouse_cli::CliArgs;
-use lan_mouse_ipc::{DEFAULT_PORT, Position};
+use lan_mouse_ipc::{DEFAULT_PORT, MacAddress, Position};
 
 use input_event::scancode::{
     self,
@@ -22,8 +27,56 @@ use input_event::scancode::{
 
 use shadow_rs::shadow;
 
+pub mod check;
+mod layers;
+mod migrate;
+mod schema;
+mod watch;
+mod writer;
+
+use layers::Layered;
+pub(crate) use layers::drop_in_dir;
+pub(crate) use watch::{ConfigChange, ConfigWatcher};
+pub(crate) use writer::{ConfigWriter, LiveConfig, write_atomic_with_permissions};
+
 shadow!(build);
 
+/// a temporary directory for tests, removed when dropped
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("lan-mouse-{name}-{}-{n}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create temporary directory");
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// write `contents` to the file `name` in the directory
+    pub(crate) fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create directory");
+        fs::write(&path, contents).expect("write file");
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
 const CONFIG_FILE_NAME: &str = "config.toml";
 const CERT_FILE_NAME: &str = "lan-mouse.pem";
 
@@ -44,38 +97,75 @@ fn default_path() -> Result<PathBuf, VarError> {
     Ok(PathBuf::from(default_path))
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 struct ConfigToml {
+    /// layout version of the config file, see [`migrate`]
+    version: Option<i64>,
     capture_backend: Option<CaptureBackend>,
     emulation_backend: Option<EmulationBackend>,
     port: Option<u16>,
     release_bind: Option<Vec<scancode::Linux>>,
     cert_path: Option<PathBuf>,
+    key_storage: Option<KeyStorage>,
+    key_passphrase_file: Option<PathBuf>,
     clients: Option<Vec<TomlClient>>,
+    profile: Option<String>,
+    profiles: Option<HashMap<String, TomlProfile>>,
     authorized_fingerprints: Option<HashMap<String, String>>,
-    #[cfg(feature = "screen_share")]
-    enable_screen_share: Option<bool>,
-    #[cfg(feature = "screen_share")]
-    screen_share_fps: Option<u32>,
-    #[cfg(feature = "screen_share")]
-    screen_share_quality: Option<u8>,
+    blocked_fingerprints: Option<HashSet<String>>,
+    blocked_ips: Option<HashSet<IpAddr>>,
+    allowed_networks: Option<Vec<IpNet>>,
+    max_handshakes_per_minute: Option<usize>,
+    max_rejections_per_minute: Option<usize>,
+    ban_duration_secs: Option<u64>,
+    wake_window_secs: Option<u64>,
+    persist_changes: Option<bool>,
+    ipc_read_only_group: Option<String>,
+    management: Option<TomlManagement>,
+    /// read regardless of the `screen_share` feature, so the same file works with every build
+    screen_share: Option<TomlScreenShare>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+struct TomlScreenShare {
+    enabled: Option<bool>,
+    fps: Option<u32>,
+    quality: Option<u8>,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+struct TomlManagement {
+    /// address of the remote management listener, disabled without one
+    address: Option<SocketAddr>,
+    /// fingerprints with the admin scope, with a description
+    admin_fingerprints: Option<HashMap<String, String>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
 struct TomlClient {
     hostname: Option<String>,
-    host_name: Option<String>,
     ips: Option<Vec<IpAddr>>,
     port: Option<u16>,
     position: Option<Position>,
     activate_on_startup: Option<bool>,
     enter_hook: Option<String>,
+    mac_address: Option<MacAddress>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+struct TomlProfile {
+    clients: Option<Vec<TomlClient>>,
+    /// networks (gateway mac address or SSID) selecting this profile
+    networks: Option<Vec<String>>,
 }
 
 impl ConfigToml {
-    fn new(path: &Path) -> Result<ConfigToml, ConfigError> {
-        let config = fs::read_to_string(path)?;
-        Ok(toml::from_str::<_>(&config)?)
+    /// load the config files applying to the user config file at `path`,
+    /// returns `None` if none of them exist
+    fn load(path: &Path) -> Result<Option<ConfigToml>, ConfigError> {
+        Layered::load(&layers::files(path))?
+            .map(|l| l.config())
+            .transpose()
     }
 }
 
@@ -90,6 +180,10 @@ struct Args {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// layout profile to start with
+    #[arg(long)]
+    profile: Option<String>,
+
     /// capture backend override
     #[arg(long)]
     capture_backend: Option<CaptureBackend>,
@@ -102,6 +196,19 @@ struct Args {
     #[arg(long)]
     cert_path: Option<PathBuf>,
 
+    /// how the private key is stored
+    #[arg(long)]
+    key_storage: Option<KeyStorage>,
+
+    /// read the passphrase of the private key from this file descriptor
+    #[cfg(unix)]
+    #[arg(long)]
+    key_passphrase_fd: Option<i32>,
+
+    /// start the service even if the config file can not be parsed
+    #[arg(long)]
+    ignore_config_errors: bool,
+
     /// enable screen sharing
     #[cfg(feature = "screen_share")]
     #[arg(long, default_value = "false")]
@@ -122,6 +229,13 @@ struct Args {
     command: Option<Command>,
 }
 
+impl Args {
+    /// whether the service is run (either directly or as a child of the frontend)
+    fn runs_service(&self) -> bool {
+        matches!(self.command, None | Some(Command::Daemon))
+    }
+}
+
 #[derive(Subcommand, Clone, Debug, Eq, PartialEq)]
 pub enum Command {
     /// test input emulation
@@ -130,6 +244,17 @@ pub enum Command {
     TestCapture(TestCaptureArgs),
     /// Lan Mouse commandline interface
     Cli(CliArgs),
+    /// interactive terminal frontend
+    #[cfg(unix)]
+    Tui,
+    /// manage the tls certificate
+    Cert(CertArgs),
+    /// back up certificate, private key and configuration into an archive
+    Backup(BackupArgs),
+    /// restore certificate, private key and configuration from an archive
+    Restore(RestoreArgs),
+    /// validate the config file
+    Config(ConfigArgs),
     /// run in daemon mode
     Daemon,
 }
@@ -255,6 +380,32 @@ impl Display for EmulationBackend {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ValueEnum)]
+pub enum KeyStorage {
+    /// unencrypted PEM file, readable only by the owner
+    #[default]
+    #[serde(rename = "plain")]
+    Plain,
+    /// PKCS#8 encrypted with a passphrase
+    #[serde(rename = "passphrase")]
+    Passphrase,
+    /// freedesktop Secret Service (e.g. gnome-keyring, KWallet)
+    #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+    #[serde(rename = "secret-service")]
+    SecretService,
+}
+
+impl Display for KeyStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyStorage::Plain => write!(f, "plain"),
+            KeyStorage::Passphrase => write!(f, "passphrase"),
+            #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+            KeyStorage::SecretService => write!(f, "secret-service"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
     /// command line arguments
@@ -274,6 +425,7 @@ pub struct ConfigClient {
     pub pos: Position,
     pub active: bool,
     pub enter_hook: Option<String>,
+    pub mac: Option<MacAddress>,
 }
 
 impl From<TomlClient> for ConfigClient {
@@ -284,6 +436,7 @@ impl From<TomlClient> for ConfigClient {
         let ips = HashSet::from_iter(toml.ips.into_iter().flatten());
         let port = toml.port.unwrap_or(DEFAULT_PORT);
         let pos = toml.position.unwrap_or_default();
+        let mac = toml.mac_address;
         Self {
             ips,
             hostname,
@@ -291,6 +444,7 @@ impl From<TomlClient> for ConfigClient {
             pos,
             active,
             enter_hook,
+            mac,
         }
     }
 }
@@ -300,17 +454,56 @@ pub enum ConfigError {
     #[error(transparent)]
     Toml(#[from] toml::de::Error),
     #[error(transparent)]
+    TomlEdit(#[from] toml_edit::TomlError),
+    #[error(transparent)]
     Io(#[from] io::Error),
     #[error(transparent)]
     Var(#[from] VarError),
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: Box<ConfigError>,
+    },
+    #[error(
+        "{0}\nrun `lan-mouse config check` for details \
+         or pass `--ignore-config-errors` to start anyway"
+    )]
+    Invalid(Box<ConfigError>),
+    #[error("{errors} error(s), {warnings} warning(s)")]
+    Check { errors: usize, warnings: usize },
+    #[error(
+        "config version {0} is not supported by this version of lan-mouse (up to {supported})",
+        supported = migrate::VERSION
+    )]
+    UnsupportedVersion(i64),
+    #[error("invalid config version `{0}`")]
+    InvalidVersion(String),
 }
 
+const DEFAULT_MAX_HANDSHAKES_PER_MINUTE: usize = 30;
+const DEFAULT_MAX_REJECTIONS_PER_MINUTE: usize = 5;
+const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(10 * 60);
+
+const DEFAULT_WAKE_WINDOW: Duration = Duration::from_secs(60);
+
+/// name of the profile made up of the top-level `clients`
+pub const DEFAULT_PROFILE: &str = "default";
+
 const DEFAULT_RELEASE_KEYS: [scancode::Linux; 4] =
     [KeyLeftCtrl, KeyLeftShift, KeyLeftMeta, KeyLeftAlt];
 
 impl Config {
     pub fn new() -> Result<Self, ConfigError> {
-        let args = Args::parse();
+        Self::parse_from(env::args_os())
+    }
+
+    /// the config for the command line arguments `args`
+    pub(crate) fn parse_from<I, T>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let args = Args::parse_from(args);
 
         // --config <file> overrules default location
         let config_path = args
@@ -318,13 +511,26 @@ impl Config {
             .clone()
             .unwrap_or(default_path()?.join(CONFIG_FILE_NAME));
 
-        let config_toml = match ConfigToml::new(&config_path) {
+        let config_toml = match ConfigToml::load(&config_path) {
+            /* about to be replaced */
+            Ok(None) | Err(_) if matches!(args.command, Some(Command::Restore(_))) => None,
+            Ok(None) => {
+                log::warn!("{config_path:?}: no config file found");
+                log::warn!("Continuing without config file ...");
+                None
+            }
+            /* a broken config would silently drop all clients */
+            Err(e) if args.runs_service() && !args.ignore_config_errors => {
+                return Err(ConfigError::Invalid(Box::new(e)));
+            }
+            /* reported in detail by the check itself */
+            Err(_) if matches!(args.command, Some(Command::Config(_))) => None,
             Err(e) => {
-                log::warn!("{config_path:?}: {e}");
+                log::warn!("{e}");
                 log::warn!("Continuing without config file ...");
                 None
             }
-            Ok(c) => Some(c),
+            Ok(c) => c,
         };
 
         // --cert-path <file> overrules default location
@@ -342,6 +548,17 @@ impl Config {
         })
     }
 
+    /// read the config files again, keeping the command line arguments
+    pub fn reload(&self) -> Result<Self, ConfigError> {
+        let config_toml = ConfigToml::load(&self.config_path)?;
+        Ok(Config {
+            args: self.args.clone(),
+            cert_path: self.cert_path.clone(),
+            config_path: self.config_path.clone(),
+            config_toml,
+        })
+    }
+
     /// the command to run
     pub fn command(&self) -> Option<Command> {
         self.args.command.clone()
@@ -351,6 +568,11 @@ impl Config {
         &self.config_path
     }
 
+    /// all config files that are applied, in order of increasing precedence
+    pub(crate) fn config_files(&self) -> Vec<PathBuf> {
+        layers::files(&self.config_path)
+    }
+
     /// public key fingerprints authorized for connection
     pub fn authorized_fingerprints(&self) -> HashMap<String, String> {
         self.config_toml
@@ -359,11 +581,133 @@ impl Config {
             .unwrap_or_default()
     }
 
+    /// public key fingerprints that are never accepted
+    pub fn blocked_fingerprints(&self) -> HashSet<String> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.blocked_fingerprints.clone())
+            .unwrap_or_default()
+    }
+
+    /// ip addresses that are never accepted
+    pub fn blocked_ips(&self) -> HashSet<IpAddr> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.blocked_ips.clone())
+            .unwrap_or_default()
+    }
+
+    /// networks that may attempt to connect (any if empty)
+    pub fn allowed_networks(&self) -> Vec<IpNet> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.allowed_networks.clone())
+            .unwrap_or_default()
+    }
+
+    /// maximum number of handshakes per address and minute
+    pub fn max_handshakes_per_minute(&self) -> usize {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.max_handshakes_per_minute)
+            .unwrap_or(DEFAULT_MAX_HANDSHAKES_PER_MINUTE)
+    }
+
+    /// number of rejected handshakes per minute after which an address is banned
+    pub fn max_rejections_per_minute(&self) -> usize {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.max_rejections_per_minute)
+            .unwrap_or(DEFAULT_MAX_REJECTIONS_PER_MINUTE)
+    }
+
+    /// duration of a temporary ban
+    pub fn ban_duration(&self) -> Duration {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.ban_duration_secs)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_BAN_DURATION)
+    }
+
+    /// how long to keep trying to connect after waking a client
+    pub fn wake_window(&self) -> Duration {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.wake_window_secs)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_WAKE_WINDOW)
+    }
+
+    /// whether changes made at runtime are written back to the config file
+    pub fn persist_changes(&self) -> bool {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.persist_changes)
+            .unwrap_or(true)
+    }
+
+    /// group whose members may connect to the frontend socket with read-only access
+    pub fn ipc_read_only_group(&self) -> Option<String> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.ipc_read_only_group.clone())
+    }
+
+    /// address of the remote management listener, if enabled
+    pub fn management_address(&self) -> Option<SocketAddr> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.management.as_ref())
+            .and_then(|m| m.address)
+    }
+
+    /// public key fingerprints that may manage the service remotely
+    pub fn admin_fingerprints(&self) -> HashMap<String, String> {
+        self.config_toml
+            .as_ref()
+            .and_then(|c| c.management.as_ref())
+            .and_then(|m| m.admin_fingerprints.clone())
+            .unwrap_or_default()
+    }
+
     /// path to certificate
     pub fn cert_path(&self) -> &Path {
         &self.cert_path
     }
 
+    /// how the private key is stored
+    pub fn key_storage(&self) -> KeyStorage {
+        self.args
+            .key_storage
+            .or(self.config_toml.as_ref().and_then(|c| c.key_storage))
+            .unwrap_or_default()
+    }
+
+    /// passphrase of the private key, if supplied
+    /// through `--key-passphrase-fd` or `key_passphrase_file`.
+    /// The file descriptor is closed after reading, so this must only be called once.
+    pub fn key_passphrase(&self) -> Result<Option<String>, io::Error> {
+        #[cfg(unix)]
+        if let Some(fd) = self.args.key_passphrase_fd {
+            use std::io::Read;
+            use std::os::fd::FromRawFd;
+            // SAFETY: ownership of the fd is passed to us on the command line
+            let mut f = unsafe { fs::File::from_raw_fd(fd) };
+            let mut passphrase = String::new();
+            f.read_to_string(&mut passphrase)?;
+            return Ok(Some(first_line(passphrase)));
+        }
+        match self
+            .config_toml
+            .as_ref()
+            .and_then(|c| c.key_passphrase_file.as_ref())
+        {
+            Some(path) => Ok(Some(first_line(fs::read_to_string(path)?))),
+            None => Ok(None),
+        }
+    }
+
     /// optional input-capture backend override
     pub fn capture_backend(&self) -> Option<CaptureBackend> {
         self.args
@@ -386,12 +730,66 @@ impl Config {
             .unwrap_or(DEFAULT_PORT)
     }
 
-    /// list of configured clients
-    pub fn clients(&self) -> Vec<ConfigClient> {
+    /// the profile to start with
+    pub fn profile(&self) -> String {
+        self.args
+            .profile
+            .clone()
+            .or(self.config_toml.as_ref().and_then(|c| c.profile.clone()))
+            .unwrap_or(DEFAULT_PROFILE.to_owned())
+    }
+
+    /// names of all profiles, starting with the default profile
+    pub fn profiles(&self) -> Vec<String> {
+        let mut profiles = self
+            .profile_tables()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        profiles.sort();
+        profiles.insert(0, DEFAULT_PROFILE.to_owned());
+        profiles
+    }
+
+    pub fn has_profile(&self, profile: &str) -> bool {
+        profile == DEFAULT_PROFILE || self.profile_tables().any(|(name, _)| name == profile)
+    }
+
+    /// the profile configured for `network` (gateway mac address or SSID)
+    pub fn network_profile(&self, network: &str) -> Option<String> {
+        let mut profiles = self
+            .profile_tables()
+            .filter(|(_, p)| {
+                p.networks
+                    .iter()
+                    .flatten()
+                    .any(|n| n.eq_ignore_ascii_case(network))
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        profiles.sort();
+        profiles.into_iter().next()
+    }
+
+    /// profiles other than the default profile
+    fn profile_tables(&self) -> impl Iterator<Item = (&String, &TomlProfile)> {
         self.config_toml
             .as_ref()
-            .map(|c| c.clients.clone())
-            .unwrap_or_default()
+            .and_then(|c| c.profiles.as_ref())
+            .into_iter()
+            .flatten()
+            .filter(|(name, _)| *name != DEFAULT_PROFILE)
+    }
+
+    /// list of clients configured in `profile`
+    pub fn clients(&self, profile: &str) -> Vec<ConfigClient> {
+        let clients = match profile {
+            DEFAULT_PROFILE => self.config_toml.as_ref().and_then(|c| c.clients.clone()),
+            profile => self
+                .profile_tables()
+                .find(|(name, _)| *name == profile)
+                .and_then(|(_, p)| p.clients.clone()),
+        };
+        clients
             .into_iter()
             .flatten()
             .map(From::<TomlClient>::from)
@@ -411,7 +809,8 @@ impl Config {
     pub fn enable_screen_share(&self) -> bool {
         self.config_toml
             .as_ref()
-            .and_then(|c| c.enable_screen_share)
+            .and_then(|c| c.screen_share.as_ref())
+            .and_then(|s| s.enabled)
             .unwrap_or(self.args.enable_screen_share)
     }
 
@@ -420,7 +819,8 @@ impl Config {
     pub fn screen_share_fps(&self) -> u32 {
         self.config_toml
             .as_ref()
-            .and_then(|c| c.screen_share_fps)
+            .and_then(|c| c.screen_share.as_ref())
+            .and_then(|s| s.fps)
             .unwrap_or(self.args.screen_share_fps)
     }
 
@@ -429,11 +829,17 @@ impl Config {
     pub fn screen_share_quality(&self) -> u8 {
         self.config_toml
             .as_ref()
-            .and_then(|c| c.screen_share_quality)
+            .and_then(|c| c.screen_share.as_ref())
+            .and_then(|s| s.quality)
             .unwrap_or(self.args.screen_share_quality)
     }
 }
 
+fn first_line(mut s: String) -> String {
+    s.truncate(s.lines().next().map(str::len).unwrap_or(0));
+    s
+}
+
 impl Clone for Config {
     fn clone(&self) -> Self {
         Self {
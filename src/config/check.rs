@@ -0,0 +1,607 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+use toml_edit::{DocumentMut, ImDocument, Item, TableLike, Value};
+
+use super::{
+    CaptureBackend, Config, ConfigError, ConfigToml, DEFAULT_PROFILE, EmulationBackend, KeyStorage,
+    Layered, TomlClient, TomlManagement, TomlProfile, TomlScreenShare, migrate, schema, writer,
+};
+
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Clone, Debug, Eq, PartialEq)]
+enum ConfigCommand {
+    /// validate the config file
+    Check,
+    /// upgrade the config file to the current version,
+    /// keeping a copy of the previous one
+    Migrate,
+    /// print the JSON schema of the config file
+    Schema {
+        /// output file (defaults to stdout)
+        path: Option<PathBuf>,
+    },
+}
+
+/// all capture backends, including those not compiled in
+pub(super) const CAPTURE_BACKENDS: &[&str] = &[
+    "input-capture-portal",
+    "layer-shell",
+    "x11",
+    "windows",
+    "macos",
+    "dummy",
+];
+
+/// all emulation backends, including those not compiled in
+pub(super) const EMULATION_BACKENDS: &[&str] = &[
+    "wlroots", "libei", "xdp", "x11", "windows", "macos", "dummy",
+];
+
+/// all key storage options, including those not compiled in
+pub(super) const KEY_STORAGES: &[&str] = &["plain", "passphrase", "secret-service"];
+
+pub fn run(config: Config, args: ConfigArgs) -> Result<(), ConfigError> {
+    match args.command {
+        ConfigCommand::Check => check(&config.config_files()),
+        ConfigCommand::Migrate => migrate_file(config.config_path()),
+        ConfigCommand::Schema { path } => {
+            let schema = serde_json::to_string_pretty(&schema::schema()).expect("valid json");
+            match path {
+                Some(path) => fs::write(path, schema + "\n")?,
+                None => println!("{schema}"),
+            }
+            Ok(())
+        }
+    }
+}
+
+/// upgrade the config file at `path` on disk
+fn migrate_file(path: &Path) -> Result<(), ConfigError> {
+    let source = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            println!("{}: no config file", path.display());
+            return Ok(());
+        }
+        r => r?,
+    };
+    let mut doc = source.parse::<DocumentMut>()?;
+    let current = migrate::VERSION;
+    match migrate::migrate(&mut doc)? {
+        None => println!("{} is up to date (version {current})", path.display()),
+        Some(version) => {
+            let backup = migrate::backup(path, version)?;
+            writer::write_atomic(path, doc.to_string().as_bytes())?;
+            println!(
+                "upgraded {} from version {version} to {current}",
+                path.display()
+            );
+            println!("previous file saved to {}", backup.display());
+        }
+    }
+    Ok(())
+}
+
+/// check each of the config `files` and show where the effective values come from
+fn check(files: &[PathBuf]) -> Result<(), ConfigError> {
+    let (mut errors, mut warnings) = (0, 0);
+    let mut found = false;
+    for path in files {
+        let source = match fs::read_to_string(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            r => r?,
+        };
+        found = true;
+        let diagnostics = diagnose(&source);
+        for diagnostic in &diagnostics {
+            println!("{}", diagnostic.display(path, &source));
+        }
+        let file_errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        errors += file_errors;
+        warnings += diagnostics.len() - file_errors;
+    }
+    if !found {
+        println!("no config file, using defaults");
+        return Ok(());
+    }
+    if errors > 0 {
+        return Err(ConfigError::Check { errors, warnings });
+    }
+
+    if let Some(layered) = Layered::load(files)? {
+        if let Some(diagnostic) = check_profile_selection(&layered)? {
+            let origin = layered.origin("profile").unwrap_or(Path::new(""));
+            println!("{}", diagnostic.display(origin, ""));
+            warnings += 1;
+        }
+        println!("effective configuration:");
+        for (key, value, origin) in layered.values() {
+            println!("{key} = {value}  # {}", origin.display());
+        }
+    }
+    match warnings {
+        0 => println!("ok"),
+        n => println!("ok ({n} warning(s))"),
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+struct Diagnostic {
+    severity: Severity,
+    /// byte range in the config file
+    span: Option<Range<usize>>,
+    message: String,
+}
+
+impl Diagnostic {
+    fn error(span: Option<Range<usize>>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn warning(span: Option<Range<usize>>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn display(&self, path: &Path, source: &str) -> String {
+        let path = path.display();
+        let (severity, message) = (self.severity, &self.message);
+        match &self.span {
+            Some(span) => {
+                let (line, column) = line_column(source, span.start);
+                format!("{path}:{line}:{column}: {severity}: {message}")
+            }
+            None => format!("{path}: {severity}: {message}"),
+        }
+    }
+}
+
+/// 1-based line and column of the byte `offset` in `source`
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+fn diagnose(source: &str) -> Vec<Diagnostic> {
+    let doc = match ImDocument::parse(source) {
+        Ok(doc) => doc,
+        Err(e) => return vec![Diagnostic::error(e.span(), one_line(e.message()))],
+    };
+    let root = doc.as_table();
+    let version = match migrate::version(root) {
+        Ok(version) => version,
+        Err(e) => {
+            let span = root.get("version").and_then(Item::span);
+            return vec![Diagnostic::error(span, e.to_string())];
+        }
+    };
+    let mut diagnostics = vec![];
+    if version < migrate::VERSION {
+        diagnostics.push(Diagnostic::warning(
+            Some(0..0),
+            format!(
+                "config version {version} is outdated and upgraded to version {} when loaded, \
+                 run `lan-mouse config migrate` to update the file",
+                migrate::VERSION
+            ),
+        ));
+    }
+    /* the file is checked as it is written, understanding the keys of its version */
+    let (outdated_keys, outdated_client_keys) = migrate::outdated_keys(version);
+    let known = Known {
+        root: with_outdated(known_keys::<ConfigToml>(), &outdated_keys),
+        profile: known_keys::<TomlProfile>(),
+        client: with_outdated(known_keys::<TomlClient>(), &outdated_client_keys),
+    };
+
+    check_unknown_keys(root, &known.root, "", &mut diagnostics);
+    check_choice::<CaptureBackend>(
+        root,
+        "capture_backend",
+        "capture backend",
+        CAPTURE_BACKENDS,
+        &mut diagnostics,
+    );
+    check_choice::<EmulationBackend>(
+        root,
+        "emulation_backend",
+        "emulation backend",
+        EMULATION_BACKENDS,
+        &mut diagnostics,
+    );
+    check_choice::<KeyStorage>(
+        root,
+        "key_storage",
+        "key storage",
+        KEY_STORAGES,
+        &mut diagnostics,
+    );
+    check_fingerprints(root, &mut diagnostics);
+    check_management(root, &mut diagnostics);
+    check_screen_share(root, &mut diagnostics);
+    check_clients(root, None, &known.client, &mut diagnostics);
+    check_profiles(root, &known, &mut diagnostics);
+
+    /* everything else (types, addresses, ...) is validated by deserializing,
+     * which would only repeat errors that were already reported */
+    if diagnostics.iter().all(|d| d.severity != Severity::Error) {
+        if let Err(e) = toml::from_str::<ConfigToml>(source) {
+            diagnostics.push(Diagnostic::error(e.span(), one_line(e.message())));
+        } else if version < migrate::VERSION {
+            /* values moved when upgrading, their location in the file is unknown */
+            match migrate::migrate_str(source).map(|s| toml::from_str::<ConfigToml>(&s)) {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => diagnostics.push(Diagnostic::error(
+                    None,
+                    format!("after upgrading: {}", one_line(e.message())),
+                )),
+                Err(e) => diagnostics.push(Diagnostic::error(None, e.to_string())),
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.span.as_ref().map(|s| s.start));
+    diagnostics
+}
+
+/// the keys understood in each kind of table
+struct Known {
+    root: Vec<String>,
+    profile: Vec<String>,
+    client: Vec<String>,
+}
+
+fn with_outdated(mut known: Vec<String>, outdated: &[&str]) -> Vec<String> {
+    known.extend(outdated.iter().map(|k| k.to_string()));
+    known
+}
+
+/// parser messages may span multiple lines
+fn one_line(message: &str) -> String {
+    message.trim().lines().collect::<Vec<_>>().join(", ")
+}
+
+/// the keys understood by `T`
+fn known_keys<T: Default + Serialize>() -> Vec<String> {
+    match serde_json::to_value(T::default()) {
+        Ok(serde_json::Value::Object(map)) => map.into_iter().map(|(k, _)| k).collect(),
+        _ => vec![],
+    }
+}
+
+fn check_unknown_keys(
+    table: &dyn TableLike,
+    known: &[String],
+    context: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (key, _) in table.iter() {
+        if !known.iter().any(|k| k == key) {
+            diagnostics.push(Diagnostic::warning(
+                key_span(table, key),
+                format!("unknown key `{key}`{context} is ignored"),
+            ));
+        }
+    }
+}
+
+/// check a value that must be one of `all`, of which only `T`'s variants are available
+fn check_choice<T: ValueEnum + Serialize>(
+    table: &dyn TableLike,
+    key: &str,
+    what: &str,
+    all: &[&str],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let Some(item) = table.get(key) else {
+        return;
+    };
+    let Some(value) = item.as_str() else {
+        return; /* reported when deserializing */
+    };
+    let available = T::value_variants()
+        .iter()
+        .filter_map(|v| serde_json::to_value(v).ok())
+        .filter_map(|v| v.as_str().map(ToOwned::to_owned))
+        .collect::<Vec<_>>();
+    if available.iter().any(|v| v == value) {
+        return;
+    }
+    let message = if all.contains(&value) {
+        format!("{what} `{value}` is not supported by this build")
+    } else {
+        format!("unknown {what} `{value}`")
+    };
+    diagnostics.push(Diagnostic::error(
+        item.span(),
+        format!("{message} (available: {})", available.join(", ")),
+    ));
+}
+
+/// whether `fingerprint` is formatted like the output of `crypto::generate_fingerprint`
+fn is_valid_fingerprint(fingerprint: &str) -> bool {
+    let bytes = fingerprint.split(':').collect::<Vec<_>>();
+    bytes.len() == 32
+        && bytes.iter().all(|b| {
+            b.len() == 2
+                && b.chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        })
+}
+
+const FINGERPRINT_FORMAT: &str = "expected 32 colon separated lowercase hex bytes";
+
+fn check_fingerprints(root: &dyn TableLike, diagnostics: &mut Vec<Diagnostic>) {
+    check_fingerprint_keys(root, "authorized_fingerprints", diagnostics);
+    if let Some(blocked) = root.get("blocked_fingerprints").and_then(Item::as_array) {
+        for value in blocked.iter() {
+            if let Some(fingerprint) = value.as_str() {
+                if !is_valid_fingerprint(fingerprint) {
+                    diagnostics.push(Diagnostic::error(
+                        value.span(),
+                        format!("invalid fingerprint `{fingerprint}`, {FINGERPRINT_FORMAT}"),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// check the keys of the fingerprint table `key` in `table`
+fn check_fingerprint_keys(table: &dyn TableLike, key: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(fingerprints) = table.get(key).and_then(Item::as_table_like) else {
+        return;
+    };
+    for (fingerprint, _) in fingerprints.iter() {
+        if !is_valid_fingerprint(fingerprint) {
+            diagnostics.push(Diagnostic::error(
+                key_span(fingerprints, fingerprint),
+                format!("invalid fingerprint `{fingerprint}`, {FINGERPRINT_FORMAT}"),
+            ));
+        }
+    }
+}
+
+fn check_management(root: &dyn TableLike, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(management) = root.get("management").and_then(Item::as_table_like) else {
+        return; /* reported when deserializing */
+    };
+    check_unknown_keys(
+        management,
+        &known_keys::<TomlManagement>(),
+        " in `management`",
+        diagnostics,
+    );
+    check_fingerprint_keys(management, "admin_fingerprints", diagnostics);
+    if management.contains_key("address")
+        && management
+            .get("admin_fingerprints")
+            .and_then(Item::as_table_like)
+            .is_none_or(|admins| admins.is_empty())
+    {
+        diagnostics.push(Diagnostic::warning(
+            key_span(management, "address"),
+            "no `admin_fingerprints` in `management`, all connections are rejected",
+        ));
+    }
+}
+
+fn check_screen_share(root: &dyn TableLike, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(screen_share) = root.get("screen_share").and_then(Item::as_table_like) else {
+        return; /* reported when deserializing */
+    };
+    check_unknown_keys(
+        screen_share,
+        &known_keys::<TomlScreenShare>(),
+        " in `screen_share`",
+        diagnostics,
+    );
+    if cfg!(not(feature = "screen_share")) {
+        diagnostics.push(Diagnostic::warning(
+            key_span(root, "screen_share"),
+            "screen sharing is not supported by this build, `screen_share` is ignored",
+        ));
+    }
+}
+
+fn check_profiles(root: &dyn TableLike, known: &Known, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(profiles) = root.get("profiles").and_then(Item::as_table_like) else {
+        return; /* reported when deserializing */
+    };
+    for (name, item) in profiles.iter() {
+        if name == DEFAULT_PROFILE {
+            diagnostics.push(Diagnostic::warning(
+                key_span(profiles, name),
+                format!("profile `{DEFAULT_PROFILE}` is made up of the top-level `clients`, this table is ignored"),
+            ));
+            continue;
+        }
+        if let Some(profile) = item.as_table_like() {
+            check_unknown_keys(
+                profile,
+                &known.profile,
+                &format!(" in profile `{name}`"),
+                diagnostics,
+            );
+            check_clients(profile, Some(name), &known.client, diagnostics);
+        }
+    }
+}
+
+/// the `profile` selected on startup must exist, which is only known after merging
+fn check_profile_selection(layered: &Layered) -> Result<Option<Diagnostic>, ConfigError> {
+    let config = layered.config()?;
+    let Some(profile) = config.profile else {
+        return Ok(None);
+    };
+    let exists =
+        profile == DEFAULT_PROFILE || config.profiles.is_some_and(|p| p.contains_key(&profile));
+    Ok((!exists).then(|| {
+        Diagnostic::warning(
+            None,
+            format!("profile `{profile}` does not exist, `{DEFAULT_PROFILE}` is used instead"),
+        )
+    }))
+}
+
+/// check the `clients` of `table`, which belongs to `profile` unless it is the root table
+fn check_clients(
+    table: &dyn TableLike,
+    profile: Option<&str>,
+    known: &[String],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let clients: Vec<(&dyn TableLike, Option<Range<usize>>)> = match table.get("clients") {
+        Some(Item::ArrayOfTables(tables)) => tables
+            .iter()
+            .map(|t| (t as &dyn TableLike, t.span()))
+            .collect(),
+        Some(Item::Value(Value::Array(array))) => array
+            .iter()
+            .filter_map(|v| v.as_inline_table().map(|t| (t as &dyn TableLike, v.span())))
+            .collect(),
+        _ => return,
+    };
+
+    let in_profile = profile
+        .map(|p| format!(" of profile `{p}`"))
+        .unwrap_or_default();
+    let mut active: HashMap<&str, usize> = HashMap::new();
+    for (i, (client, span)) in clients.iter().enumerate() {
+        let n = i + 1;
+        let context = format!(" in client #{n}{in_profile}");
+        check_unknown_keys(*client, known, &context, diagnostics);
+
+        let activated = client
+            .get("activate_on_startup")
+            .and_then(Item::as_bool)
+            .unwrap_or(false);
+        if !activated {
+            continue;
+        }
+        let position = client
+            .get("position")
+            .and_then(Item::as_str)
+            .unwrap_or("left");
+        if let Some(other) = active.insert(position, n) {
+            diagnostics.push(Diagnostic::warning(
+                span.clone(),
+                format!(
+                    "clients #{other} and #{n}{in_profile} are both activated on startup \
+                     at position `{position}`, only one of them will be active"
+                ),
+            ));
+        }
+    }
+}
+
+fn key_span(table: &dyn TableLike, key: &str) -> Option<Range<usize>> {
+    table.key(key).and_then(|k| k.span())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Severity, diagnose, line_column};
+
+    /// (line, column, severity) of each diagnostic
+    fn positions(source: &str) -> Vec<(usize, usize, Severity)> {
+        diagnose(source)
+            .iter()
+            .map(|d| {
+                let span = d.span.clone().expect("span");
+                let (line, column) = line_column(source, span.start);
+                (line, column, d.severity)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn outdated_file_keeps_locations() {
+        let source = "enable_screen_share = true\n\
+                      colour = 1\n\
+                      \n\
+                      [[clients]]\n\
+                      host_name = \"a\"\n\
+                      position = \"left\"\n\
+                      speed = 2\n";
+        /* the legacy keys are understood, the unknown ones reported where they are */
+        assert_eq!(
+            positions(source),
+            [
+                (1, 1, Severity::Warning),
+                (2, 1, Severity::Warning),
+                (7, 1, Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn outdated_file_type_error() {
+        let source = "port = \"x\"\n[[clients]]\nhost_name = \"a\"\n";
+        assert_eq!(
+            positions(source),
+            [(1, 1, Severity::Warning), (1, 8, Severity::Error)]
+        );
+    }
+
+    #[test]
+    fn outdated_value_error_after_upgrading() {
+        let source = "enable_screen_share = 1\n";
+        let diagnostics = diagnose(source);
+        let error = diagnostics
+            .iter()
+            .find(|d| d.severity == Severity::Error)
+            .expect("error");
+        assert!(error.span.is_none());
+        assert!(
+            error.message.starts_with("after upgrading:"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn current_file() {
+        let source = "version = 2\nport = 4242\n[[clients]]\nhostname = \"a\"\nhost_name = \"b\"\n";
+        assert_eq!(positions(source), [(5, 1, Severity::Warning)]);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{CONFIG_FILE_NAME, ConfigError, ConfigToml, migrate};
+
+/// directory next to the system wide config file containing drop-ins
+const DROP_IN_DIR: &str = "config.d";
+
+/// directory of the system wide config file
+fn system_dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let dir = Some(PathBuf::from("/etc/lan-mouse"));
+
+    #[cfg(not(unix))]
+    let dir = std::env::var("PROGRAMDATA")
+        .ok()
+        .map(|d| PathBuf::from(d).join("lan-mouse"));
+    dir
+}
+
+/// directory containing system wide drop-ins
+pub(crate) fn drop_in_dir() -> Option<PathBuf> {
+    system_dir().map(|d| d.join(DROP_IN_DIR))
+}
+
+/// config files in the order they are applied, later files take precedence:
+/// the system wide config file, its drop-ins (sorted by name) and the user config file
+pub(crate) fn files(user: &Path) -> Vec<PathBuf> {
+    let mut files = system_files();
+    files.push(user.to_owned());
+    files
+}
+
+/// the system wide config file and its drop-ins
+pub(crate) fn system_files() -> Vec<PathBuf> {
+    system_dir()
+        .map(|d| system_files_in(&d))
+        .unwrap_or_default()
+}
+
+/// the config file in the system wide config directory `dir` and its drop-ins
+fn system_files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![dir.join(CONFIG_FILE_NAME)];
+    files.extend(drop_ins(&dir.join(DROP_IN_DIR)));
+    files
+}
+
+fn drop_ins(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut files = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// configuration merged from several config files
+#[derive(Default)]
+pub(crate) struct Layered {
+    table: toml::Table,
+    /// the file each value was taken from, by dotted key
+    origins: BTreeMap<String, PathBuf>,
+}
+
+impl Layered {
+    /// read and merge `files`, skipping those that do not exist.
+    /// Returns `None` if none of them exist.
+    pub(crate) fn load(files: &[PathBuf]) -> Result<Option<Self>, ConfigError> {
+        let mut layered: Option<Self> = None;
+        for path in files {
+            let table = match read(path) {
+                Ok(table) => table,
+                Err(ConfigError::Io(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(ConfigError::File {
+                        path: path.clone(),
+                        source: Box::new(e),
+                    });
+                }
+            };
+            let layered = layered.get_or_insert_with(Default::default);
+            merge(&mut layered.table, table, "", path, &mut layered.origins);
+        }
+        Ok(layered)
+    }
+
+    pub(crate) fn config(&self) -> Result<ConfigToml, ConfigError> {
+        Ok(self.table.clone().try_into()?)
+    }
+
+    /// the file the value of the (dotted) `key` was taken from
+    pub(crate) fn origin(&self, key: &str) -> Option<&Path> {
+        self.origins.get(key).map(PathBuf::as_path)
+    }
+
+    /// all effective values with the file they were taken from
+    pub(crate) fn values(&self) -> Vec<(String, &toml::Value, &Path)> {
+        let mut values = vec![];
+        self.collect("", &self.table, &mut values);
+        values
+    }
+
+    fn collect<'a>(
+        &'a self,
+        prefix: &str,
+        table: &'a toml::Table,
+        values: &mut Vec<(String, &'a toml::Value, &'a Path)>,
+    ) {
+        for (key, value) in table {
+            let key = dotted(prefix, key);
+            match value {
+                toml::Value::Table(table) => self.collect(&key, table, values),
+                value => {
+                    if let Some(origin) = self.origins.get(&key) {
+                        values.push((key, value, origin));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<toml::Table, ConfigError> {
+    let source = fs::read_to_string(path)?;
+    let migrated = migrate::migrate_str(&source)?;
+    /* validate every file on its own and as it is written,
+     * so errors point to the right location */
+    toml::from_str::<ConfigToml>(&source)?;
+    let table: toml::Table = toml::from_str(&migrated)?;
+    /* values only understood after upgrading the file (without a location) */
+    if migrated != source {
+        table.clone().try_into::<ConfigToml>()?;
+    }
+    Ok(table)
+}
+
+/// merge `layer` into `base`: tables are merged key by key,
+/// any other value (including arrays) replaces the previous one
+fn merge(
+    base: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    source: &Path,
+    origins: &mut BTreeMap<String, PathBuf>,
+) {
+    for (key, value) in layer {
+        let path = dotted(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(entry)), toml::Value::Table(table)) => {
+                merge(entry, table, &path, source, origins);
+            }
+            (_, toml::Value::Table(table)) => {
+                origins.remove(&path);
+                let mut entry = toml::Table::new();
+                merge(&mut entry, table, &path, source, origins);
+                base.insert(key, toml::Value::Table(entry));
+            }
+            (_, value) => {
+                let nested = format!("{path}.");
+                origins.retain(|k, _| !k.starts_with(&nested));
+                origins.insert(path, source.to_owned());
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// append `key` to the dotted key `prefix`, quoting it if necessary
+fn dotted(prefix: &str, key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let key = match bare {
+        true => key.to_owned(),
+        false => format!("{key:?}"),
+    };
+    match prefix {
+        "" => key,
+        prefix => format!("{prefix}.{key}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Layered, read, system_files_in};
+    use crate::config::{ConfigError, TempDir};
+
+    /// `/etc/lan-mouse`, its drop-ins and the user config file
+    fn load(dir: &TempDir) -> Layered {
+        let mut files = system_files_in(&dir.path().join("etc"));
+        files.push(dir.path().join("user/config.toml"));
+        Layered::load(&files).unwrap().expect("config files")
+    }
+
+    #[test]
+    fn precedence() {
+        let dir = TempDir::new("layers-precedence");
+        let etc = dir.write("etc/config.toml", "port = 1\nwake_window_secs = 10\n");
+        dir.write("etc/config.d/20-b.toml", "port = 3\n");
+        let drop_in = dir.write(
+            "etc/config.d/10-a.toml",
+            "port = 2\npersist_changes = false\n",
+        );
+        dir.write("etc/config.d/30-c.toml.disabled", "port = 4\n");
+        let user = dir.write("user/config.toml", "max_handshakes_per_minute = 5\n");
+
+        let layered = load(&dir);
+        let config = layered.config().unwrap();
+        /* drop-ins are applied in the order of their names */
+        assert_eq!(config.port, Some(3));
+        assert_eq!(config.wake_window_secs, Some(10));
+        assert_eq!(config.persist_changes, Some(false));
+        assert_eq!(config.max_handshakes_per_minute, Some(5));
+        assert_eq!(layered.origin("wake_window_secs"), Some(etc.as_path()));
+        assert_eq!(layered.origin("persist_changes"), Some(drop_in.as_path()));
+        assert_eq!(
+            layered.origin("max_handshakes_per_minute"),
+            Some(user.as_path())
+        );
+
+        dir.write("user/config.toml", "port = 5\n");
+        let layered = load(&dir);
+        assert_eq!(layered.config().unwrap().port, Some(5));
+        assert_eq!(layered.origin("port"), Some(user.as_path()));
+    }
+
+    #[test]
+    fn tables_merge_and_arrays_replace() {
+        let dir = TempDir::new("layers-merge");
+        let etc = dir.write(
+            "etc/config.toml",
+            "blocked_ips = [\"10.0.0.1\", \"10.0.0.2\"]\n\
+             [management]\n\
+             address = \"127.0.0.1:4243\"\n\
+             [authorized_fingerprints]\n\
+             aa = \"system\"\n",
+        );
+        let user = dir.write(
+            "user/config.toml",
+            "blocked_ips = [\"10.0.0.3\"]\n\
+             [management.admin_fingerprints]\n\
+             bb = \"admin\"\n\
+             [authorized_fingerprints]\n\
+             cc = \"user\"\n",
+        );
+
+        let layered = load(&dir);
+        let config = layered.config().unwrap();
+        let blocked = config.blocked_ips.unwrap();
+        assert_eq!(blocked.len(), 1);
+        assert!(blocked.contains(&"10.0.0.3".parse().unwrap()));
+        let management = config.management.unwrap();
+        assert_eq!(management.address, Some("127.0.0.1:4243".parse().unwrap()));
+        assert!(management.admin_fingerprints.unwrap().contains_key("bb"));
+        let authorized = config.authorized_fingerprints.unwrap();
+        assert_eq!(authorized.len(), 2);
+
+        assert_eq!(layered.origin("blocked_ips"), Some(user.as_path()));
+        assert_eq!(layered.origin("management.address"), Some(etc.as_path()));
+        assert_eq!(
+            layered.origin("management.admin_fingerprints.bb"),
+            Some(user.as_path())
+        );
+        assert_eq!(
+            layered.origin("authorized_fingerprints.aa"),
+            Some(etc.as_path())
+        );
+        assert_eq!(
+            layered.origin("authorized_fingerprints.cc"),
+            Some(user.as_path())
+        );
+        let values = layered.values();
+        assert!(
+            values
+                .iter()
+                .any(|(k, _, o)| k == "blocked_ips" && *o == user)
+        );
+    }
+
+    #[test]
+    fn missing_files() {
+        let dir = TempDir::new("layers-missing");
+        let files = system_files_in(&dir.path().join("etc"));
+        assert!(Layered::load(&files).unwrap().is_none());
+        let user = dir.write("user/config.toml", "port = 1\n");
+        let layered = load(&dir);
+        assert_eq!(layered.origin("port"), Some(Path::new(&user)));
+    }
+
+    #[test]
+    fn error_location_in_outdated_file() {
+        let dir = TempDir::new("layers-outdated");
+        /* without a version, the file is upgraded when read */
+        let path = dir.write(
+            "config.toml",
+            "port = \"x\"\nenable_screen_share = true\n\n[[clients]]\nhost_name = \"a\"\n",
+        );
+        let e = read(&path).unwrap_err();
+        assert!(matches!(e, ConfigError::Toml(_)));
+        assert!(e.to_string().contains("line 1, column 8"), "{e}");
+
+        let path = dir.write(
+            "config.toml",
+            "enable_screen_share = true\n\n[[clients]]\nhost_name = \"a\"\nport = -1\n",
+        );
+        let e = read(&path).unwrap_err();
+        assert!(e.to_string().contains("line 5, column 8"), "{e}");
+
+        let path = dir.write(
+            "config.toml",
+            "enable_screen_share = true\n[[clients]]\nhost_name = \"a\"\n",
+        );
+        let table = read(&path).unwrap();
+        assert_eq!(table["version"].as_integer(), Some(2));
+        assert_eq!(table["screen_share"]["enabled"].as_bool(), Some(true));
+        assert_eq!(table["clients"][0]["hostname"].as_str(), Some("a"));
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+use super::ConfigError;
+
+/// each migration upgrades the layout of a config file by one version, starting at version 1
+const MIGRATIONS: &[fn(&mut Table)] = &[v1_to_v2];
+
+/// the version of the config file layout understood by this build
+pub(crate) const VERSION: i64 = MIGRATIONS.len() as i64 + 1;
+
+/// the `version` of a config file, files without one predate versioning
+pub(crate) fn version(doc: &dyn TableLike) -> Result<i64, ConfigError> {
+    let Some(item) = doc.get("version") else {
+        return Ok(1);
+    };
+    match item.as_integer() {
+        Some(version) if (1..=VERSION).contains(&version) => Ok(version),
+        Some(version) if version > VERSION => Err(ConfigError::UnsupportedVersion(version)),
+        _ => Err(ConfigError::InvalidVersion(
+            item.to_string().trim().to_owned(),
+        )),
+    }
+}
+
+/// upgrade `doc` to the current version.
+/// Returns the previous version if anything was migrated.
+pub(crate) fn migrate(doc: &mut DocumentMut) -> Result<Option<i64>, ConfigError> {
+    let version = version(doc.as_table())?;
+    if version == VERSION {
+        return Ok(None);
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(doc.as_table_mut());
+    }
+    /* the version goes first, below comments at the top of the file */
+    let first = doc
+        .iter()
+        .find(|(_, i)| i.is_value())
+        .map(|(k, _)| k.to_owned());
+    doc.insert("version", toml_edit::value(VERSION));
+    doc.sort_values_by(|a, _, b, _| (b.get() == "version").cmp(&(a.get() == "version")));
+    if let Some(mut key) = first.as_deref().and_then(|k| doc.key_mut(k)) {
+        let prefix = key.leaf_decor().prefix().cloned();
+        key.leaf_decor_mut().set_prefix("");
+        if let (Some(prefix), Some(mut version)) = (prefix, doc.key_mut("version")) {
+            version.leaf_decor_mut().set_prefix(prefix);
+        }
+    }
+    Ok(Some(version))
+}
+
+/// the contents of a config file upgraded to the current version
+pub(crate) fn migrate_str(source: &str) -> Result<String, ConfigError> {
+    let mut doc = source.parse::<DocumentMut>()?;
+    match migrate(&mut doc)? {
+        Some(_) => Ok(doc.to_string()),
+        None => Ok(source.to_owned()),
+    }
+}
+
+/// copy the config file at `path` that is about to be upgraded from `version`,
+/// keeping an existing copy of that version
+pub(crate) fn backup(path: &Path, version: i64) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{version}.bak"));
+    let backup = PathBuf::from(backup);
+    if !backup.exists() {
+        fs::copy(path, &backup)?;
+    }
+    Ok(backup)
+}
+
+/// keys of the layout of `version` that are renamed or moved when upgrading it,
+/// as (keys of the top-level table, keys of clients)
+pub(crate) fn outdated_keys(version: i64) -> (Vec<&'static str>, Vec<&'static str>) {
+    let (mut root, mut client) = (vec![], vec![]);
+    if version < 2 {
+        root.extend(SCREEN_SHARE.iter().map(|(old, _)| *old));
+        client.push(HOST_NAME);
+    }
+    (root, client)
+}
+
+/// version 1 name of the `hostname` of clients
+const HOST_NAME: &str = "host_name";
+
+/// version 1 top-level screen share settings and their keys in `[screen_share]`
+const SCREEN_SHARE: [(&str, &str); 3] = [
+    ("enable_screen_share", "enabled"),
+    ("screen_share_fps", "fps"),
+    ("screen_share_quality", "quality"),
+];
+
+/// `host_name` of clients is now `hostname`,
+/// screen share settings moved to a `[screen_share]` table
+fn v1_to_v2(doc: &mut Table) {
+    for_each_client(doc, |client| {
+        /* keep comments above the key */
+        let decor = client.key(HOST_NAME).map(|k| k.leaf_decor().clone());
+        if let Some(host_name) = client.remove(HOST_NAME) {
+            if !client.contains_key("hostname") {
+                client.insert("hostname", host_name);
+                if let (Some(decor), Some(mut key)) = (decor, client.key_mut("hostname")) {
+                    *key.leaf_decor_mut() = decor;
+                }
+            }
+        }
+    });
+
+    let moved = SCREEN_SHARE
+        .iter()
+        .filter_map(|(old, new)| doc.remove(old).map(|v| (*new, v)))
+        .collect::<Vec<_>>();
+    if moved.is_empty() {
+        return;
+    }
+    let table = doc
+        .entry("screen_share")
+        .or_insert(Item::Table(Table::new()));
+    if let Some(table) = table.as_table_like_mut() {
+        for (key, value) in moved {
+            table.insert(key, value);
+        }
+    }
+}
+
+/// call `f` for the clients of the top-level table and of every profile
+fn for_each_client(doc: &mut Table, mut f: impl FnMut(&mut dyn TableLike)) {
+    clients(doc, &mut f);
+    if let Some(profiles) = doc.get_mut("profiles").and_then(Item::as_table_like_mut) {
+        for (_, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_table_like_mut() {
+                clients(profile, &mut f);
+            }
+        }
+    }
+}
+
+fn clients(table: &mut dyn TableLike, f: &mut impl FnMut(&mut dyn TableLike)) {
+    match table.get_mut("clients") {
+        Some(Item::ArrayOfTables(tables)) => tables.iter_mut().for_each(|t| f(t)),
+        Some(Item::Value(Value::Array(array))) => array
+            .iter_mut()
+            .filter_map(Value::as_inline_table_mut)
+            .for_each(|t| f(t)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{VERSION, backup, migrate_str};
+    use crate::config::{ConfigError, TempDir};
+
+    const V1: &str = "# lan-mouse\n\
+                      port = 4242\n\
+                      enable_screen_share = true\n\
+                      screen_share_fps = 30\n\
+                      \n\
+                      [[clients]]\n\
+                      # the laptop\n\
+                      host_name = \"laptop\"\n\
+                      position = \"left\"\n\
+                      \n\
+                      [profiles.work]\n\
+                      clients = [{ host_name = \"desktop\", position = \"right\" }]\n";
+
+    const V2: &str = "# lan-mouse\n\
+                      version = 2\n\
+                      port = 4242\n\
+                      \n\
+                      [[clients]]\n\
+                      position = \"left\"\n\
+                      # the laptop\n\
+                      hostname = \"laptop\"\n\
+                      \n\
+                      [profiles.work]\n\
+                      clients = [{ position = \"right\" , hostname = \"desktop\"}]\n\
+                      \n\
+                      [screen_share]\n\
+                      enabled = true\n\
+                      fps = 30\n";
+
+    #[test]
+    fn v1_to_v2() {
+        assert_eq!(migrate_str(V1).unwrap(), V2);
+    }
+
+    #[test]
+    fn migrating_twice() {
+        let once = migrate_str(V1).unwrap();
+        assert_eq!(migrate_str(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn hostname_is_kept() {
+        let source = "[[clients]]\nhostname = \"a\"\nhost_name = \"b\"\n";
+        let migrated = migrate_str(source).unwrap();
+        assert_eq!(migrated, "version = 2\n[[clients]]\nhostname = \"a\"\n");
+    }
+
+    #[test]
+    fn versions() {
+        let current = format!("version = {VERSION}\nport = 1\n");
+        assert_eq!(migrate_str(&current).unwrap(), current);
+        let newer = format!("version = {}\n", VERSION + 1);
+        assert!(matches!(
+            migrate_str(&newer),
+            Err(ConfigError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+        assert!(matches!(
+            migrate_str("version = \"2\"\n"),
+            Err(ConfigError::InvalidVersion(_))
+        ));
+    }
+
+    #[test]
+    fn backup_is_kept() {
+        let dir = TempDir::new("migrate-backup");
+        let path = dir.write("config.toml", V1);
+        let copy = backup(&path, 1).unwrap();
+        assert_eq!(copy, dir.path().join("config.toml.v1.bak"));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), V1);
+
+        /* the copy of the original version is not replaced */
+        fs::write(&path, "port = 1\n").unwrap();
+        assert_eq!(backup(&path, 1).unwrap(), copy);
+        assert_eq!(fs::read_to_string(&copy).unwrap(), V1);
+    }
+}
@@ -0,0 +1,227 @@
+use input_event::scancode;
+use lan_mouse_ipc::DEFAULT_PORT;
+use serde_json::{Value, json};
+
+use super::{
+    DEFAULT_BAN_DURATION, DEFAULT_MAX_HANDSHAKES_PER_MINUTE, DEFAULT_MAX_REJECTIONS_PER_MINUTE,
+    DEFAULT_PROFILE, DEFAULT_WAKE_WINDOW,
+    check::{CAPTURE_BACKENDS, EMULATION_BACKENDS, KEY_STORAGES},
+    migrate,
+};
+
+/// highest linux key code (`KEY_MAX`)
+const KEY_MAX: u32 = 0x2ff;
+
+/// JSON schema of the config file (current version) for editor completion
+pub(crate) fn schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "lan-mouse configuration",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "version": {
+                "description": "layout version of this file, older files are upgraded automatically",
+                "type": "integer",
+                "minimum": 1,
+                "maximum": migrate::VERSION,
+            },
+            "capture_backend": {
+                "description": "input capture backend",
+                "enum": CAPTURE_BACKENDS,
+            },
+            "emulation_backend": {
+                "description": "input emulation backend",
+                "enum": EMULATION_BACKENDS,
+            },
+            "port": port("listen port"),
+            "release_bind": {
+                "description": "keys releasing the captured mouse and keyboard",
+                "type": "array",
+                "items": { "enum": key_names() },
+            },
+            "cert_path": {
+                "description": "non-default certificate location",
+                "type": "string",
+            },
+            "key_storage": {
+                "description": "how the private key is stored",
+                "enum": KEY_STORAGES,
+                "default": "plain",
+            },
+            "key_passphrase_file": {
+                "description": "file containing the passphrase of the private key",
+                "type": "string",
+            },
+            "clients": clients(),
+            "profile": {
+                "description": format!("layout profile to start with, `{DEFAULT_PROFILE}` consists of the top-level `clients`"),
+                "type": "string",
+                "default": DEFAULT_PROFILE,
+            },
+            "profiles": {
+                "description": "layout profiles with their own set of clients",
+                "type": "object",
+                "additionalProperties": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "clients": clients(),
+                        "networks": {
+                            "description": "networks (gateway mac address or SSID) selecting this profile",
+                            "type": "array",
+                            "items": { "type": "string" },
+                        },
+                    },
+                },
+            },
+            "authorized_fingerprints": {
+                "description": "certificate fingerprints accepted for incoming connections, with a description",
+                "type": "object",
+                "propertyNames": fingerprint(),
+                "additionalProperties": { "type": "string" },
+            },
+            "blocked_fingerprints": {
+                "description": "certificate fingerprints that are never accepted",
+                "type": "array",
+                "items": fingerprint(),
+            },
+            "blocked_ips": {
+                "description": "ip addresses that are never accepted",
+                "type": "array",
+                "items": { "type": "string" },
+            },
+            "allowed_networks": {
+                "description": "only accept connection attempts from these networks (e.g. `192.168.178.0/24`)",
+                "type": "array",
+                "items": { "type": "string" },
+            },
+            "max_handshakes_per_minute": {
+                "description": "handshakes per ip address and minute",
+                "type": "integer",
+                "minimum": 0,
+                "default": DEFAULT_MAX_HANDSHAKES_PER_MINUTE,
+            },
+            "max_rejections_per_minute": {
+                "description": "ban an ip address after this many rejected handshakes within a minute",
+                "type": "integer",
+                "minimum": 0,
+                "default": DEFAULT_MAX_REJECTIONS_PER_MINUTE,
+            },
+            "ban_duration_secs": {
+                "description": "how long an ip address is banned",
+                "type": "integer",
+                "minimum": 0,
+                "default": DEFAULT_BAN_DURATION.as_secs(),
+            },
+            "wake_window_secs": {
+                "description": "how long to keep waking up a sleeping client",
+                "type": "integer",
+                "minimum": 0,
+                "default": DEFAULT_WAKE_WINDOW.as_secs(),
+            },
+            "persist_changes": {
+                "description": "write changes made at runtime back to this file",
+                "type": "boolean",
+                "default": true,
+            },
+            "ipc_read_only_group": {
+                "description": "members of this group may connect to the frontend socket, but only receive events",
+                "type": "string",
+            },
+            "management": {
+                "description": "remote management via WebSocket over TLS, disabled by default",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "address": {
+                        "description": "address to listen on (e.g. `0.0.0.0:4243`), enables the listener",
+                        "type": "string",
+                    },
+                    "admin_fingerprints": {
+                        "description": "certificate fingerprints allowed to manage this device, with a description",
+                        "type": "object",
+                        "propertyNames": fingerprint(),
+                        "additionalProperties": { "type": "string" },
+                    },
+                },
+            },
+            "screen_share": {
+                "description": "screen sharing (only available if compiled with the `screen_share` feature)",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "enabled": { "type": "boolean", "default": false },
+                    "fps": { "type": "integer", "minimum": 1, "default": 30 },
+                    "quality": { "type": "integer", "minimum": 1, "maximum": 100, "default": 80 },
+                },
+            },
+        },
+    })
+}
+
+fn clients() -> Value {
+    json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "hostname": {
+                    "description": "hostname of the client, at least one ip address is required without it",
+                    "type": "string",
+                },
+                "ips": {
+                    "description": "known ip addresses of the client",
+                    "type": "array",
+                    "items": { "type": "string" },
+                },
+                "port": port("port of the client"),
+                "position": {
+                    "description": "side of this device the client is located at",
+                    "enum": ["left", "right", "top", "bottom"],
+                    "default": "left",
+                },
+                "activate_on_startup": {
+                    "description": "activate this client when lan-mouse is started",
+                    "type": "boolean",
+                    "default": false,
+                },
+                "enter_hook": {
+                    "description": "command run when entering the client",
+                    "type": "string",
+                },
+                "mac_address": {
+                    "description": "mac address to wake the client via Wake-on-LAN",
+                    "type": "string",
+                    "pattern": "^[0-9a-fA-F]{2}([:-][0-9a-fA-F]{2}){5}$",
+                },
+            },
+        },
+    })
+}
+
+fn port(description: &str) -> Value {
+    json!({
+        "description": description,
+        "type": "integer",
+        "minimum": 0,
+        "maximum": u16::MAX,
+        "default": DEFAULT_PORT,
+    })
+}
+
+fn fingerprint() -> Value {
+    json!({
+        "type": "string",
+        "pattern": "^[0-9a-f]{2}(:[0-9a-f]{2}){31}$",
+    })
+}
+
+/// names of all key codes as used in `release_bind`
+fn key_names() -> Vec<Value> {
+    (0..=KEY_MAX)
+        .filter_map(|code| scancode::Linux::try_from(code).ok())
+        .filter_map(|key| serde_json::to_value(key).ok())
+        .collect()
+}
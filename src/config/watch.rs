@@ -0,0 +1,141 @@
+use std::{
+    collections::HashSet,
+    future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+#[cfg(unix)]
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+/// time to wait for further changes before reloading,
+/// editors often write a file in multiple steps
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// what caused a reload
+pub(crate) enum ConfigChange {
+    /// the config files at these paths changed
+    Files(HashSet<PathBuf>),
+    /// SIGHUP was received, reload regardless of changes
+    Signal,
+}
+
+/// notifies about changes to the config files or a SIGHUP
+pub(crate) struct ConfigWatcher {
+    /// keeps the file watcher alive
+    _watcher: Option<RecommendedWatcher>,
+    changed: UnboundedReceiver<PathBuf>,
+    #[cfg(unix)]
+    sighup: Option<Signal>,
+}
+
+impl ConfigWatcher {
+    /// watch `files` and `dirs` containing drop-ins
+    pub(crate) fn new(files: &[PathBuf], dirs: &[PathBuf]) -> Self {
+        let (tx, changed) = mpsc::unbounded_channel();
+        let watcher = match watch(files, dirs, tx) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                log::warn!("not watching config files for changes: {e}");
+                None
+            }
+        };
+        #[cfg(unix)]
+        let sighup = signal(SignalKind::hangup())
+            .inspect_err(|e| log::warn!("failed to listen for SIGHUP: {e}"))
+            .ok();
+        Self {
+            _watcher: watcher,
+            changed,
+            #[cfg(unix)]
+            sighup,
+        }
+    }
+
+    /// wait until the config file should be reloaded
+    pub(crate) async fn changed(&mut self) -> ConfigChange {
+        #[cfg(unix)]
+        let sighup = async {
+            match self.sighup.as_mut() {
+                Some(s) => s.recv().await,
+                None => future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let sighup = future::pending::<Option<()>>();
+        let mut change = tokio::select! {
+            Some(path) = self.changed.recv() => ConfigChange::Files(HashSet::from([path])),
+            _ = sighup => {
+                log::info!("received SIGHUP");
+                ConfigChange::Signal
+            }
+        };
+        tokio::time::sleep(DEBOUNCE).await;
+        while let Ok(path) = self.changed.try_recv() {
+            if let ConfigChange::Files(files) = &mut change {
+                files.insert(path);
+            }
+        }
+        change
+    }
+}
+
+/// watch the directories containing the config files, which
+/// also catches files being replaced or (re)created
+fn watch(
+    files: &[PathBuf],
+    dirs: &[PathBuf],
+    tx: mpsc::UnboundedSender<PathBuf>,
+) -> Result<RecommendedWatcher, notify::Error> {
+    let file_names = files
+        .iter()
+        .filter_map(|f| f.file_name())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    let drop_in_dirs = dirs.to_vec();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if event.kind.is_access() {
+            return;
+        }
+        let relevant = |p: &PathBuf| {
+            let drop_in = p.extension().is_some_and(|e| e == "toml")
+                && p.parent()
+                    .is_some_and(|d| drop_in_dirs.iter().any(|x| x == d));
+            drop_in
+                || p.file_name()
+                    .is_some_and(|n| file_names.iter().any(|f| f == n))
+        };
+        for path in event.paths.into_iter().filter(relevant) {
+            let _ = tx.send(path);
+        }
+    })?;
+    let mut watched = vec![];
+    for dir in files
+        .iter()
+        .map(|f| parent(f))
+        .chain(dirs.iter().map(|d| d.as_path()))
+    {
+        if watched.contains(&dir) || !dir.is_dir() {
+            continue;
+        }
+        match watcher.watch(dir, RecursiveMode::NonRecursive) {
+            Ok(()) => watched.push(dir),
+            Err(e) => log::warn!("{dir:?}: not watching for changes: {e}"),
+        }
+    }
+    Ok(watcher)
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => Path::new("/"),
+    }
+}
@@ -0,0 +1,634 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, Permissions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use lan_mouse_ipc::{ClientConfig, ClientHandle, ClientState, DEFAULT_PORT};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use super::{
+    Config, ConfigClient, ConfigError, ConfigToml, DEFAULT_PROFILE, TomlClient, layers, migrate,
+};
+
+/// state of the service that is persisted to the config file
+pub(crate) struct LiveConfig {
+    /// the profile `clients` belong to
+    pub(crate) profile: String,
+    pub(crate) clients: Vec<(ClientHandle, ClientConfig, ClientState)>,
+    pub(crate) authorized_fingerprints: HashMap<String, String>,
+    pub(crate) blocked_fingerprints: HashSet<String>,
+    pub(crate) blocked_ips: HashSet<IpAddr>,
+}
+
+/// writes changes made at runtime back to the config file,
+/// preserving comments and unknown keys
+pub(crate) struct ConfigWriter {
+    path: PathBuf,
+    /// port changed at runtime
+    port: Option<u16>,
+    /// release bind changed at runtime
+    release_bind: Option<Vec<String>>,
+    /// contents of the last write, to tell it apart from changes by others
+    written: Option<String>,
+}
+
+impl ConfigWriter {
+    /// create a config writer, unless `persist_changes` is disabled
+    pub(crate) fn new(config: &Config) -> Option<Self> {
+        if !config.persist_changes() {
+            log::info!("not persisting changes to {:?}", config.config_path());
+            return None;
+        }
+        Some(Self {
+            path: config.config_path().to_owned(),
+            port: None,
+            release_bind: None,
+            written: None,
+        })
+    }
+
+    pub(crate) fn set_port(&mut self, port: u16) {
+        self.port = Some(port);
+    }
+
+    pub(crate) fn set_release_bind(&mut self, release_bind: Vec<String>) {
+        self.release_bind = Some(release_bind);
+    }
+
+    /// write the live state, `handles` correspond to the `[[clients]]` tables in the file.
+    /// Returns the handles in the order they were written.
+    pub(crate) fn write(
+        &mut self,
+        live: &LiveConfig,
+        handles: &[ClientHandle],
+    ) -> Result<Vec<ClientHandle>, ConfigError> {
+        /* write through symlinks instead of replacing them */
+        let path = fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        let (mut doc, exists) = match fs::read_to_string(&path) {
+            Ok(s) => (s.parse::<DocumentMut>()?, true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (DocumentMut::new(), false),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(version) = migrate::migrate(&mut doc)? {
+            if exists {
+                let backup = migrate::backup(&path, version)?;
+                log::info!(
+                    "upgrading {path:?} from config version {version}, saved the previous file to {backup:?}"
+                );
+            }
+        }
+        let handles = update(
+            doc.as_table_mut(),
+            live,
+            &inherited(),
+            self.port,
+            self.release_bind.as_deref(),
+            handles,
+        );
+        let contents = doc.to_string();
+        write_atomic(&path, contents.as_bytes())?;
+        log::debug!("saved config to {path:?}");
+        self.written = Some(contents);
+        Ok(handles)
+    }
+
+    /// write the live state and reload `config` from the saved files:
+    /// the change of the file is not reloaded by the service, switching profiles
+    /// and other uses of the config must see what was saved
+    pub(crate) fn save(
+        &mut self,
+        config: &mut Config,
+        live: &LiveConfig,
+        handles: &[ClientHandle],
+    ) -> Result<Vec<ClientHandle>, ConfigError> {
+        let handles = self.write(live, handles)?;
+        match config.reload() {
+            Ok(reloaded) => *config = reloaded,
+            Err(e) => log::warn!("failed to reload saved config: {e}"),
+        }
+        Ok(handles)
+    }
+
+    /// whether `path` is the config file and still holds what was last written to it
+    pub(crate) fn wrote(&self, path: &Path) -> bool {
+        let Some(written) = self.written.as_deref() else {
+            return false;
+        };
+        let same_file = match (fs::canonicalize(path), fs::canonicalize(&self.path)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        };
+        same_file && fs::read_to_string(path).is_ok_and(|s| s == written)
+    }
+}
+
+/// the config of the system wide config files,
+/// values coming from them are not copied to the user config
+fn inherited() -> ConfigToml {
+    layers::Layered::load(&layers::system_files())
+        .ok()
+        .flatten()
+        .and_then(|l| l.config().ok())
+        .unwrap_or_default()
+}
+
+/// update the document, returns the client handles in the order they are written
+fn update(
+    doc: &mut Table,
+    live: &LiveConfig,
+    inherited: &ConfigToml,
+    port: Option<u16>,
+    release_bind: Option<&[String]>,
+    handles: &[ClientHandle],
+) -> Vec<ClientHandle> {
+    if let Some(port) = port {
+        set(doc, "port", Some(i64::from(port).into()));
+    }
+    if let Some(release_bind) = release_bind {
+        set(doc, "release_bind", Some(Value::from_iter(release_bind)));
+    }
+
+    let mut blocked_fingerprints = live.blocked_fingerprints.iter().collect::<Vec<_>>();
+    blocked_fingerprints.sort();
+    let blocked_fingerprints = Value::from_iter(blocked_fingerprints);
+    let inherited_fingerprints = inherited.blocked_fingerprints.iter().flatten();
+    let inherited_fingerprints = Value::from_iter(inherited_fingerprints);
+    set_layered(
+        doc,
+        "blocked_fingerprints",
+        blocked_fingerprints,
+        &inherited_fingerprints,
+    );
+
+    let mut blocked_ips = live.blocked_ips.iter().collect::<Vec<_>>();
+    blocked_ips.sort();
+    let blocked_ips = Value::from_iter(blocked_ips.iter().map(|ip| ip.to_string()));
+    let inherited_ips = inherited.blocked_ips.iter().flatten();
+    let inherited_ips = Value::from_iter(inherited_ips.map(|ip| ip.to_string()));
+    set_layered(doc, "blocked_ips", blocked_ips, &inherited_ips);
+
+    /* fingerprints authorized system wide are not copied to the user config */
+    let inherited_authorized = inherited.authorized_fingerprints.as_ref();
+    let authorized = live
+        .authorized_fingerprints
+        .iter()
+        .filter(|(fp, desc)| inherited_authorized.and_then(|a| a.get(*fp)) != Some(desc))
+        .map(|(fp, desc)| (fp.clone(), desc.clone()))
+        .collect();
+    update_authorized(doc, &authorized);
+
+    let inherited_clients = match live.profile.as_str() {
+        DEFAULT_PROFILE => inherited.clients.as_deref(),
+        profile => inherited
+            .profiles
+            .as_ref()
+            .and_then(|p| p.get(profile))
+            .and_then(|p| p.clients.as_deref()),
+    };
+    match profile_table(doc, &live.profile) {
+        /* clients are replaced as a whole, the system wide ones apply as long as they are unchanged */
+        Some(table) if same_clients(&live.clients, inherited_clients.unwrap_or_default()) => {
+            table.remove("clients");
+            vec![]
+        }
+        /* an empty list keeps the system wide clients from applying */
+        Some(table) if live.clients.is_empty() => {
+            set(table, "clients", Some(Value::Array(Array::new())));
+            vec![]
+        }
+        Some(table) => update_clients(table, &live.clients, handles),
+        None => {
+            log::warn!(
+                "profile `{}` is not a table, not saving clients",
+                live.profile
+            );
+            handles.to_vec()
+        }
+    }
+}
+
+/// the table containing the clients of `profile`
+fn profile_table<'a>(doc: &'a mut Table, profile: &str) -> Option<&'a mut Table> {
+    if profile == DEFAULT_PROFILE {
+        return Some(doc);
+    }
+    doc.entry("profiles")
+        .or_insert(implicit_table())
+        .as_table_mut()?
+        .entry(profile)
+        .or_insert(implicit_table())
+        .as_table_mut()
+}
+
+/// a table that is only written if it contains values
+fn implicit_table() -> Item {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    Item::Table(table)
+}
+
+fn update_authorized(doc: &mut Table, authorized: &HashMap<String, String>) {
+    if authorized.is_empty() {
+        doc.remove("authorized_fingerprints");
+        return;
+    }
+    let table = doc
+        .entry("authorized_fingerprints")
+        .or_insert(Item::Table(Table::new()));
+    if !table.is_table() {
+        *table = Item::Table(Table::new());
+    }
+    let table = table.as_table_mut().expect("table");
+    table.retain(|fp, _| authorized.contains_key(fp));
+    let mut authorized = authorized.iter().collect::<Vec<_>>();
+    authorized.sort();
+    for (fp, desc) in authorized {
+        set(table, fp, Some(desc.into()));
+    }
+}
+
+fn update_clients(
+    doc: &mut Table,
+    clients: &[(ClientHandle, ClientConfig, ClientState)],
+    handles: &[ClientHandle],
+) -> Vec<ClientHandle> {
+    let old = match doc.remove("clients") {
+        Some(Item::ArrayOfTables(tables)) => tables.into_iter().collect(),
+        /* clients = [{ ... }] is written as [[clients]] tables */
+        Some(Item::Value(Value::Array(array))) => array
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::InlineTable(t) => Some(t.into_table()),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    /* keep the tables (and comments) of existing clients in
+     * their original order and append newly created ones */
+    let mut tables = ArrayOfTables::new();
+    let mut order = vec![];
+    for (handle, mut table) in handles.iter().zip(old) {
+        if let Some((_, config, state)) = clients.iter().find(|(h, _, _)| h == handle) {
+            update_client(&mut table, config, state);
+            tables.push(table);
+            order.push(*handle);
+        }
+    }
+    let new = clients.iter().filter(|(h, _, _)| !order.contains(h));
+    for (handle, config, state) in new.collect::<Vec<_>>() {
+        let mut table = Table::new();
+        update_client(&mut table, config, state);
+        tables.push(table);
+        order.push(*handle);
+    }
+    doc.insert("clients", Item::ArrayOfTables(tables));
+    order
+}
+
+fn update_client(table: &mut Table, config: &ClientConfig, state: &ClientState) {
+    set(table, "position", Some(config.pos.to_string().into()));
+    set(table, "hostname", config.hostname.as_ref().map(Into::into));
+    let ips = Value::from_iter(config.fix_ips.iter().map(|ip| ip.to_string()));
+    set_nonempty(table, "ips", ips);
+    let port = config.port != DEFAULT_PORT || table.contains_key("port");
+    set(table, "port", port.then(|| i64::from(config.port).into()));
+    let active = state.active || table.contains_key("activate_on_startup");
+    set(
+        table,
+        "activate_on_startup",
+        active.then(|| state.active.into()),
+    );
+    set(table, "enter_hook", config.cmd.as_ref().map(Into::into));
+    let mac = config.mac.map(|m| m.to_string().into());
+    set(table, "mac_address", mac);
+}
+
+/// set or remove a value, keeping comments attached to an existing key
+fn set(table: &mut Table, key: &str, value: Option<Value>) {
+    let Some(mut value) = value else {
+        table.remove(key);
+        return;
+    };
+    match table.get_mut(key) {
+        Some(Item::Value(old)) => {
+            if !same(old, &value) {
+                *value.decor_mut() = old.decor().clone();
+                *old = value;
+            }
+        }
+        Some(item) => *item = Item::Value(value),
+        None => {
+            table.insert(key, Item::Value(value));
+        }
+    }
+}
+
+/// compare values ignoring their formatting and the order of array elements
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().all(|a| b.iter().any(|b| same(a, b)))
+        }
+        _ => false,
+    }
+}
+
+fn set_nonempty(table: &mut Table, key: &str, value: Value) {
+    let empty = value.as_array().is_some_and(|a| a.is_empty());
+    set(table, key, (!empty).then_some(value));
+}
+
+/// set a value replacing the `inherited` one of the system wide config files:
+/// removed while it is the same, so the system wide one applies
+fn set_layered(table: &mut Table, key: &str, value: Value, inherited: &Value) {
+    let value = (!same(&value, inherited)).then_some(value);
+    set(table, key, value);
+}
+
+/// whether the live clients are the ones in `inherited`
+fn same_clients(
+    live: &[(ClientHandle, ClientConfig, ClientState)],
+    inherited: &[TomlClient],
+) -> bool {
+    live.len() == inherited.len()
+        && live
+            .iter()
+            .zip(inherited)
+            .all(|((_, config, state), toml)| {
+                let toml = ConfigClient::from(toml.clone());
+                toml.hostname == config.hostname
+                    && toml.ips == config.fix_ips.iter().copied().collect()
+                    && toml.port == config.port
+                    && toml.pos == config.pos
+                    && toml.active == state.active
+                    && toml.enter_hook == config.cmd
+                    && toml.mac == config.mac
+            })
+}
+
+/// replace the file at `path` without leaving a partially written file behind,
+/// keeping its permissions
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let permissions = fs::metadata(path).map(|m| m.permissions()).ok();
+    write_atomic_with_permissions(path, contents, permissions)
+}
+
+/// like [`write_atomic`], with the `permissions` of the new file
+/// set before anything is written to it
+pub(crate) fn write_atomic_with_permissions(
+    path: &Path,
+    contents: &[u8],
+    permissions: Option<Permissions>,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    /* a temporary file left behind may be read-only */
+    if tmp.exists() {
+        fs::remove_file(&tmp)?;
+    }
+    let mut file = fs::File::create(&tmp)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use lan_mouse_ipc::{ClientConfig, ClientHandle, ClientState, Position};
+    use toml_edit::DocumentMut;
+
+    use super::{ConfigWriter, LiveConfig, update};
+    use crate::config::{Config, ConfigToml, DEFAULT_PROFILE, TempDir};
+
+    fn client(hostname: &str, pos: Position) -> ClientConfig {
+        ClientConfig {
+            hostname: Some(hostname.to_owned()),
+            pos,
+            ..Default::default()
+        }
+    }
+
+    fn live(clients: Vec<ClientConfig>) -> LiveConfig {
+        LiveConfig {
+            profile: DEFAULT_PROFILE.to_owned(),
+            clients: clients
+                .into_iter()
+                .enumerate()
+                .map(|(h, c)| (h as ClientHandle, c, ClientState::default()))
+                .collect(),
+            authorized_fingerprints: HashMap::new(),
+            blocked_fingerprints: HashSet::new(),
+            blocked_ips: HashSet::new(),
+        }
+    }
+
+    /// update the user config `source` with `live`, inheriting `system`
+    fn write(source: &str, system: &str, live: &LiveConfig, handles: &[ClientHandle]) -> String {
+        let mut doc = source.parse::<DocumentMut>().unwrap();
+        let inherited = toml::from_str::<ConfigToml>(system).unwrap();
+        update(doc.as_table_mut(), live, &inherited, None, None, handles);
+        doc.to_string()
+    }
+
+    const SYSTEM: &str = "blocked_ips = [\"10.0.0.1\"]\n\
+                          blocked_fingerprints = [\"aa\"]\n\
+                          [authorized_fingerprints]\n\
+                          bb = \"system\"\n\
+                          [[clients]]\n\
+                          hostname = \"a\"\n\
+                          position = \"left\"\n";
+
+    /// the live state of the service after loading `SYSTEM`
+    fn inherited_live() -> LiveConfig {
+        let mut live = live(vec![client("a", Position::Left)]);
+        live.blocked_ips.insert("10.0.0.1".parse().unwrap());
+        live.blocked_fingerprints.insert("aa".to_owned());
+        live.authorized_fingerprints
+            .insert("bb".to_owned(), "system".to_owned());
+        live
+    }
+
+    #[test]
+    fn system_values_are_not_copied() {
+        let live = inherited_live();
+        assert_eq!(write("port = 1\n", SYSTEM, &live, &[]), "port = 1\n");
+    }
+
+    #[test]
+    fn changed_system_values_are_replaced() {
+        let mut live = inherited_live();
+        live.blocked_ips.insert("10.0.0.2".parse().unwrap());
+        live.blocked_fingerprints.clear();
+        live.clients[0].1.pos = Position::Right;
+        assert_eq!(
+            write("", SYSTEM, &live, &[]),
+            "blocked_fingerprints = []\n\
+             blocked_ips = [\"10.0.0.1\", \"10.0.0.2\"]\n\
+             \n\
+             [[clients]]\n\
+             position = \"right\"\n\
+             hostname = \"a\"\n"
+        );
+    }
+
+    #[test]
+    fn removed_system_clients() {
+        let mut live = inherited_live();
+        live.clients.clear();
+        assert_eq!(write("", SYSTEM, &live, &[]), "clients = []\n");
+        /* once they are the same again, the system wide clients apply */
+        let live = inherited_live();
+        assert_eq!(write("clients = []\n", SYSTEM, &live, &[]), "");
+    }
+
+    const USER: &str = "# lan-mouse config\n\
+                        version = 2\n\
+                        colour = \"blue\" # not a lan-mouse setting\n\
+                        \n\
+                        [management]\n\
+                        address = \"127.0.0.1:4243\"\n\
+                        \n\
+                        # the laptop\n\
+                        [[clients]]\n\
+                        hostname = \"a\" # on the left\n\
+                        position = \"left\"\n\
+                        shape = \"round\"\n\
+                        \n\
+                        [authorized_fingerprints]\n\
+                        # mine\n\
+                        cc = \"laptop\"\n\
+                        \n\
+                        # the desktop\n\
+                        [[clients]]\n\
+                        position = \"right\"\n\
+                        hostname = \"b\"\n";
+
+    #[test]
+    fn comments_and_unknown_keys_are_kept() {
+        let mut live = live(vec![
+            client("a", Position::Top),
+            client("b", Position::Right),
+        ]);
+        live.authorized_fingerprints
+            .insert("cc".to_owned(), "laptop".to_owned());
+        live.authorized_fingerprints
+            .insert("dd".to_owned(), "phone".to_owned());
+        assert_eq!(
+            write(USER, "", &live, &[0, 1]),
+            "# lan-mouse config\n\
+             version = 2\n\
+             colour = \"blue\" # not a lan-mouse setting\n\
+             \n\
+             [management]\n\
+             address = \"127.0.0.1:4243\"\n\
+             \n\
+             # the laptop\n\
+             [[clients]]\n\
+             hostname = \"a\" # on the left\n\
+             position = \"top\"\n\
+             shape = \"round\"\n\
+             \n\
+             [authorized_fingerprints]\n\
+             # mine\n\
+             cc = \"laptop\"\n\
+             dd = \"phone\"\n\
+             \n\
+             # the desktop\n\
+             [[clients]]\n\
+             position = \"right\"\n\
+             hostname = \"b\"\n"
+        );
+    }
+
+    #[test]
+    fn clients_are_matched_by_handle() {
+        /* the first client was removed, a new one created */
+        let mut live = live(vec![]);
+        live.clients = vec![
+            (3, client("c", Position::Bottom), ClientState::default()),
+            (1, client("b", Position::Right), ClientState::default()),
+        ];
+        live.authorized_fingerprints
+            .insert("cc".to_owned(), "laptop".to_owned());
+        let output = write(USER, "", &live, &[0, 1]);
+        assert!(
+            output.ends_with(
+                "[authorized_fingerprints]\n\
+             # mine\n\
+             cc = \"laptop\"\n\
+             \n\
+             # the desktop\n\
+             [[clients]]\n\
+             position = \"right\"\n\
+             hostname = \"b\"\n\
+             \n\
+             [[clients]]\n\
+             position = \"bottom\"\n\
+             hostname = \"c\"\n"
+            ),
+            "{output}"
+        );
+        assert!(!output.contains("# the laptop"));
+    }
+
+    #[test]
+    fn switching_profiles() {
+        let dir = TempDir::new("writer-profiles");
+        let path = dir.write(
+            "config.toml",
+            "version = 2\n\
+             [profiles.a]\n\
+             clients = [{ hostname = \"a\", position = \"left\" }]\n\
+             [profiles.b]\n\
+             clients = [{ hostname = \"b\", position = \"right\" }]\n",
+        );
+        let mut config =
+            Config::parse_from(["lan-mouse".as_ref(), "--config".as_ref(), path.as_os_str()])
+                .unwrap();
+        let mut writer = ConfigWriter::new(&config).unwrap();
+
+        /* the client of profile `a` is moved, then the service switches to `b` and back */
+        let mut live = live(vec![client("a", Position::Top)]);
+        live.profile = "a".to_owned();
+        writer.save(&mut config, &live, &[0]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "version = 2\n\
+             [profiles.a]\n\
+             \n\
+             [[profiles.a.clients]]\n\
+             hostname = \"a\"\n\
+             position = \"top\"\n\
+             [profiles.b]\n\
+             clients = [{ hostname = \"b\", position = \"right\" }]\n"
+        );
+        let b = config.clients("b");
+        assert_eq!(b[0].hostname.as_deref(), Some("b"));
+        let a = config.clients("a");
+        assert_eq!(a[0].hostname.as_deref(), Some("a"));
+        assert_eq!(a[0].pos, Position::Top);
+
+        /* changes to `b` do not affect `a` */
+        live.profile = "b".to_owned();
+        live.clients[0].1 = client("b", Position::Bottom);
+        writer.save(&mut config, &live, &[0]).unwrap();
+        assert_eq!(config.clients("a")[0].pos, Position::Top);
+        assert_eq!(config.clients("b")[0].pos, Position::Bottom);
+    }
+}
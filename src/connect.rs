@@ -1,4 +1,8 @@
-use crate::client::ClientManager;
+use crate::{
+    client::ClientManager,
+    stats::{Peer, Stats},
+    wol,
+};
 use lan_mouse_ipc::{ClientHandle, DEFAULT_PORT};
 use lan_mouse_proto::{MAX_EVENT_SIZE, ProtoEvent};
 use local_channel::mpsc::{Receiver, Sender, channel};
@@ -9,7 +13,7 @@ use std::{
     net::SocketAddr,
     rc::Rc,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 use tokio::{
@@ -41,6 +45,7 @@ pub(crate) enum LanMouseConnectionError {
 }
 
 const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+const WAKE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 async fn connect(
     addr: SocketAddr,
@@ -92,27 +97,43 @@ async fn connect_any(
 }
 
 pub(crate) struct LanMouseConnection {
+    context: Context,
+    recv_rx: Receiver<(ClientHandle, ProtoEvent)>,
+}
+
+/// state shared with the tasks connecting to and receiving from clients
+#[derive(Clone)]
+struct Context {
     cert: Certificate,
     client_manager: ClientManager,
     conns: Rc<Mutex<HashMap<SocketAddr, Arc<dyn Conn + Send + Sync>>>>,
     connecting: Rc<Mutex<HashSet<ClientHandle>>>,
-    recv_rx: Receiver<(ClientHandle, ProtoEvent)>,
     recv_tx: Sender<(ClientHandle, ProtoEvent)>,
     ping_response: Rc<RefCell<HashSet<SocketAddr>>>,
+    /// how long to keep trying to connect after waking a client
+    wake_window: Duration,
+    stats: Stats,
 }
 
 impl LanMouseConnection {
-    pub(crate) fn new(cert: Certificate, client_manager: ClientManager) -> Self {
+    pub(crate) fn new(
+        cert: Certificate,
+        client_manager: ClientManager,
+        wake_window: Duration,
+        stats: Stats,
+    ) -> Self {
         let (recv_tx, recv_rx) = channel();
-        Self {
+        let context = Context {
             cert,
             client_manager,
+            wake_window,
+            stats,
             conns: Default::default(),
             connecting: Default::default(),
-            recv_rx,
             recv_tx,
             ping_response: Default::default(),
-        }
+        };
+        Self { context, recv_rx }
     }
 
     pub(crate) async fn recv(&mut self) -> (ClientHandle, ProtoEvent) {
@@ -124,22 +145,24 @@ impl LanMouseConnection {
         event: ProtoEvent,
         handle: ClientHandle,
     ) -> Result<(), LanMouseConnectionError> {
+        let context = &self.context;
         let (buf, len): ([u8; MAX_EVENT_SIZE], usize) = event.into();
         let buf = &buf[..len];
-        if let Some(addr) = self.client_manager.active_addr(handle) {
+        if let Some(addr) = context.client_manager.active_addr(handle) {
             let conn = {
-                let conns = self.conns.lock().await;
+                let conns = context.conns.lock().await;
                 conns.get(&addr).cloned()
             };
             if let Some(conn) = conn {
-                if !self.client_manager.alive(handle) {
+                if !context.client_manager.alive(handle) {
                     return Err(LanMouseConnectionError::TargetEmulationDisabled);
                 }
                 match conn.send(buf).await {
-                    Ok(_) => {}
+                    Ok(_) => context.stats.sent(Peer::Client(handle), &event, len),
                     Err(e) => {
                         log::warn!("client {handle} failed to send: {e}");
-                        disconnect(&self.client_manager, handle, addr, &self.conns).await;
+                        context.stats.send_error(Peer::Client(handle));
+                        context.disconnect(handle, addr).await;
                     }
                 }
                 log::trace!("{event} >->->->->- {addr}");
@@ -148,78 +171,142 @@ impl LanMouseConnection {
         }
 
         // check if we are already trying to connect
-        let mut connecting = self.connecting.lock().await;
+        let mut connecting = context.connecting.lock().await;
         if !connecting.contains(&handle) {
             connecting.insert(handle);
+            context.stats.connecting(Peer::Client(handle));
             // connect in the background
-            spawn_local(connect_to_handle(
-                self.client_manager.clone(),
-                self.cert.clone(),
+            spawn_local(context.clone().connect_to_handle(handle));
+        }
+        Err(LanMouseConnectionError::NotConnected)
+    }
+}
+
+impl Context {
+    async fn connect_to_handle(self, handle: ClientHandle) -> Result<(), LanMouseConnectionError> {
+        log::info!("client {handle} connecting ...");
+        // sending did not work, figure out active conn.
+        if let Some(addrs) = client_addrs(&self.client_manager, handle) {
+            log::info!("client ({handle}) connecting ... (ips: {addrs:?})");
+            let res = match connect_any(&addrs, self.cert.clone()).await {
+                Err(_) if self.client_manager.get_mac(handle).is_some() => {
+                    self.wake_and_connect(handle).await
+                }
+                res => res,
+            };
+            let (conn, addr) = match res {
+                Ok(c) => c,
+                Err(e) => {
+                    self.connecting.lock().await.remove(&handle);
+                    self.stats.disconnected(Peer::Client(handle));
+                    return Err(e);
+                }
+            };
+            log::info!("client ({handle}) connected @ {addr}");
+            self.stats.connected(Peer::Client(handle), addr);
+            self.client_manager.set_active_addr(handle, Some(addr));
+            self.conns.lock().await.insert(addr, conn.clone());
+            self.connecting.lock().await.remove(&handle);
+
+            // poll connection for active
+            spawn_local(ping_pong(
                 handle,
-                self.conns.clone(),
-                self.connecting.clone(),
-                self.recv_tx.clone(),
+                addr,
+                conn.clone(),
                 self.ping_response.clone(),
+                self.stats.clone(),
             ));
+
+            // receiver
+            spawn_local(self.receive_loop(handle, addr, conn));
+            return Ok(());
         }
+        self.connecting.lock().await.remove(&handle);
+        self.stats.disconnected(Peer::Client(handle));
         Err(LanMouseConnectionError::NotConnected)
     }
-}
 
-async fn connect_to_handle(
-    client_manager: ClientManager,
-    cert: Certificate,
-    handle: ClientHandle,
-    conns: Rc<Mutex<HashMap<SocketAddr, Arc<dyn Conn + Send + Sync>>>>,
-    connecting: Rc<Mutex<HashSet<ClientHandle>>>,
-    tx: Sender<(ClientHandle, ProtoEvent)>,
-    ping_response: Rc<RefCell<HashSet<SocketAddr>>>,
-) -> Result<(), LanMouseConnectionError> {
-    log::info!("client {handle} connecting ...");
-    // sending did not work, figure out active conn.
-    if let Some(addrs) = client_manager.get_ips(handle) {
-        let port = client_manager.get_port(handle).unwrap_or(DEFAULT_PORT);
-        let addrs = addrs
-            .into_iter()
-            .map(|a| SocketAddr::new(a, port))
-            .collect::<Vec<_>>();
-        log::info!("client ({handle}) connecting ... (ips: {addrs:?})");
-        let res = connect_any(&addrs, cert).await;
-        let (conn, addr) = match res {
-            Ok(c) => c,
-            Err(e) => {
-                connecting.lock().await.remove(&handle);
-                return Err(e);
+    /// send a Wake-on-LAN packet to the client and keep
+    /// trying to connect until the wake window has passed
+    async fn wake_and_connect(
+        &self,
+        handle: ClientHandle,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr), LanMouseConnectionError> {
+        let client_manager = &self.client_manager;
+        let mac = client_manager.get_mac(handle).expect("no mac address");
+        let ips = client_manager.get_ips(handle).unwrap_or_default();
+        log::info!("client ({handle}) unreachable, sending Wake-on-LAN packet to {mac}");
+        if let Err(e) = wol::wake(mac, &ips.into_iter().collect::<Vec<_>>()).await {
+            log::warn!("failed to send Wake-on-LAN packet: {e}");
+        }
+        let deadline = Instant::now() + self.wake_window;
+        loop {
+            tokio::time::sleep(WAKE_RETRY_INTERVAL).await;
+            let addrs = client_addrs(client_manager, handle)
+                .ok_or(LanMouseConnectionError::NotConnected)?;
+            match connect_any(&addrs, self.cert.clone()).await {
+                Ok(c) => return Ok(c),
+                Err(e) if Instant::now() >= deadline => {
+                    log::warn!(
+                        "client ({handle}) did not wake up within {:?}",
+                        self.wake_window
+                    );
+                    return Err(e);
+                }
+                Err(_) => {}
             }
-        };
-        log::info!("client ({handle}) connected @ {addr}");
-        client_manager.set_active_addr(handle, Some(addr));
-        conns.lock().await.insert(addr, conn.clone());
-        connecting.lock().await.remove(&handle);
+        }
+    }
 
-        // poll connection for active
-        spawn_local(ping_pong(addr, conn.clone(), ping_response.clone()));
+    async fn receive_loop(
+        self,
+        handle: ClientHandle,
+        addr: SocketAddr,
+        conn: Arc<dyn Conn + Send + Sync>,
+    ) {
+        let mut buf = [0u8; MAX_EVENT_SIZE];
+        while let Ok(len) = conn.recv(&mut buf).await {
+            let event: Option<ProtoEvent> = buf.try_into().ok();
+            self.stats
+                .received(Peer::Client(handle), event.as_ref(), len);
+            if let Some(event) = event {
+                log::trace!("{addr} <==<==<== {event}");
+                match event {
+                    ProtoEvent::Pong(b) => {
+                        self.client_manager.set_active_addr(handle, Some(addr));
+                        self.client_manager.set_alive(handle, b);
+                        self.ping_response.borrow_mut().insert(addr);
+                    }
+                    event => self.recv_tx.send((handle, event)).expect("channel closed"),
+                }
+            }
+        }
+        log::warn!("recv error");
+        self.disconnect(handle, addr).await;
+    }
 
-        // receiver
-        spawn_local(receive_loop(
-            client_manager,
-            handle,
-            addr,
-            conn,
-            conns,
-            tx,
-            ping_response.clone(),
-        ));
-        return Ok(());
+    async fn disconnect(&self, handle: ClientHandle, addr: SocketAddr) {
+        log::warn!("client ({handle}) @ {addr} connection closed");
+        self.stats.disconnected(Peer::Client(handle));
+        self.conns.lock().await.remove(&addr);
+        self.client_manager.set_active_addr(handle, None);
+        let active: Vec<SocketAddr> = self.conns.lock().await.keys().copied().collect();
+        log::info!("active connections: {active:?}");
     }
-    connecting.lock().await.remove(&handle);
-    Err(LanMouseConnectionError::NotConnected)
+}
+
+fn client_addrs(client_manager: &ClientManager, handle: ClientHandle) -> Option<Vec<SocketAddr>> {
+    let ips = client_manager.get_ips(handle)?;
+    let port = client_manager.get_port(handle).unwrap_or(DEFAULT_PORT);
+    Some(ips.into_iter().map(|a| SocketAddr::new(a, port)).collect())
 }
 
 async fn ping_pong(
+    handle: ClientHandle,
     addr: SocketAddr,
     conn: Arc<dyn Conn + Send + Sync>,
     ping_response: Rc<RefCell<HashSet<SocketAddr>>>,
+    stats: Stats,
 ) {
     loop {
         let (buf, len) = ProtoEvent::Ping.into();
@@ -228,9 +315,11 @@ async fn ping_pong(
         for _ in 0..4 {
             if let Err(e) = conn.send(&buf[..len]).await {
                 log::warn!("{addr}: send error `{e}`, closing connection");
+                stats.send_error(Peer::Client(handle));
                 let _ = conn.close().await;
                 break;
             }
+            stats.sent(Peer::Client(handle), &ProtoEvent::Ping, len);
             log::trace!("PING >->->->->- {addr}");
 
             tokio::time::sleep(Duration::from_millis(500)).await;
@@ -243,43 +332,3 @@ async fn ping_pong(
         }
     }
 }
-
-async fn receive_loop(
-    client_manager: ClientManager,
-    handle: ClientHandle,
-    addr: SocketAddr,
-    conn: Arc<dyn Conn + Send + Sync>,
-    conns: Rc<Mutex<HashMap<SocketAddr, Arc<dyn Conn + Send + Sync>>>>,
-    tx: Sender<(ClientHandle, ProtoEvent)>,
-    ping_response: Rc<RefCell<HashSet<SocketAddr>>>,
-) {
-    let mut buf = [0u8; MAX_EVENT_SIZE];
-    while conn.recv(&mut buf).await.is_ok() {
-        if let Ok(event) = buf.try_into() {
-            log::trace!("{addr} <==<==<== {event}");
-            match event {
-                ProtoEvent::Pong(b) => {
-                    client_manager.set_active_addr(handle, Some(addr));
-                    client_manager.set_alive(handle, b);
-                    ping_response.borrow_mut().insert(addr);
-                }
-                event => tx.send((handle, event)).expect("channel closed"),
-            }
-        }
-    }
-    log::warn!("recv error");
-    disconnect(&client_manager, handle, addr, &conns).await;
-}
-
-async fn disconnect(
-    client_manager: &ClientManager,
-    handle: ClientHandle,
-    addr: SocketAddr,
-    conns: &Mutex<HashMap<SocketAddr, Arc<dyn Conn + Send + Sync>>>,
-) {
-    log::warn!("client ({handle}) @ {addr} connection closed");
-    conns.lock().await.remove(&addr);
-    client_manager.set_active_addr(handle, None);
-    let active: Vec<SocketAddr> = conns.lock().await.keys().copied().collect();
-    log::info!("active connections: {active:?}");
-}
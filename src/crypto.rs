@@ -1,14 +1,38 @@
 use std::fs;
-use std::io::{self, BufWriter, Read, Write};
+use std::io::{self, Read};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs::File, io::BufReader};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+use crate::config::{self, KeyStorage};
+
+#[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+mod keyring;
+
+use pkcs8::{
+    EncryptedPrivateKeyInfo, PrivateKeyInfo,
+    der::Encode,
+    pkcs5::pbes2,
+    rand_core::{OsRng, RngCore},
+};
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use ring::{
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_ASN1, ED25519, UnparsedPublicKey, VerificationAlgorithm},
+};
+use rustls::pki_types::CertificateDer;
 use sha2::{Digest, Sha256};
 use thiserror::Error;
-use webrtc_dtls::crypto::Certificate;
+use webrtc_dtls::crypto::{Certificate, CryptoPrivateKey, CryptoPrivateKeyKind};
+use x509_parser::{
+    certificate::X509Certificate,
+    der_parser::oid::Oid,
+    oid_registry::{OID_KEY_TYPE_EC_PUBLIC_KEY, OID_SIG_ED25519},
+    prelude::FromDer,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -16,6 +40,27 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error(transparent)]
     Dtls(#[from] webrtc_dtls::Error),
+    #[error(transparent)]
+    Rcgen(#[from] rcgen::Error),
+    #[error("failed to sign key rotation statement")]
+    Signature,
+    #[error("key type does not support key rotation")]
+    UnsupportedKey,
+    #[error("certificate too large for a key rotation statement ({0} bytes)")]
+    CertificateTooLarge(usize),
+    #[error(transparent)]
+    Pkcs8(#[from] pkcs8::Error),
+    #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+    #[error("secret service: {0}")]
+    SecretService(#[from] secret_service::Error),
+    #[error("invalid PEM: {0}")]
+    InvalidPem(String),
+    #[error("private key not found")]
+    MissingKey,
+    #[error("a passphrase is required to unlock the private key")]
+    PassphraseRequired,
+    #[error("invalid passphrase")]
+    InvalidPassphrase,
 }
 
 pub fn generate_fingerprint(cert: &[u8]) -> String {
@@ -34,38 +79,534 @@ pub fn certificate_fingerprint(cert: &Certificate) -> String {
     generate_fingerprint(certificate)
 }
 
-/// load certificate from file
-pub fn load_certificate(path: &Path) -> Result<Certificate, Error> {
+/// how the private key is kept in the certificate file
+#[derive(Debug)]
+enum StoredKey {
+    /// unencrypted PKCS#8
+    Plain(Vec<u8>),
+    /// PKCS#8 encrypted with a passphrase
+    Encrypted(Vec<u8>),
+    /// the file only contains the certificate, the key is kept in the secret service
+    External,
+}
+
+impl StoredKey {
+    fn storage(&self) -> KeyStorage {
+        match self {
+            StoredKey::Plain(_) => KeyStorage::Plain,
+            StoredKey::Encrypted(_) => KeyStorage::Passphrase,
+            #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+            StoredKey::External => KeyStorage::SecretService,
+            #[cfg(not(all(unix, feature = "secret_service", not(target_os = "macos"))))]
+            StoredKey::External => KeyStorage::Plain,
+        }
+    }
+}
+
+/// tag of unencrypted keys written by webrtc-dtls, kept for older versions
+const PRIVATE_KEY_TAG: &str = "PRIVATE_KEY";
+/// tag of unencrypted PKCS#8 keys written by openssl and most other tools
+const PKCS8_PRIVATE_KEY_TAG: &str = "PRIVATE KEY";
+const ENCRYPTED_PRIVATE_KEY_TAG: &str = "ENCRYPTED PRIVATE KEY";
+const CERTIFICATE_TAG: &str = "CERTIFICATE";
+
+/// read the (possibly encrypted) private key and certificate chain from file
+fn read_pem(path: &Path) -> Result<(StoredKey, Vec<CertificateDer<'static>>), Error> {
     let f = File::open(path)?;
 
     let mut reader = BufReader::new(f);
     let mut pem = String::new();
     reader.read_to_string(&mut pem)?;
-    Ok(Certificate::from_pem(pem.as_str())?)
+    let pems = pem::parse_many(pem).map_err(|e| Error::InvalidPem(e.to_string()))?;
+
+    let mut key = StoredKey::External;
+    let mut certificate = Vec::new();
+    for p in pems {
+        match p.tag() {
+            PRIVATE_KEY_TAG | PKCS8_PRIVATE_KEY_TAG => key = StoredKey::Plain(p.into_contents()),
+            ENCRYPTED_PRIVATE_KEY_TAG => key = StoredKey::Encrypted(p.into_contents()),
+            CERTIFICATE_TAG => certificate.push(CertificateDer::from(p.into_contents())),
+            tag => return Err(Error::InvalidPem(format!("unexpected tag '{tag}'"))),
+        }
+    }
+    if certificate.is_empty() {
+        return Err(Error::InvalidPem("no certificate found".into()));
+    }
+    Ok((key, certificate))
+}
+
+/// load the certificate chain from file without accessing the private key
+pub fn load_certificate_chain(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let (_, certificate) = read_pem(path)?;
+    Ok(certificate)
 }
 
-pub(crate) fn load_or_generate_key_and_cert(path: &Path) -> Result<Certificate, Error> {
-    if path.exists() && path.is_file() {
-        Ok(load_certificate(path)?)
-    } else {
-        generate_key_and_cert(path)
+/// how the private key in the certificate file is currently stored
+pub fn key_storage(path: &Path) -> Result<KeyStorage, Error> {
+    let (key, _) = read_pem(path)?;
+    Ok(key.storage())
+}
+
+/// load certificate from file.
+/// An encrypted private key is decrypted using `passphrase`, a key kept
+/// in the secret service is looked up by the certificate fingerprint.
+pub async fn load_certificate(path: &Path, passphrase: Option<&str>) -> Result<Certificate, Error> {
+    let (key, certificate) = read_pem(path)?;
+    let key = match key {
+        StoredKey::Plain(der) => der,
+        StoredKey::Encrypted(der) => decrypt_key(&der, passphrase)?,
+        #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+        StoredKey::External => {
+            let fingerprint = generate_fingerprint(&certificate[0]);
+            keyring::load_key(&fingerprint)
+                .await?
+                .ok_or(Error::MissingKey)?
+        }
+        #[cfg(not(all(unix, feature = "secret_service", not(target_os = "macos"))))]
+        StoredKey::External => return Err(Error::MissingKey),
+    };
+    let key_pair = KeyPair::try_from(key.as_slice())?;
+    Ok(Certificate {
+        certificate,
+        private_key: CryptoPrivateKey::from_key_pair(&key_pair)?,
+    })
+}
+
+/// load the certificate or generate a new one if it does not exist.
+/// A private key that is not stored as configured by `storage` is migrated.
+pub(crate) async fn load_or_generate_key_and_cert(
+    path: &Path,
+    storage: KeyStorage,
+    passphrase: Option<&str>,
+) -> Result<Certificate, Error> {
+    if !(path.exists() && path.is_file()) {
+        return generate_key_and_cert(path, storage, passphrase).await;
+    }
+    let (key, _) = read_pem(path)?;
+    let cert = load_certificate(path, passphrase).await?;
+    if key.storage() != storage {
+        log::info!(
+            "migrating private key from `{}` to `{storage}` storage",
+            key.storage()
+        );
+        store_certificate(path, &cert, storage, passphrase).await?;
     }
+    Ok(cert)
 }
 
-pub(crate) fn generate_key_and_cert(path: &Path) -> Result<Certificate, Error> {
+pub(crate) async fn generate_key_and_cert(
+    path: &Path,
+    storage: KeyStorage,
+    passphrase: Option<&str>,
+) -> Result<Certificate, Error> {
     let cert = Certificate::generate_self_signed(["ignored".to_owned()])?;
-    let serialized = cert.serialize_pem();
-    let parent = path.parent().expect("is a path");
-    fs::create_dir_all(parent)?;
-    let f = File::create(path)?;
-    #[cfg(unix)]
-    {
-        let mut perm = f.metadata()?.permissions();
-        perm.set_mode(0o400); /* r-- --- --- */
-        f.set_permissions(perm)?;
+    store_certificate(path, &cert, storage, passphrase).await?;
+    Ok(cert)
+}
+
+/// write certificate and private key to `path`, readable only by the owner,
+/// keeping the private key as configured by `storage`
+pub(crate) async fn store_certificate(
+    path: &Path,
+    cert: &Certificate,
+    storage: KeyStorage,
+    passphrase: Option<&str>,
+) -> Result<(), Error> {
+    let key = match storage {
+        KeyStorage::Plain => StoredKey::Plain(cert.private_key.serialized_der.clone()),
+        KeyStorage::Passphrase => {
+            StoredKey::Encrypted(encrypt_key(&cert.private_key.serialized_der, passphrase)?)
+        }
+        #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+        KeyStorage::SecretService => {
+            let fingerprint = certificate_fingerprint(cert);
+            keyring::store_key(&fingerprint, &cert.private_key.serialized_der).await?;
+            StoredKey::External
+        }
+    };
+
+    /* a key replaced or migrated out of the secret service */
+    #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+    let replaced = match read_pem(path) {
+        Ok((StoredKey::External, previous)) => {
+            Some(generate_fingerprint(&previous[0])).filter(|previous| {
+                !matches!(key, StoredKey::External) || *previous != certificate_fingerprint(cert)
+            })
+        }
+        _ => None,
+    };
+
+    let mut pems = match key {
+        StoredKey::Plain(der) => vec![pem::Pem::new(PRIVATE_KEY_TAG, der)],
+        StoredKey::Encrypted(der) => vec![pem::Pem::new(ENCRYPTED_PRIVATE_KEY_TAG, der)],
+        StoredKey::External => vec![],
+    };
+    pems.extend(
+        cert.certificate
+            .iter()
+            .map(|c| pem::Pem::new(CERTIFICATE_TAG, c.as_ref())),
+    );
+    write_pem(path, &pem::encode_many(&pems))?;
+
+    /* only once the new certificate is in place */
+    #[cfg(all(unix, feature = "secret_service", not(target_os = "macos")))]
+    if let Some(previous) = replaced {
+        if let Err(e) = keyring::delete_key(&previous).await {
+            log::warn!("failed to remove previous key from secret service: {e}");
+        }
     }
+    Ok(())
+}
+
+/// replace the certificate file at `path`, readable only by the owner
+fn write_pem(path: &Path, serialized: &str) -> Result<(), Error> {
+    #[cfg(unix)]
+    let permissions = Some(fs::Permissions::from_mode(0o400)); /* r-- --- --- */
     /* FIXME windows permissions */
-    let mut writer = BufWriter::new(f);
-    writer.write_all(serialized.as_bytes())?;
-    Ok(cert)
+    #[cfg(not(unix))]
+    let permissions = None;
+    config::write_atomic_with_permissions(path, serialized.as_bytes(), permissions)?;
+    Ok(())
+}
+
+/// encrypt a PKCS#8 private key (scrypt + AES-256-CBC)
+fn encrypt_key(der: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, Error> {
+    let passphrase = passphrase
+        .filter(|p| !p.is_empty())
+        .ok_or(Error::PassphraseRequired)?;
+    let key = PrivateKeyInfo::try_from(der)?;
+    let encrypted = key.encrypt(OsRng, passphrase)?;
+    Ok(encrypted.as_bytes().to_vec())
+}
+
+fn decrypt_key(der: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, Error> {
+    let passphrase = passphrase.ok_or(Error::PassphraseRequired)?;
+    let key = EncryptedPrivateKeyInfo::try_from(der)?;
+    let decrypted = key
+        .decrypt(passphrase)
+        .map_err(|_| Error::InvalidPassphrase)?;
+    Ok(decrypted.as_bytes().to_vec())
+}
+
+/// encrypt arbitrary data with a passphrase (scrypt + AES-256-CBC),
+/// DER encoded in the same structure as an encrypted PKCS#8 key.
+/// The data is prefixed with its SHA-256 digest, so that a wrong passphrase
+/// or corrupted data is detected on decryption.
+pub(crate) fn encrypt_data(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if passphrase.is_empty() {
+        return Err(Error::PassphraseRequired);
+    }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+    let params = pbes2::Parameters::scrypt_aes256cbc(Default::default(), &salt, &iv)
+        .map_err(pkcs8::Error::from)?;
+    let mut plain = Sha256::digest(data).to_vec();
+    plain.extend_from_slice(data);
+    let encrypted = params
+        .encrypt(passphrase, &plain)
+        .map_err(pkcs8::Error::from)?;
+    let info = EncryptedPrivateKeyInfo {
+        encryption_algorithm: params.into(),
+        encrypted_data: &encrypted,
+    };
+    Ok(info.to_der().map_err(pkcs8::Error::from)?)
+}
+
+/// decrypt data encrypted with [`encrypt_data`]
+pub(crate) fn decrypt_data(der: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let info = EncryptedPrivateKeyInfo::try_from(der)?;
+    let plain = info
+        .encryption_algorithm
+        .decrypt(passphrase, info.encrypted_data)
+        .map_err(|_| Error::InvalidPassphrase)?;
+    /* the padding alone may happen to be valid with a wrong passphrase */
+    match plain.split_at_checked(Sha256::output_size()) {
+        Some((digest, data)) if Sha256::digest(data).as_slice() == digest => Ok(data.to_vec()),
+        _ => Err(Error::InvalidPassphrase),
+    }
+}
+
+/// OID of the (non-critical) certificate extension carrying a key rotation statement
+const KEY_ROTATION_OID: &[u64] = &[2, 25, 15_868_296_171_843_735_661, 1];
+
+/// context string prepended to every signed key rotation statement
+const KEY_ROTATION_CONTEXT: &[u8] = b"lan-mouse key rotation v1";
+
+/// A statement, signed by a previous key, that a certificate replaces it.
+#[derive(Clone, Debug)]
+pub struct KeyRotation {
+    /// fingerprint of the certificate that was replaced
+    pub old_fingerprint: String,
+    /// end of the transition window
+    pub not_after: SystemTime,
+}
+
+/// generate a new key and certificate carrying a rotation statement
+/// signed by `old`, that peers trusting `old` accept until `not_after`
+pub(crate) fn generate_rotated_key_and_cert(
+    old: &Certificate,
+    not_after: SystemTime,
+) -> Result<Certificate, Error> {
+    let key_pair = KeyPair::generate()?;
+    let old_cert = old.certificate.first().expect("certificate missing");
+    /* the statement embeds the old certificate behind a u16 length */
+    let len =
+        u16::try_from(old_cert.len()).map_err(|_| Error::CertificateTooLarge(old_cert.len()))?;
+    let not_after = not_after
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let message = rotation_message(&key_pair.public_key_der(), not_after);
+    let signature = match &old.private_key.kind {
+        CryptoPrivateKeyKind::Ecdsa256(key) => key
+            .sign(&SystemRandom::new(), &message)
+            .map_err(|_| Error::Signature)?
+            .as_ref()
+            .to_vec(),
+        CryptoPrivateKeyKind::Ed25519(key) => key.sign(&message).as_ref().to_vec(),
+        CryptoPrivateKeyKind::Rsa256(_) => return Err(Error::UnsupportedKey),
+    };
+
+    /* [u16 len][old certificate][u64 not after][signature] */
+    let mut content = Vec::new();
+    content.extend_from_slice(&len.to_be_bytes());
+    content.extend_from_slice(old_cert);
+    content.extend_from_slice(&not_after.to_be_bytes());
+    content.extend_from_slice(&signature);
+
+    let mut params = CertificateParams::new(["ignored".to_owned()])?;
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(KEY_ROTATION_OID, content));
+    let cert = params.self_signed(&key_pair)?;
+    Ok(Certificate {
+        certificate: vec![cert.der().to_owned()],
+        private_key: CryptoPrivateKey::from_key_pair(&key_pair)?,
+    })
+}
+
+/// extract and verify the key rotation statement of a (DER encoded) certificate.
+/// Returns `None` if there is none, its signature is invalid or
+/// its transition window has ended.
+pub fn verify_key_rotation(cert: &[u8]) -> Option<KeyRotation> {
+    let rotation = parse_key_rotation(cert)?;
+    if rotation.not_after < SystemTime::now() {
+        log::debug!("ignoring expired key rotation statement");
+        return None;
+    }
+    Some(rotation)
+}
+
+/// extract the key rotation statement of a (DER encoded) certificate
+/// and verify its signature without considering the transition window
+pub fn parse_key_rotation(cert: &[u8]) -> Option<KeyRotation> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let oid = Oid::from(KEY_ROTATION_OID).ok()?;
+    let content = cert.extensions().iter().find(|e| e.oid == oid)?.value;
+
+    let (len, content) = content.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if content.len() < len {
+        return None;
+    }
+    let (old_cert, content) = content.split_at(len);
+    let (not_after, signature) = content.split_first_chunk::<8>()?;
+    let not_after = u64::from_be_bytes(*not_after);
+
+    let (_, old) = X509Certificate::from_der(old_cert).ok()?;
+    let old_key = old.public_key();
+    let algorithm: &dyn VerificationAlgorithm = match &old_key.algorithm.algorithm {
+        a if *a == OID_KEY_TYPE_EC_PUBLIC_KEY => &ECDSA_P256_SHA256_ASN1,
+        a if *a == OID_SIG_ED25519 => &ED25519,
+        _ => return None,
+    };
+    let message = rotation_message(cert.public_key().raw, not_after);
+    UnparsedPublicKey::new(algorithm, &old_key.subject_public_key.data)
+        .verify(&message, signature)
+        .ok()?;
+
+    Some(KeyRotation {
+        old_fingerprint: generate_fingerprint(old_cert),
+        not_after: UNIX_EPOCH + Duration::from_secs(not_after),
+    })
+}
+
+fn rotation_message(public_key: &[u8], not_after: u64) -> Vec<u8> {
+    let mut message = KEY_ROTATION_CONTEXT.to_vec();
+    message.extend_from_slice(public_key);
+    message.extend_from_slice(&not_after.to_be_bytes());
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use webrtc_dtls::crypto::Certificate;
+
+    use super::{
+        Error, PKCS8_PRIVATE_KEY_TAG, StoredKey, certificate_fingerprint, decrypt_data,
+        decrypt_key, encrypt_data, encrypt_key, generate_rotated_key_and_cert, parse_key_rotation,
+        read_pem, verify_key_rotation,
+    };
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn generate() -> Certificate {
+        Certificate::generate_self_signed(["ignored".to_owned()]).expect("certificate")
+    }
+
+    #[test]
+    fn key_rotation_round_trip() {
+        let old = generate();
+        let new = generate_rotated_key_and_cert(&old, SystemTime::now() + DAY).unwrap();
+        let rotation = verify_key_rotation(&new.certificate[0]).expect("valid statement");
+        assert_eq!(rotation.old_fingerprint, certificate_fingerprint(&old));
+        /* a certificate without a statement */
+        assert!(verify_key_rotation(&old.certificate[0]).is_none());
+    }
+
+    #[test]
+    fn expired_key_rotation() {
+        let old = generate();
+        let new = generate_rotated_key_and_cert(&old, SystemTime::now() - DAY).unwrap();
+        assert!(verify_key_rotation(&new.certificate[0]).is_none());
+        /* the signature itself is still valid */
+        assert!(parse_key_rotation(&new.certificate[0]).is_some());
+    }
+
+    #[test]
+    fn tampered_key_rotation_signature() {
+        let old = generate();
+        let new = generate_rotated_key_and_cert(&old, SystemTime::now() + DAY).unwrap();
+        let mut der = new.certificate[0].to_vec();
+        /* [u16 len][old certificate][u64 not after][signature] */
+        let old_der = &old.certificate[0];
+        let start = der
+            .windows(old_der.len())
+            .position(|w| w == old_der.as_ref())
+            .expect("embedded certificate");
+        let signature = start + old_der.len() + 8;
+        der[signature + 8] ^= 0x01;
+        assert!(parse_key_rotation(&der).is_none());
+    }
+
+    #[test]
+    fn key_rotation_signed_by_wrong_key() {
+        let old = generate();
+        let other = generate();
+        /* claims to replace `old` but is signed with the key of `other` */
+        let forged = Certificate {
+            certificate: old.certificate.clone(),
+            private_key: other.private_key.clone(),
+        };
+        let new = generate_rotated_key_and_cert(&forged, SystemTime::now() + DAY).unwrap();
+        assert!(parse_key_rotation(&new.certificate[0]).is_none());
+    }
+
+    #[test]
+    fn import_pkcs8_private_key_tag() {
+        let cert = generate();
+        let pems = [
+            pem::Pem::new(
+                PKCS8_PRIVATE_KEY_TAG,
+                cert.private_key.serialized_der.clone(),
+            ),
+            pem::Pem::new("CERTIFICATE", cert.certificate[0].to_vec()),
+        ];
+        let path = std::env::temp_dir().join(format!("lan-mouse-pkcs8-{}.pem", std::process::id()));
+        fs::write(&path, pem::encode_many(&pems)).unwrap();
+        let read = read_pem(&path);
+        fs::remove_file(&path).unwrap();
+        let (key, chain) = read.unwrap();
+        assert!(matches!(key, StoredKey::Plain(der) if der == cert.private_key.serialized_der));
+        assert_eq!(chain, cert.certificate);
+    }
+
+    #[test]
+    fn encrypted_key_round_trip() {
+        let key = generate().private_key.serialized_der;
+        let encrypted = encrypt_key(&key, Some("secret")).unwrap();
+        assert_ne!(encrypted, key);
+        assert_eq!(decrypt_key(&encrypted, Some("secret")).unwrap(), key);
+        assert!(matches!(
+            encrypt_key(&key, Some("")),
+            Err(Error::PassphraseRequired)
+        ));
+        assert!(matches!(
+            decrypt_key(&encrypted, None),
+            Err(Error::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn encrypted_key_wrong_passphrase() {
+        let key = generate().private_key.serialized_der;
+        let encrypted = encrypt_key(&key, Some("secret")).unwrap();
+        assert!(matches!(
+            decrypt_key(&encrypted, Some("wrong")),
+            Err(Error::InvalidPassphrase)
+        ));
+    }
+
+    #[test]
+    fn encrypted_key_corrupted() {
+        let key = generate().private_key.serialized_der;
+        let mut encrypted = encrypt_key(&key, Some("secret")).unwrap();
+        let last = encrypted.len() - 20;
+        encrypted[last] ^= 0xff;
+        assert!(decrypt_key(&encrypted, Some("secret")).is_err());
+        /* truncated */
+        assert!(decrypt_key(&encrypted[..encrypted.len() / 2], Some("secret")).is_err());
+    }
+
+    #[test]
+    fn encrypted_data_round_trip() {
+        let data = b"lan-mouse backup";
+        let encrypted = encrypt_data(data, "secret").unwrap();
+        assert_eq!(decrypt_data(&encrypted, "secret").unwrap(), data);
+        assert_eq!(
+            decrypt_data(&encrypt_data(b"", "secret").unwrap(), "secret").unwrap(),
+            b""
+        );
+        assert!(matches!(
+            encrypt_data(data, ""),
+            Err(Error::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn encrypted_data_wrong_passphrase() {
+        let encrypted = encrypt_data(b"lan-mouse backup", "secret").unwrap();
+        /* checked by the digest even if the padding happens to be valid */
+        for passphrase in ["wrong", "Secret", "secret ", "s"] {
+            assert!(matches!(
+                decrypt_data(&encrypted, passphrase),
+                Err(Error::InvalidPassphrase)
+            ));
+        }
+    }
+
+    #[test]
+    fn encrypted_data_corrupted() {
+        let data = [0x42u8; 100];
+        let encrypted = encrypt_data(&data, "secret").unwrap();
+        /* in the first, a middle and the last cipher block */
+        for i in [
+            encrypted.len() - 112,
+            encrypted.len() - 40,
+            encrypted.len() - 1,
+        ] {
+            let mut corrupted = encrypted.clone();
+            corrupted[i] ^= 0x01;
+            assert!(decrypt_data(&corrupted, "secret").is_err(), "byte {i}");
+        }
+        assert!(decrypt_data(&encrypted[..encrypted.len() - 16], "secret").is_err());
+        assert!(decrypt_data(b"not der", "secret").is_err());
+    }
 }
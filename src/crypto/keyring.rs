@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use secret_service::{EncryptionType, Error, SecretService};
+
+const APPLICATION: &str = "lan-mouse";
+const LABEL: &str = "Lan Mouse private key";
+const CONTENT_TYPE: &str = "application/pkcs8";
+
+fn attributes(fingerprint: &str) -> HashMap<&str, &str> {
+    HashMap::from([("application", APPLICATION), ("fingerprint", fingerprint)])
+}
+
+/// store the (PKCS#8 DER encoded) private key belonging
+/// to the certificate with the given fingerprint
+pub(super) async fn store_key(fingerprint: &str, key: &[u8]) -> Result<(), Error> {
+    let service = SecretService::connect(EncryptionType::Dh).await?;
+    let collection = service.get_default_collection().await?;
+    collection.ensure_unlocked().await?;
+    collection
+        .create_item(LABEL, attributes(fingerprint), key, true, CONTENT_TYPE)
+        .await?;
+    Ok(())
+}
+
+/// look up the private key belonging to the certificate with the given fingerprint
+pub(super) async fn load_key(fingerprint: &str) -> Result<Option<Vec<u8>>, Error> {
+    let service = SecretService::connect(EncryptionType::Dh).await?;
+    let items = service.search_items(attributes(fingerprint)).await?;
+    let Some(item) = items.unlocked.first().or(items.locked.first()) else {
+        return Ok(None);
+    };
+    item.ensure_unlocked().await?;
+    Ok(Some(item.get_secret().await?))
+}
+
+/// remove the private key belonging to the certificate with the given fingerprint
+pub(super) async fn delete_key(fingerprint: &str) -> Result<(), Error> {
+    let service = SecretService::connect(EncryptionType::Dh).await?;
+    let items = service.search_items(attributes(fingerprint)).await?;
+    for item in items.unlocked.iter().chain(items.locked.iter()) {
+        item.delete().await?;
+    }
+    Ok(())
+}
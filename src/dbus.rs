@@ -0,0 +1,197 @@
+//! The `de.feschber.LanMouse1` interface on the session bus.
+//!
+//! Mirrors the requests of the frontend socket as methods and keeps
+//! the state reported by frontend events in properties, so desktop
+//! integrations do not have to speak the JSON protocol.
+
+use lan_mouse_ipc::{FrontendError, FrontendEvent, FrontendRequest, FrontendResponse};
+use tokio::sync::oneshot;
+
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+mod interface;
+
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+use tokio::sync::mpsc;
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+use zbus::{connection, object_server::InterfaceRef};
+
+/// well-known name of the service on the session bus
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+pub(crate) const NAME: &str = "de.feschber.LanMouse1";
+/// path of the object implementing [`NAME`]
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+pub(crate) const PATH: &str = "/de/feschber/LanMouse1";
+
+/// receives the result of a request made by a method call
+pub(crate) type Reply = oneshot::Sender<Result<FrontendResponse, FrontendError>>;
+
+/// the session bus connection of the service
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+pub(crate) struct DbusService {
+    /// the exported interface, `None` without a session bus
+    interface: Option<InterfaceRef<interface::LanMouse>>,
+    /// requests made by method calls
+    requests: mpsc::UnboundedReceiver<(FrontendRequest, Reply)>,
+}
+
+#[cfg(all(unix, feature = "dbus", not(target_os = "macos")))]
+impl DbusService {
+    /// export the interface on the session bus, if there is one
+    pub(crate) async fn new() -> Self {
+        Self::on_bus(connection::Builder::session()).await
+    }
+
+    /// export the interface on the bus `bus` connects to
+    async fn on_bus(bus: zbus::Result<connection::Builder<'_>>) -> Self {
+        let (tx, requests) = mpsc::unbounded_channel();
+        let interface = match Self::export(bus, interface::LanMouse::new(tx)).await {
+            Ok(interface) => {
+                log::info!("D-Bus interface available as {NAME}");
+                Some(interface)
+            }
+            Err(e) => {
+                log::warn!("D-Bus interface not available: {e}");
+                None
+            }
+        };
+        Self {
+            interface,
+            requests,
+        }
+    }
+
+    async fn export(
+        bus: zbus::Result<connection::Builder<'_>>,
+        lan_mouse: interface::LanMouse,
+    ) -> zbus::Result<InterfaceRef<interface::LanMouse>> {
+        let connection = bus?.name(NAME)?.serve_at(PATH, lan_mouse)?.build().await?;
+        connection.object_server().interface(PATH).await
+    }
+
+    /// the next request made by a method call
+    pub(crate) async fn next(&mut self) -> (FrontendRequest, Reply) {
+        match self.requests.recv().await {
+            Some(request) => request,
+            /* the interface keeps the sender */
+            None => std::future::pending().await,
+        }
+    }
+
+    /// update properties and emit signals for a frontend event
+    pub(crate) async fn broadcast(&self, event: &FrontendEvent) {
+        let Some(interface) = &self.interface else {
+            return;
+        };
+        if let Err(e) = interface::update(interface, event).await {
+            log::warn!("failed to emit D-Bus signal: {e}");
+        }
+    }
+}
+
+/// No-op session bus connection (when the `dbus` feature is disabled)
+#[cfg(not(all(unix, feature = "dbus", not(target_os = "macos"))))]
+pub(crate) struct DbusService;
+
+#[cfg(not(all(unix, feature = "dbus", not(target_os = "macos"))))]
+impl DbusService {
+    pub(crate) async fn new() -> Self {
+        Self
+    }
+
+    /// never returns
+    pub(crate) async fn next(&mut self) -> (FrontendRequest, Reply) {
+        std::future::pending().await
+    }
+
+    pub(crate) async fn broadcast(&self, _event: &FrontendEvent) {}
+}
+
+#[cfg(all(test, unix, feature = "dbus", not(target_os = "macos")))]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use futures::StreamExt;
+    use lan_mouse_ipc::{FrontendEvent, FrontendRequest, FrontendResponse};
+    use zbus::{Proxy, connection};
+
+    use super::{DbusService, NAME, PATH};
+
+    /// a private session bus, terminated when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .inspect_err(|e| eprintln!("skipping, dbus-daemon not available: {e}"))
+                .ok()?;
+            let stdout = daemon.stdout.take().expect("stdout");
+            let mut address = String::new();
+            BufReader::new(stdout).read_line(&mut address).ok()?;
+            let address = address.trim().to_owned();
+            Some(Self { daemon, address })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn method_call_and_property_change() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let mut service = DbusService::on_bus(connection::Builder::address(&*bus.address)).await;
+        assert!(service.interface.is_some(), "interface not exported");
+
+        let client = connection::Builder::address(&*bus.address)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = Proxy::new(&client, NAME, PATH, NAME).await.unwrap();
+
+        /* a method call is handed to the service as a request */
+        let call = proxy.call::<_, _, u64>("Create", &());
+        let serve = async {
+            let (request, reply) = service.next().await;
+            assert_eq!(request, FrontendRequest::Create);
+            reply.send(Ok(FrontendResponse::Created(7))).unwrap();
+        };
+        let (handle, ()) = tokio::join!(call, serve);
+        assert_eq!(handle.unwrap(), 7);
+
+        /* a frontend event updates the property and emits PropertiesChanged */
+        let mut port_changed = proxy.receive_property_changed::<u16>("Port").await;
+        service
+            .broadcast(&FrontendEvent::PortChanged {
+                port: 4243,
+                error: None,
+            })
+            .await;
+        let changed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let change = port_changed.next().await.expect("property stream closed");
+                if change.get().await.unwrap() == 4243 {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(changed.is_ok(), "no PropertiesChanged signal for Port");
+    }
+}
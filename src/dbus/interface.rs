@@ -0,0 +1,571 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use lan_mouse_ipc::{
+    ClientConfig, ClientHandle, ClientState, ConnectionStats, FrontendError, FrontendEvent,
+    FrontendRequest, FrontendResponse, MacAddress, Position, Status,
+};
+use tokio::sync::{mpsc, oneshot};
+use zbus::{
+    DBusError, interface,
+    object_server::{InterfaceRef, SignalEmitter},
+    zvariant::{OwnedValue, Value},
+};
+
+use super::Reply;
+
+/// errors returned by method calls
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "de.feschber.LanMouse1.Error")]
+pub(crate) enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    NoSuchClient(String),
+    NoSuchProfile(String),
+    NoSuchKey(String),
+    PortChange(String),
+    InvalidArgs(String),
+    Failed(String),
+}
+
+impl From<FrontendError> for Error {
+    fn from(e: FrontendError) -> Self {
+        let message = e.to_string();
+        match e {
+            FrontendError::NoSuchClient(_) => Self::NoSuchClient(message),
+            FrontendError::NoSuchProfile(_) => Self::NoSuchProfile(message),
+            FrontendError::NoSuchKey(_) => Self::NoSuchKey(message),
+            FrontendError::PortChange(_) => Self::PortChange(message),
+            FrontendError::InvalidReleaseBind(_) => Self::InvalidArgs(message),
+            _ => Self::Failed(message),
+        }
+    }
+}
+
+/// properties of a client or connection, see [`client_properties`] and [`stats_properties`]
+type Properties = HashMap<String, OwnedValue>;
+
+/// the state reported by frontend events
+#[derive(Default)]
+struct State {
+    clients: BTreeMap<ClientHandle, (ClientConfig, ClientState)>,
+    capture: Status,
+    emulation: Status,
+    controlling: Option<ClientHandle>,
+    port: u16,
+    fingerprint: String,
+    /// fingerprints of connected devices by address
+    devices: HashMap<SocketAddr, String>,
+    authorized: HashMap<String, String>,
+    blocked_keys: Vec<String>,
+    blocked_ips: Vec<String>,
+    profile: String,
+    profiles: Vec<String>,
+    release_bind: Vec<String>,
+}
+
+pub(crate) struct LanMouse {
+    requests: mpsc::UnboundedSender<(FrontendRequest, Reply)>,
+    state: Mutex<State>,
+}
+
+impl LanMouse {
+    pub(crate) fn new(requests: mpsc::UnboundedSender<(FrontendRequest, Reply)>) -> Self {
+        Self {
+            requests,
+            state: Default::default(),
+        }
+    }
+
+    /// hand `request` to the service and wait for its result
+    async fn request(&self, request: FrontendRequest) -> Result<FrontendResponse, Error> {
+        let (tx, rx) = oneshot::channel();
+        let terminated = || Error::Failed("the service is terminating".into());
+        self.requests
+            .send((request, tx))
+            .map_err(|_| terminated())?;
+        Ok(rx.await.map_err(|_| terminated())??)
+    }
+
+    async fn expect_done(&self, request: FrontendRequest) -> Result<(), Error> {
+        match self.request(request).await? {
+            FrontendResponse::Done => Ok(()),
+            r => Err(Error::Failed(format!("unexpected response: {r:?}"))),
+        }
+    }
+
+    async fn expect_profile(&self, request: FrontendRequest) -> Result<String, Error> {
+        match self.request(request).await? {
+            FrontendResponse::Profile(profile) => Ok(profile),
+            r => Err(Error::Failed(format!("unexpected response: {r:?}"))),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("lock")
+    }
+}
+
+/// `FrontendRequest`s without a counterpart:
+/// `Hello` (the interface name is versioned), `Sync` and `Enumerate`
+/// (the state is available as properties) and `Unlock`
+/// (the interface is only exported once the private key is unlocked)
+#[interface(name = "de.feschber.LanMouse1")]
+impl LanMouse {
+    async fn activate(&self, handle: ClientHandle, active: bool) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::Activate { handle, active })
+            .await
+    }
+
+    /// create a client with default configuration, returns its handle
+    async fn create(&self) -> Result<ClientHandle, Error> {
+        match self.request(FrontendRequest::Create).await? {
+            FrontendResponse::Created(handle) => Ok(handle),
+            r => Err(Error::Failed(format!("unexpected response: {r:?}"))),
+        }
+    }
+
+    async fn delete(&self, handle: ClientHandle) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::Delete(handle)).await
+    }
+
+    /// change the listen port, returns the new port once it is in use
+    async fn change_port(&self, port: u16) -> Result<u16, Error> {
+        match self.request(FrontendRequest::ChangePort(port)).await? {
+            FrontendResponse::Port(port) => Ok(port),
+            r => Err(Error::Failed(format!("unexpected response: {r:?}"))),
+        }
+    }
+
+    async fn resolve_dns(&self, handle: ClientHandle) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::ResolveDns(handle)).await
+    }
+
+    /// an empty `hostname` removes it
+    async fn update_hostname(&self, handle: ClientHandle, hostname: String) -> Result<(), Error> {
+        let hostname = non_empty(hostname);
+        self.expect_done(FrontendRequest::UpdateHostname { handle, hostname })
+            .await
+    }
+
+    async fn update_port(&self, handle: ClientHandle, port: u16) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::UpdatePort { handle, port })
+            .await
+    }
+
+    /// `position` is one of `left`, `right`, `top` and `bottom`
+    async fn update_position(&self, handle: ClientHandle, position: String) -> Result<(), Error> {
+        let position = parse::<Position>(&position)?;
+        self.expect_done(FrontendRequest::UpdatePosition { handle, position })
+            .await
+    }
+
+    async fn update_fix_ips(&self, handle: ClientHandle, ips: Vec<String>) -> Result<(), Error> {
+        let ips = ips
+            .iter()
+            .map(|ip| parse::<IpAddr>(ip))
+            .collect::<Result<_, _>>()?;
+        self.expect_done(FrontendRequest::UpdateFixIps { handle, ips })
+            .await
+    }
+
+    /// an empty `enter_hook` removes it
+    async fn update_enter_hook(
+        &self,
+        handle: ClientHandle,
+        enter_hook: String,
+    ) -> Result<(), Error> {
+        let enter_hook = non_empty(enter_hook);
+        self.expect_done(FrontendRequest::UpdateEnterHook { handle, enter_hook })
+            .await
+    }
+
+    /// an empty `mac` removes it
+    async fn update_mac_address(&self, handle: ClientHandle, mac: String) -> Result<(), Error> {
+        let mac = non_empty(mac)
+            .map(|mac| parse::<MacAddress>(&mac))
+            .transpose()?;
+        self.expect_done(FrontendRequest::UpdateMacAddress { handle, mac })
+            .await
+    }
+
+    async fn enable_capture(&self) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::EnableCapture).await
+    }
+
+    async fn enable_emulation(&self) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::EnableEmulation).await
+    }
+
+    async fn authorize_key(&self, description: String, fingerprint: String) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::AuthorizeKey {
+            description,
+            fingerprint,
+        })
+        .await
+    }
+
+    async fn remove_authorized_key(&self, fingerprint: String) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::RemoveAuthorizedKey(fingerprint))
+            .await
+    }
+
+    async fn block_key(&self, fingerprint: String) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::BlockKey(fingerprint))
+            .await
+    }
+
+    async fn unblock_key(&self, fingerprint: String) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::UnblockKey(fingerprint))
+            .await
+    }
+
+    async fn block_ip(&self, ip: String) -> Result<(), Error> {
+        let ip = parse::<IpAddr>(&ip)?;
+        self.expect_done(FrontendRequest::BlockIp(ip)).await
+    }
+
+    async fn unblock_ip(&self, ip: String) -> Result<(), Error> {
+        let ip = parse::<IpAddr>(&ip)?;
+        self.expect_done(FrontendRequest::UnblockIp(ip)).await
+    }
+
+    /// connection statistics of clients (by handle) and incoming devices (by fingerprint)
+    #[allow(clippy::type_complexity)]
+    async fn get_stats(
+        &self,
+    ) -> Result<(Vec<(ClientHandle, Properties)>, Vec<(String, Properties)>), Error> {
+        match self.request(FrontendRequest::GetStats).await? {
+            FrontendResponse::Stats { clients, incoming } => Ok((
+                clients
+                    .iter()
+                    .map(|(h, s)| (*h, stats_properties(s)))
+                    .collect(),
+                incoming
+                    .iter()
+                    .map(|(fp, s)| (fp.clone(), stats_properties(s)))
+                    .collect(),
+            )),
+            r => Err(Error::Failed(format!("unexpected response: {r:?}"))),
+        }
+    }
+
+    /// switch to a layout profile, returns the active profile
+    async fn switch_profile(&self, profile: String) -> Result<String, Error> {
+        self.expect_profile(FrontendRequest::SwitchProfile(profile))
+            .await
+    }
+
+    /// report the current network, returns the profile selected for it
+    async fn set_network(&self, network: String) -> Result<String, Error> {
+        self.expect_profile(FrontendRequest::SetNetwork(network))
+            .await
+    }
+
+    /// change the key combination releasing the captured input
+    async fn set_release_bind(&self, keys: Vec<String>) -> Result<(), Error> {
+        self.expect_done(FrontendRequest::SetReleaseBind(keys))
+            .await
+    }
+
+    /// all clients with their configuration and state
+    #[zbus(property)]
+    fn clients(&self) -> Vec<(ClientHandle, Properties)> {
+        self.state()
+            .clients
+            .iter()
+            .map(|(h, (c, s))| (*h, client_properties(c, s)))
+            .collect()
+    }
+
+    #[zbus(property)]
+    fn capture_enabled(&self) -> bool {
+        self.state().capture.into()
+    }
+
+    #[zbus(property)]
+    fn emulation_enabled(&self) -> bool {
+        self.state().emulation.into()
+    }
+
+    /// the client currently receiving input, empty if input is not captured
+    #[zbus(property)]
+    fn controlling(&self) -> Vec<ClientHandle> {
+        self.state().controlling.into_iter().collect()
+    }
+
+    #[zbus(property)]
+    fn port(&self) -> u16 {
+        self.state().port
+    }
+
+    /// public key fingerprint of this device
+    #[zbus(property)]
+    fn fingerprint(&self) -> String {
+        self.state().fingerprint.clone()
+    }
+
+    /// fingerprints of connected devices by address
+    #[zbus(property)]
+    fn connected_devices(&self) -> HashMap<String, String> {
+        self.state()
+            .devices
+            .iter()
+            .map(|(addr, fp)| (addr.to_string(), fp.clone()))
+            .collect()
+    }
+
+    /// descriptions of authorized fingerprints
+    #[zbus(property)]
+    fn authorized_keys(&self) -> HashMap<String, String> {
+        self.state().authorized.clone()
+    }
+
+    #[zbus(property)]
+    fn blocked_keys(&self) -> Vec<String> {
+        self.state().blocked_keys.clone()
+    }
+
+    #[zbus(property)]
+    fn blocked_ips(&self) -> Vec<String> {
+        self.state().blocked_ips.clone()
+    }
+
+    /// the active layout profile
+    #[zbus(property)]
+    fn profile(&self) -> String {
+        self.state().profile.clone()
+    }
+
+    #[zbus(property)]
+    fn profiles(&self) -> Vec<String> {
+        self.state().profiles.clone()
+    }
+
+    /// the key combination releasing the captured input
+    #[zbus(property)]
+    fn release_bind(&self) -> Vec<String> {
+        self.state().release_bind.clone()
+    }
+
+    #[zbus(signal)]
+    async fn client_created(emitter: &SignalEmitter<'_>, handle: ClientHandle) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn client_deleted(emitter: &SignalEmitter<'_>, handle: ClientHandle) -> zbus::Result<()>;
+
+    /// an incoming device entered this screen
+    #[zbus(signal)]
+    async fn device_entered(
+        emitter: &SignalEmitter<'_>,
+        fingerprint: &str,
+        address: &str,
+        position: &str,
+    ) -> zbus::Result<()>;
+
+    /// a device with an unknown fingerprint tried to connect
+    #[zbus(signal)]
+    async fn connection_attempt(emitter: &SignalEmitter<'_>, fingerprint: &str)
+    -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn error(emitter: &SignalEmitter<'_>, message: &str) -> zbus::Result<()>;
+}
+
+/// update the properties of the interface and emit signals for `event`
+pub(crate) async fn update(
+    interface: &InterfaceRef<LanMouse>,
+    event: &FrontendEvent,
+) -> zbus::Result<()> {
+    let emitter = interface.signal_emitter();
+    let lan_mouse = interface.get().await;
+    match event {
+        FrontendEvent::Created {
+            handle,
+            config,
+            state,
+        } => {
+            let client = (config.clone(), state.clone());
+            lan_mouse.state().clients.insert(*handle, client);
+            lan_mouse.clients_changed(emitter).await?;
+            LanMouse::client_created(emitter, *handle).await?;
+        }
+        FrontendEvent::State {
+            handle,
+            config,
+            state,
+        } => {
+            let client = (config.clone(), state.clone());
+            lan_mouse.state().clients.insert(*handle, client);
+            lan_mouse.clients_changed(emitter).await?;
+        }
+        FrontendEvent::Deleted(handle) => {
+            lan_mouse.state().clients.remove(handle);
+            lan_mouse.clients_changed(emitter).await?;
+            LanMouse::client_deleted(emitter, *handle).await?;
+        }
+        FrontendEvent::PortChanged { port, error } => {
+            lan_mouse.state().port = *port;
+            lan_mouse.port_changed(emitter).await?;
+            if let Some(e) = error {
+                LanMouse::error(emitter, &format!("failed to change port: {e}")).await?;
+            }
+        }
+        FrontendEvent::Enumerate(clients) => {
+            lan_mouse.state().clients = clients
+                .iter()
+                .map(|(h, c, s)| (*h, (c.clone(), s.clone())))
+                .collect();
+            lan_mouse.clients_changed(emitter).await?;
+        }
+        FrontendEvent::Error(e) => LanMouse::error(emitter, e).await?,
+        FrontendEvent::CaptureStatus(status) => {
+            lan_mouse.state().capture = *status;
+            lan_mouse.capture_enabled_changed(emitter).await?;
+        }
+        FrontendEvent::EmulationStatus(status) => {
+            lan_mouse.state().emulation = *status;
+            lan_mouse.emulation_enabled_changed(emitter).await?;
+        }
+        FrontendEvent::Controlling(handle) => {
+            lan_mouse.state().controlling = *handle;
+            lan_mouse.controlling_changed(emitter).await?;
+        }
+        FrontendEvent::AuthorizedUpdated(keys) => {
+            lan_mouse.state().authorized = keys.clone();
+            lan_mouse.authorized_keys_changed(emitter).await?;
+        }
+        FrontendEvent::PublicKeyFingerprint(fingerprint) => {
+            lan_mouse.state().fingerprint = fingerprint.clone();
+            lan_mouse.fingerprint_changed(emitter).await?;
+        }
+        FrontendEvent::DeviceConnected { addr, fingerprint } => {
+            lan_mouse.state().devices.insert(*addr, fingerprint.clone());
+            lan_mouse.connected_devices_changed(emitter).await?;
+        }
+        FrontendEvent::DeviceEntered {
+            fingerprint,
+            addr,
+            pos,
+        } => {
+            let known = lan_mouse.state().devices.insert(*addr, fingerprint.clone());
+            if known.as_ref() != Some(fingerprint) {
+                lan_mouse.connected_devices_changed(emitter).await?;
+            }
+            let (addr, pos) = (addr.to_string(), pos.to_string());
+            LanMouse::device_entered(emitter, fingerprint, &addr, &pos).await?;
+        }
+        FrontendEvent::IncomingDisconnected(addr) => {
+            lan_mouse.state().devices.remove(addr);
+            lan_mouse.connected_devices_changed(emitter).await?;
+        }
+        FrontendEvent::ConnectionAttempt { fingerprint } => {
+            LanMouse::connection_attempt(emitter, fingerprint).await?;
+        }
+        /* the interface is exported once the private key is unlocked */
+        FrontendEvent::PassphraseRequired => {}
+        FrontendEvent::BlockedUpdated { fingerprints, ips } => {
+            {
+                let mut state = lan_mouse.state();
+                state.blocked_keys = fingerprints.iter().cloned().collect();
+                state.blocked_keys.sort();
+                state.blocked_ips = ips.iter().map(|ip| ip.to_string()).collect();
+                state.blocked_ips.sort();
+            }
+            lan_mouse.blocked_keys_changed(emitter).await?;
+            lan_mouse.blocked_ips_changed(emitter).await?;
+        }
+        FrontendEvent::ProfileChanged { active, profiles } => {
+            {
+                let mut state = lan_mouse.state();
+                state.profile = active.clone();
+                state.profiles = profiles.clone();
+            }
+            lan_mouse.profile_changed(emitter).await?;
+            lan_mouse.profiles_changed(emitter).await?;
+        }
+        FrontendEvent::ReleaseBind(keys) => {
+            lan_mouse.state().release_bind = keys.clone();
+            lan_mouse.release_bind_changed(emitter).await?;
+        }
+    }
+    Ok(())
+}
+
+/// configuration and state of a client, named like in the config file
+fn client_properties(config: &ClientConfig, state: &ClientState) -> Properties {
+    let mut properties = Properties::new();
+    let mut insert = |key: &str, value: Value<'_>| {
+        let value = value.try_to_owned().expect("no file descriptors");
+        properties.insert(key.to_owned(), value);
+    };
+    if let Some(hostname) = &config.hostname {
+        insert("hostname", hostname.as_str().into());
+    }
+    insert("ips", strings(&config.fix_ips).into());
+    insert("port", config.port.into());
+    insert("position", config.pos.to_string().into());
+    if let Some(enter_hook) = &config.cmd {
+        insert("enter_hook", enter_hook.as_str().into());
+    }
+    if let Some(mac) = &config.mac {
+        insert("mac_address", mac.to_string().into());
+    }
+    insert("active", state.active.into());
+    if let Some(addr) = state.active_addr {
+        insert("active_addr", addr.to_string().into());
+    }
+    insert("alive", state.alive.into());
+    insert("dns_ips", strings(&state.dns_ips).into());
+    insert("resolving", state.resolving.into());
+    properties
+}
+
+fn stats_properties(stats: &ConnectionStats) -> Properties {
+    let mut properties = Properties::new();
+    let mut insert = |key: &str, value: Value<'_>| {
+        let value = value.try_to_owned().expect("no file descriptors");
+        properties.insert(key.to_owned(), value);
+    };
+    insert("state", stats.state.to_string().into());
+    if let Some(addr) = stats.addr {
+        insert("addr", addr.to_string().into());
+    }
+    insert("events_sent", counts(&stats.events_sent).into());
+    insert("events_received", counts(&stats.events_received).into());
+    insert("bytes_sent", stats.bytes_sent.into());
+    insert("bytes_received", stats.bytes_received.into());
+    insert("datagrams_sent", stats.datagrams_sent.into());
+    insert("datagrams_received", stats.datagrams_received.into());
+    insert("send_errors", stats.send_errors.into());
+    insert("reconnects", stats.reconnects.into());
+    if let Some(since) = stats.since_last_event {
+        let millis = since.as_millis() as u64;
+        insert("since_last_event_ms", millis.into());
+    }
+    properties
+}
+
+fn counts(events: &BTreeMap<String, u64>) -> HashMap<String, u64> {
+    events.iter().map(|(k, n)| (k.clone(), *n)).collect()
+}
+
+fn strings(ips: &[IpAddr]) -> Vec<String> {
+    ips.iter().map(|ip| ip.to_string()).collect()
+}
+
+fn non_empty(s: String) -> Option<String> {
+    Some(s).filter(|s| !s.is_empty())
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    s.parse()
+        .map_err(|e: T::Err| Error::InvalidArgs(e.to_string()))
+}
@@ -31,6 +31,11 @@ pub(crate) enum EmulationEvent {
     ConnectionAttempt {
         fingerprint: String,
     },
+    /// an authorized key was replaced by its successor
+    KeyRotated {
+        old_fingerprint: String,
+        fingerprint: String,
+    },
     /// new connection
     Entered {
         /// address of the connection
@@ -162,6 +167,9 @@ impl ListenTask {
                                 self.event_tx.send(EmulationEvent::ConnectionAttempt { fingerprint }).expect("channel closed");
                             }
                     }
+                    Some(ListenEvent::KeyRotated { old_fingerprint, fingerprint }) => {
+                        self.event_tx.send(EmulationEvent::KeyRotated { old_fingerprint, fingerprint }).expect("channel closed");
+                    }
                     None => break
                 }}
                 event = self.emulation_proxy.event() => {
@@ -1,12 +1,19 @@
+mod access;
+pub mod backup;
 mod capture;
 pub mod capture_test;
+pub mod cert;
 pub mod client;
 pub mod config;
 mod connect;
 mod crypto;
+mod dbus;
 mod dns;
 mod emulation;
 pub mod emulation_test;
 mod listen;
+mod management;
 pub mod screen_share;
 pub mod service;
+mod stats;
+mod wol;
@@ -3,7 +3,7 @@ use lan_mouse_proto::{MAX_EVENT_SIZE, ProtoEvent};
 use local_channel::mpsc::{Receiver, Sender, channel};
 use rustls::pki_types::CertificateDer;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::HashMap,
     net::SocketAddr,
     rc::Rc,
     sync::{Arc, Mutex, RwLock},
@@ -18,18 +18,22 @@ use webrtc_dtls::{
     config::{ClientAuthType::RequireAnyClientCert, Config, ExtendedMasterSecretType},
     conn::DTLSConn,
     crypto::Certificate,
-    listener::listen,
 };
-use webrtc_util::{Conn, Error, conn::Listener};
+use webrtc_util::{
+    Conn, Error,
+    conn::{Listener, conn_udp_listener::ListenConfig},
+};
 
-use crate::crypto;
+use crate::{
+    access::AccessControl,
+    crypto,
+    stats::{Peer, Stats},
+};
 
 #[derive(Error, Debug)]
 pub enum ListenerCreationError {
     #[error(transparent)]
     WebrtcUtil(#[from] webrtc_util::Error),
-    #[error(transparent)]
-    WebrtcDtls(#[from] webrtc_dtls::Error),
 }
 
 type ArcConn = Arc<dyn Conn + Send + Sync>;
@@ -46,6 +50,12 @@ pub(crate) enum ListenEvent {
     Rejected {
         fingerprint: String,
     },
+    /// an authorized key was replaced by a key
+    /// presenting a valid rotation statement
+    KeyRotated {
+        old_fingerprint: String,
+        fingerprint: String,
+    },
 }
 
 pub(crate) struct LanMouseListener {
@@ -55,6 +65,7 @@ pub(crate) struct LanMouseListener {
     conns: Rc<AsyncMutex<Vec<(SocketAddr, ArcConn)>>>,
     request_port_change: Sender<u16>,
     port_changed: Receiver<Result<u16, ListenerCreationError>>,
+    stats: Stats,
 }
 
 type VerifyPeerCertificateFn = Arc<
@@ -63,103 +74,66 @@ type VerifyPeerCertificateFn = Arc<
         + Sync,
 >;
 
+/// DTLS content type of handshake records
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+
+/// time after which an incomplete handshake is aborted
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl LanMouseListener {
     pub(crate) async fn new(
         port: u16,
         cert: Certificate,
         authorized_keys: Arc<RwLock<HashMap<String, String>>>,
+        access: Arc<Mutex<AccessControl>>,
+        stats: Stats,
     ) -> Result<Self, ListenerCreationError> {
         let (listen_tx, listen_rx) = channel();
         let (request_port_change, mut request_port_change_rx) = channel();
         let (port_changed_tx, port_changed) = channel();
-        let connection_attempts: Arc<Mutex<VecDeque<String>>> = Default::default();
 
-        let authorized = authorized_keys.clone();
-        let verify_peer_certificate: Option<VerifyPeerCertificateFn> = {
-            let connection_attempts = connection_attempts.clone();
-            Some(Arc::new(
-                move |certs: &[Vec<u8>], _chains: &[CertificateDer<'static>]| {
-                    assert!(certs.len() == 1);
-                    let fingerprints = certs
-                        .iter()
-                        .map(|c| crypto::generate_fingerprint(c))
-                        .collect::<Vec<_>>();
-                    if authorized
-                        .read()
-                        .expect("lock")
-                        .contains_key(&fingerprints[0])
-                    {
-                        Ok(())
-                    } else {
-                        let fingerprint = fingerprints.into_iter().next().expect("fingerprint");
-                        connection_attempts
-                            .lock()
-                            .expect("lock")
-                            .push_back(fingerprint);
-                        Err(webrtc_dtls::Error::ErrVerifyDataMismatch)
-                    }
-                },
-            ))
-        };
         let cfg = Config {
             certificates: vec![cert.clone()],
             extended_master_secret: ExtendedMasterSecretType::Require,
             client_auth: RequireAnyClientCert,
-            verify_peer_certificate,
             ..Default::default()
         };
 
-        let listen_addr = SocketAddr::new("0.0.0.0".parse().expect("invalid ip"), port);
-        let mut listener = listen(listen_addr, cfg.clone()).await?;
+        let mut listener = bind(port).await?;
 
         let conns: Rc<AsyncMutex<Vec<(SocketAddr, ArcConn)>>> =
             Rc::new(AsyncMutex::new(Vec::new()));
 
         let conns_clone = conns.clone();
+        let stats_clone = stats.clone();
         let listen_task: JoinHandle<()> = {
             let listen_tx = listen_tx.clone();
-            let connection_attempts = connection_attempts.clone();
             spawn_local(async move {
                 loop {
-                    let sleep = tokio::time::sleep(Duration::from_secs(2));
                     tokio::select! {
-                        /* workaround for https://github.com/webrtc-rs/webrtc/issues/614 */
-                        _ = sleep => continue,
                         c = listener.accept() => match c {
                             Ok((conn, addr)) => {
-                                log::info!("dtls client connected, ip: {addr}");
-                                let mut conns = conns_clone.lock().await;
-                                conns.push((addr, conn.clone()));
-                                let dtls_conn: &DTLSConn = conn.as_any().downcast_ref().expect("dtls conn");
-                                let certs = dtls_conn.connection_state().await.peer_certificates;
-                                let cert = certs.first().expect("cert");
-                                let fingerprint = crypto::generate_fingerprint(cert);
-                                listen_tx.send(ListenEvent::Accept { addr, fingerprint }).expect("channel closed");
-                                spawn_local(read_loop(conns_clone.clone(), addr, conn, listen_tx.clone()));
-                            },
-                            Err(e) => {
-                                if let Error::Std(ref e) = e {
-                                    if let Some(e) = e.0.downcast_ref::<webrtc_dtls::Error>() {
-                                        match e {
-                                            webrtc_dtls::Error::ErrVerifyDataMismatch => {
-                                                if let Some(fingerprint) = connection_attempts.lock().expect("lock").pop_front() {
-                                                    listen_tx.send(ListenEvent::Rejected { fingerprint }).expect("channel closed");
-                                                }
-                                            }
-                                            _ => log::warn!("accept: {e}"),
-                                        }
-                                    } else {
-                                        log::warn!("accept: {e:?}");
-                                    }
-                                } else {
-                                    log::warn!("accept: {e:?}");
+                                let checked = access.lock().expect("lock").check_handshake(addr.ip());
+                                if let Err(e) = checked {
+                                    log::debug!("{addr}: dropping handshake: {e}");
+                                    let _ = conn.close().await;
+                                    continue;
                                 }
+                                let handshake = Handshake {
+                                    conn,
+                                    addr,
+                                    cfg: cfg.clone(),
+                                    authorized: authorized_keys.clone(),
+                                    access: access.clone(),
+                                    stats: stats_clone.clone(),
+                                };
+                                spawn_local(handshake.run(conns_clone.clone(), listen_tx.clone()));
                             }
+                            Err(e) => log::warn!("accept: {e:?}"),
                         },
                         port = request_port_change_rx.recv() => {
                             let port = port.expect("channel closed");
-                            let listen_addr = SocketAddr::new("0.0.0.0".parse().expect("invalid ip"), port);
-                            match listen(listen_addr, cfg.clone()).await {
+                            match bind(port).await {
                                 Ok(new_listener) => {
                                     let _ = listener.close().await;
                                     listener = new_listener;
@@ -183,6 +157,7 @@ impl LanMouseListener {
             listen_task,
             port_changed,
             request_port_change,
+            stats,
         })
     }
 
@@ -209,7 +184,11 @@ impl LanMouseListener {
         let conns = self.conns.lock().await;
         for (a, conn) in conns.iter() {
             if *a == addr {
-                let _ = conn.send(&buf[..len]).await;
+                let peer = self.stats.incoming(addr);
+                match conn.send(&buf[..len]).await {
+                    Ok(_) => peer.map(|p| self.stats.sent(p, &event, len)),
+                    Err(_) => peer.map(|p| self.stats.send_error(p)),
+                };
             }
         }
     }
@@ -245,16 +224,149 @@ impl Stream for LanMouseListener {
     }
 }
 
+/// udp listener handing out a connection for every new peer sending a handshake
+async fn bind(port: u16) -> Result<impl Listener, webrtc_util::Error> {
+    let mut cfg = ListenConfig {
+        accept_filter: Some(Box::new(|packet: &[u8]| {
+            let handshake = packet.first() == Some(&CONTENT_TYPE_HANDSHAKE);
+            Box::pin(async move { handshake })
+        })),
+        ..Default::default()
+    };
+    let listen_addr = SocketAddr::new("0.0.0.0".parse().expect("invalid ip"), port);
+    cfg.listen(listen_addr).await
+}
+
+/// an incoming DTLS handshake
+struct Handshake {
+    conn: ArcConn,
+    addr: SocketAddr,
+    cfg: Config,
+    authorized: Arc<RwLock<HashMap<String, String>>>,
+    access: Arc<Mutex<AccessControl>>,
+    stats: Stats,
+}
+
+impl Handshake {
+    async fn run(
+        mut self,
+        conns: Rc<AsyncMutex<Vec<(SocketAddr, ArcConn)>>>,
+        listen_tx: Sender<ListenEvent>,
+    ) {
+        let addr = self.addr;
+        /* event resulting from the certificate verification */
+        let outcome: Arc<Mutex<Option<ListenEvent>>> = Default::default();
+        self.cfg.verify_peer_certificate = Some(self.verify_peer_certificate(outcome.clone()));
+        let handshake = DTLSConn::new(self.conn.clone(), self.cfg, false, None);
+        let result = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await;
+        let outcome = outcome.lock().expect("lock").take();
+        let dtls_conn = match result {
+            Ok(Ok(c)) => c,
+            Ok(Err(webrtc_dtls::Error::ErrVerifyDataMismatch)) => {
+                self.access
+                    .lock()
+                    .expect("lock")
+                    .record_rejection(addr.ip());
+                if let Some(event) = outcome {
+                    listen_tx.send(event).expect("channel closed");
+                }
+                let _ = self.conn.close().await;
+                return;
+            }
+            Ok(Err(e)) => {
+                log::warn!("{addr}: handshake failed: {e}");
+                let _ = self.conn.close().await;
+                return;
+            }
+            Err(_) => {
+                log::warn!("{addr}: handshake timed out");
+                let _ = self.conn.close().await;
+                return;
+            }
+        };
+        if let Some(event) = outcome {
+            listen_tx.send(event).expect("channel closed");
+        }
+        log::info!("dtls client connected, ip: {addr}");
+        let certs = dtls_conn.connection_state().await.peer_certificates;
+        let cert = certs.first().expect("cert");
+        let fingerprint = crypto::generate_fingerprint(cert);
+        let conn: ArcConn = Arc::new(dtls_conn);
+        conns.lock().await.push((addr, conn.clone()));
+        self.stats
+            .connected(Peer::Incoming(fingerprint.clone()), addr);
+        listen_tx
+            .send(ListenEvent::Accept { addr, fingerprint })
+            .expect("channel closed");
+        spawn_local(read_loop(conns, addr, conn, listen_tx, self.stats));
+    }
+
+    fn verify_peer_certificate(
+        &self,
+        outcome: Arc<Mutex<Option<ListenEvent>>>,
+    ) -> VerifyPeerCertificateFn {
+        let addr = self.addr;
+        let authorized = self.authorized.clone();
+        let access = self.access.clone();
+        Arc::new(
+            move |certs: &[Vec<u8>], _chains: &[CertificateDer<'static>]| {
+                assert!(certs.len() == 1);
+                let fingerprint = crypto::generate_fingerprint(&certs[0]);
+                if access
+                    .lock()
+                    .expect("lock")
+                    .is_blocked_fingerprint(&fingerprint)
+                {
+                    log::info!("{addr}: rejected blocked key {fingerprint}");
+                    return Err(webrtc_dtls::Error::ErrVerifyDataMismatch);
+                }
+                if authorized.read().expect("lock").contains_key(&fingerprint) {
+                    return Ok(());
+                }
+                // a previously authorized key may have been replaced
+                if let Some(rotation) = crypto::verify_key_rotation(&certs[0]) {
+                    let mut authorized = authorized.write().expect("lock");
+                    if let Some(desc) = authorized.remove(&rotation.old_fingerprint) {
+                        log::info!(
+                            "{desc}: key rotated {} -> {fingerprint}",
+                            rotation.old_fingerprint
+                        );
+                        authorized.insert(fingerprint.clone(), desc);
+                        outcome
+                            .lock()
+                            .expect("lock")
+                            .replace(ListenEvent::KeyRotated {
+                                old_fingerprint: rotation.old_fingerprint,
+                                fingerprint,
+                            });
+                        return Ok(());
+                    }
+                }
+                outcome
+                    .lock()
+                    .expect("lock")
+                    .replace(ListenEvent::Rejected { fingerprint });
+                Err(webrtc_dtls::Error::ErrVerifyDataMismatch)
+            },
+        )
+    }
+}
+
 async fn read_loop(
     conns: Rc<AsyncMutex<Vec<(SocketAddr, ArcConn)>>>,
     addr: SocketAddr,
     conn: ArcConn,
     dtls_tx: Sender<ListenEvent>,
+    stats: Stats,
 ) -> Result<(), Error> {
     let mut b = [0u8; MAX_EVENT_SIZE];
 
-    while conn.recv(&mut b).await.is_ok() {
-        match b.try_into() {
+    while let Ok(len) = conn.recv(&mut b).await {
+        let event: Result<ProtoEvent, _> = b.try_into();
+        if let Some(peer) = stats.incoming(addr) {
+            stats.received(peer, event.as_ref().ok(), len);
+        }
+        match event {
             Ok(event) => dtls_tx
                 .send(ListenEvent::Msg { event, addr })
                 .expect("channel closed"),
@@ -265,6 +377,7 @@ async fn read_loop(
         }
     }
     log::info!("dtls client disconnected {addr:?}");
+    stats.incoming_disconnected(addr);
     let mut conns = conns.lock().await;
     let index = conns
         .iter()
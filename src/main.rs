@@ -2,7 +2,9 @@ use env_logger::Env;
 use input_capture::InputCaptureError;
 use input_emulation::InputEmulationError;
 use lan_mouse::{
+    backup::{self, BackupError},
     capture_test,
+    cert::{self, CertError},
     config::{self, Command, Config, ConfigError},
     emulation_test,
     service::{Service, ServiceError},
@@ -11,6 +13,8 @@ use lan_mouse_cli::CliError;
 #[cfg(feature = "gtk")]
 use lan_mouse_gtk::GtkError;
 use lan_mouse_ipc::{IpcError, IpcListenerCreationError};
+#[cfg(unix)]
+use lan_mouse_tui::TuiError;
 use std::{
     future::Future,
     io,
@@ -38,6 +42,13 @@ enum LanMouseError {
     Gtk(#[from] GtkError),
     #[error(transparent)]
     Cli(#[from] CliError),
+    #[cfg(unix)]
+    #[error(transparent)]
+    Tui(#[from] TuiError),
+    #[error(transparent)]
+    Cert(#[from] CertError),
+    #[error(transparent)]
+    Backup(#[from] BackupError),
 }
 
 fn main() {
@@ -58,6 +69,12 @@ fn run() -> Result<(), LanMouseError> {
             Command::TestEmulation(args) => run_async(emulation_test::run(config, args))?,
             Command::TestCapture(args) => run_async(capture_test::run(config, args))?,
             Command::Cli(cli_args) => run_async(lan_mouse_cli::run(cli_args))?,
+            #[cfg(unix)]
+            Command::Tui => run_async(lan_mouse_tui::run())?,
+            Command::Cert(args) => run_async(cert::run(config, args))?,
+            Command::Backup(args) => run_async(backup::backup(config, args))?,
+            Command::Restore(args) => run_async(backup::restore(config, args))?,
+            Command::Config(args) => config::check::run(config, args)?,
             Command::Daemon => {
                 // if daemon is specified we run the service
                 match run_async(run_service(config)) {
@@ -0,0 +1,639 @@
+//! Remote management of the service.
+//!
+//! An optional listener accepting WebSocket connections over TLS,
+//! authenticated with the certificate of the service and the client certificate
+//! of an administrator (`[management] admin_fingerprints`).
+//! Every text message carries a request exactly like on the frontend socket
+//! and the service answers with the same messages, see [`lan_mouse_ipc`].
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use lan_mouse_ipc::{FrontendEvent, FrontendMessage, FrontendRequest, IpcError, RequestEnvelope};
+use local_channel::mpsc::{Receiver, Sender, channel};
+use rustls::{
+    DigitallySignedStruct, DistinguishedName, ServerConfig, ServerConnection, SignatureScheme,
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{CertificateDer, PrivateKeyDer, UnixTime},
+    server::danger::{ClientCertVerified, ClientCertVerifier},
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::spawn_local,
+};
+use webrtc_dtls::crypto::Certificate;
+
+use crate::{access::AccessControl, config::Config, crypto};
+
+mod websocket;
+
+use websocket::{Decoder, Message, WebSocketError};
+
+/// time for a client to complete the TLS handshake and the upgrade
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// identifies a remote management session
+pub(crate) type SessionId = u64;
+
+#[derive(Debug, Error)]
+pub enum ManagementError {
+    #[error("failed to bind management listener to {addr}: {source}")]
+    Bind { addr: SocketAddr, source: io::Error },
+    #[error("failed to set up TLS for the management listener: {0}")]
+    Tls(#[from] rustls::Error),
+}
+
+#[derive(Debug, Error)]
+enum SessionError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+    #[error(transparent)]
+    WebSocket(#[from] WebSocketError),
+    #[error("connection closed")]
+    Closed,
+    #[error("timed out")]
+    Timeout,
+    #[error("unauthorized certificate {0}")]
+    Unauthorized(String),
+}
+
+/// what a session reports to the listener
+enum SessionEvent {
+    Connected(Sender<FrontendMessage>),
+    Request(Result<RequestEnvelope, IpcError>),
+    Closed,
+}
+
+pub(crate) struct ManagementListener {
+    /// events of all sessions
+    rx: Receiver<(SessionId, SessionEvent)>,
+    /// messages to the connected sessions
+    sessions: HashMap<SessionId, Sender<FrontendMessage>>,
+    /// fingerprints with the admin scope
+    admins: Arc<RwLock<HashMap<String, String>>>,
+    /// the address the listener is bound to, if enabled
+    addr: Option<SocketAddr>,
+}
+
+impl ManagementListener {
+    /// listen on the configured address, if any
+    pub(crate) async fn new(
+        config: &Config,
+        cert: &Certificate,
+        access: Arc<Mutex<AccessControl>>,
+    ) -> Result<Self, ManagementError> {
+        let (tx, rx) = channel();
+        let admins = Arc::new(RwLock::new(config.admin_fingerprints()));
+        let addr = config.management_address();
+        if let Some(addr) = addr {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|source| ManagementError::Bind { addr, source })?;
+            let tls = Arc::new(tls_config(cert)?);
+            log::info!("remote management listening on {addr}");
+            spawn_local(accept(listener, tls, admins.clone(), access, tx));
+        }
+        Ok(Self {
+            rx,
+            sessions: Default::default(),
+            admins,
+            addr,
+        })
+    }
+
+    /// apply a reloaded configuration
+    pub(crate) fn update(&mut self, config: &Config) {
+        *self.admins.write().expect("lock") = config.admin_fingerprints();
+        if config.management_address() != self.addr {
+            log::warn!("the management address is only changed after a restart");
+        }
+    }
+
+    /// the next request of a session.
+    /// A new session is synchronized without being asked, like a frontend.
+    pub(crate) async fn next(&mut self) -> (SessionId, Result<RequestEnvelope, IpcError>) {
+        loop {
+            let Some((session, event)) = self.rx.recv().await else {
+                /* disabled */
+                return std::future::pending().await;
+            };
+            match event {
+                SessionEvent::Connected(tx) => {
+                    self.sessions.insert(session, tx);
+                    let sync = RequestEnvelope {
+                        id: None,
+                        request: FrontendRequest::Sync,
+                    };
+                    return (session, Ok(sync));
+                }
+                SessionEvent::Request(request) => return (session, request),
+                SessionEvent::Closed => {
+                    self.sessions.remove(&session);
+                }
+            }
+        }
+    }
+
+    /// send an event to all sessions
+    pub(crate) fn broadcast(&mut self, event: &FrontendEvent) {
+        self.sessions
+            .retain(|_, tx| tx.send(FrontendMessage::Event(event.clone())).is_ok());
+    }
+
+    /// send a message to a single session
+    pub(crate) fn send(&mut self, session: SessionId, message: FrontendMessage) {
+        if let Some(tx) = self.sessions.get(&session) {
+            if tx.send(message).is_err() {
+                self.sessions.remove(&session);
+            }
+        }
+    }
+}
+
+fn tls_config(cert: &Certificate) -> Result<ServerConfig, rustls::Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = Arc::new(AnyClientCertificate(
+        provider.signature_verification_algorithms,
+    ));
+    let key = PrivateKeyDer::Pkcs8(cert.private_key.serialized_der.clone().into());
+    ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(cert.certificate.clone(), key)
+}
+
+/// requires a client certificate without validating it,
+/// the fingerprint is checked once the handshake is complete
+#[derive(Debug)]
+struct AnyClientCertificate(WebPkiSupportedAlgorithms);
+
+impl ClientCertVerifier for AnyClientCertificate {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    tls: Arc<ServerConfig>,
+    admins: Arc<RwLock<HashMap<String, String>>>,
+    access: Arc<Mutex<AccessControl>>,
+    tx: Sender<(SessionId, SessionEvent)>,
+) {
+    let mut next_id = 0;
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                log::warn!("management listener: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = access.lock().expect("lock").check_handshake(addr.ip()) {
+            log::info!("{addr}: rejected management connection: {e}");
+            continue;
+        }
+        let session = Session {
+            id: next_id,
+            addr,
+            tls: tls.clone(),
+            admins: admins.clone(),
+            access: access.clone(),
+            tx: tx.clone(),
+        };
+        next_id += 1;
+        spawn_local(session.run(stream));
+    }
+}
+
+/// a connection of an administrator
+struct Session {
+    id: SessionId,
+    addr: SocketAddr,
+    tls: Arc<ServerConfig>,
+    admins: Arc<RwLock<HashMap<String, String>>>,
+    access: Arc<Mutex<AccessControl>>,
+    tx: Sender<(SessionId, SessionEvent)>,
+}
+
+impl Session {
+    async fn run(self, stream: TcpStream) {
+        let addr = self.addr;
+        let conn = match ServerConnection::new(self.tls.clone()) {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::warn!("{addr}: {e}");
+                return;
+            }
+        };
+        let mut stream = TlsStream { tcp: stream, conn };
+        let connected = tokio::time::timeout(HANDSHAKE_TIMEOUT, self.connect(&mut stream)).await;
+        let decoder = match connected.unwrap_or(Err(SessionError::Timeout)) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                if let SessionError::Unauthorized(_) = e {
+                    self.access
+                        .lock()
+                        .expect("lock")
+                        .record_rejection(addr.ip());
+                }
+                log::warn!("{addr}: rejected management connection: {e}");
+                return;
+            }
+        };
+        let (messages, rx) = channel();
+        let _ = self.tx.send((self.id, SessionEvent::Connected(messages)));
+        match self.serve(&mut stream, decoder, rx).await {
+            Ok(()) | Err(SessionError::Closed) => {}
+            Err(e) => log::warn!("{addr}: management session: {e}"),
+        }
+        let _ = stream.write(&websocket::close_frame()).await;
+        log::info!("{addr}: management session closed");
+        let _ = self.tx.send((self.id, SessionEvent::Closed));
+    }
+
+    /// complete the TLS handshake, check the client certificate and upgrade to a websocket
+    async fn connect(&self, stream: &mut TlsStream) -> Result<Decoder, SessionError> {
+        let mut request = vec![];
+        stream.handshake(&mut request).await?;
+        let fingerprint = stream
+            .conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| crypto::generate_fingerprint(cert))
+            .ok_or(SessionError::Closed)?;
+        let Some(admin) = self.admins.read().expect("lock").get(&fingerprint).cloned() else {
+            return Err(SessionError::Unauthorized(fingerprint));
+        };
+
+        let response = loop {
+            match websocket::upgrade(&request) {
+                Ok(Some(response)) => break response,
+                Ok(None) => stream.read(&mut request).await?,
+                Err(e) => {
+                    let _ = stream.write(websocket::BAD_REQUEST.as_bytes()).await;
+                    return Err(e.into());
+                }
+            }
+        };
+        stream.write(response.as_bytes()).await?;
+        log::info!(
+            "{}: management session of {admin} ({fingerprint})",
+            self.addr
+        );
+        let mut decoder = Decoder::default();
+        /* frames sent right after the upgrade request */
+        let end = request
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("complete request");
+        decoder.extend(&request[end + 4..]);
+        Ok(decoder)
+    }
+
+    async fn serve(
+        &self,
+        stream: &mut TlsStream,
+        mut decoder: Decoder,
+        mut messages: Receiver<FrontendMessage>,
+    ) -> Result<(), SessionError> {
+        let mut data = vec![];
+        loop {
+            while let Some(message) = decoder.next()? {
+                match message {
+                    Message::Text(text) => {
+                        let request = RequestEnvelope::parse(&text);
+                        let _ = self.tx.send((self.id, SessionEvent::Request(request)));
+                    }
+                    Message::Ping(payload) => {
+                        stream.write(&websocket::pong_frame(&payload)).await?
+                    }
+                    Message::Close => return Ok(()),
+                }
+            }
+            tokio::select! {
+                r = stream.read(&mut data) => {
+                    r?;
+                    decoder.extend(&data);
+                    data.clear();
+                    stream.flush().await?;
+                }
+                message = messages.recv() => {
+                    let Some(message) = message else {
+                        return Ok(());
+                    };
+                    let json = serde_json::to_string(&message).expect("serializable");
+                    stream.write(&websocket::text_frame(&json)).await?;
+                }
+            }
+        }
+    }
+}
+
+/// a TLS connection driven on top of a [`TcpStream`]
+struct TlsStream {
+    tcp: TcpStream,
+    conn: ServerConnection,
+}
+
+impl TlsStream {
+    /// complete the handshake, appending plain text received
+    /// along with it (e.g. in the flight of the client's Finished) to `data`
+    async fn handshake(&mut self, data: &mut Vec<u8>) -> Result<(), SessionError> {
+        while self.conn.is_handshaking() {
+            self.flush().await?;
+            if self.conn.wants_read() {
+                self.read(data).await?;
+            }
+        }
+        self.flush().await
+    }
+
+    /// append the plain text received next to `data`.
+    /// Cancel safe as long as no data was received.
+    async fn read(&mut self, data: &mut Vec<u8>) -> Result<(), SessionError> {
+        let mut buf = [0u8; 4096];
+        let n = self.tcp.read(&mut buf).await?;
+        if n == 0 {
+            return Err(SessionError::Closed);
+        }
+        let mut received = &buf[..n];
+        while !received.is_empty() {
+            self.conn.read_tls(&mut received)?;
+            if let Err(e) = self.conn.process_new_packets() {
+                /* send the alert */
+                let _ = self.flush().await;
+                return Err(e.into());
+            }
+        }
+        match self.conn.reader().read_to_end(data) {
+            Ok(_) => Err(SessionError::Closed), /* close_notify */
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), SessionError> {
+        self.conn.writer().write_all(data)?;
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SessionError> {
+        while self.conn.wants_write() {
+            let mut buf = vec![];
+            self.conn.write_tls(&mut buf)?;
+            self.tcp.write_all(&buf).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{Ipv4Addr, TcpStream},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use lan_mouse_ipc::{FrontendMessage, FrontendRequest, FrontendResponse};
+    use rustls::{
+        ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::WebPkiSupportedAlgorithms,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    };
+    use webrtc_dtls::crypto::Certificate;
+
+    use super::ManagementListener;
+    use crate::{access::AccessControl, config::Config, crypto};
+
+    /// accepts the self signed certificate of the service
+    #[derive(Debug)]
+    struct AnyServerCertificate(WebPkiSupportedAlgorithms);
+
+    impl ServerCertVerifier for AnyServerCertificate {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.supported_schemes()
+        }
+    }
+
+    const UPGRADE_REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+
+    /// a text frame as sent by a client (masked)
+    fn masked_text_frame(text: &str) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let payload = text.as_bytes();
+        assert!(payload.len() < 126);
+        let mut frame = vec![0x81, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        frame
+    }
+
+    /// connect as the administrator with `cert`, send `request` and return the reply
+    fn client(port: u16, cert: Certificate, request: &str) -> String {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = AnyServerCertificate(provider.signature_verification_algorithms);
+        let key = PrivateKeyDer::Pkcs8(cert.private_key.serialized_der.clone().into());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_client_auth_cert(cert.certificate.clone(), key)
+            .unwrap();
+        let conn =
+            ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+        let tcp = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut tls = StreamOwned::new(conn, tcp);
+
+        /* buffered until the handshake completes and then sent
+         * in the same flight as the client's Finished message */
+        tls.conn.writer().write_all(UPGRADE_REQUEST).unwrap();
+        tls.flush().unwrap();
+
+        let mut received = vec![];
+        let mut buf = [0u8; 4096];
+        let end = loop {
+            if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let n = tls.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed during the upgrade");
+            received.extend_from_slice(&buf[..n]);
+        };
+        let response = String::from_utf8(received[..end].to_vec()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"), "{response}");
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        let mut received = received.split_off(end);
+
+        tls.write_all(&masked_text_frame(request)).unwrap();
+        tls.flush().unwrap();
+
+        /* the first text frame, unmasked with a 7 or 16 bit length */
+        loop {
+            if received.len() >= 2 {
+                assert_eq!(received[0], 0x81, "expected a single text frame");
+                let (len, offset) = match received[1] {
+                    126 if received.len() >= 4 => {
+                        (u16::from_be_bytes([received[2], received[3]]) as usize, 4)
+                    }
+                    126 => (usize::MAX, 4),
+                    len => (len as usize, 2),
+                };
+                if received.len().saturating_sub(offset) >= len {
+                    return String::from_utf8(received[offset..offset + len].to_vec()).unwrap();
+                }
+            }
+            let n = tls.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed before the response");
+            received.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn tls_websocket_round_trip() {
+        let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+        let server_cert = Certificate::generate_self_signed(["ignored".to_owned()]).unwrap();
+        let admin_cert = Certificate::generate_self_signed(["ignored".to_owned()]).unwrap();
+        let fingerprint = crypto::certificate_fingerprint(&admin_cert);
+
+        let dir = std::env::temp_dir().join(format!("lan-mouse-management-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "[management]\n\
+                 address = \"127.0.0.1:{port}\"\n\
+                 admin_fingerprints = {{ \"{fingerprint}\" = \"test\" }}\n"
+            ),
+        )
+        .unwrap();
+        let config = Config::parse_from([
+            "lan-mouse".as_ref(),
+            "--config".as_ref(),
+            config_path.as_os_str(),
+        ])
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let access = Arc::new(Mutex::new(AccessControl::new(&config)));
+                let mut listener = ManagementListener::new(&config, &server_cert, access)
+                    .await
+                    .unwrap();
+                let request = r#"{"id": 1, "request": "Enumerate"}"#;
+                let client = tokio::task::spawn_blocking(move || client(port, admin_cert, request));
+
+                let serve = async {
+                    /* a new session is synchronized first */
+                    let (session, sync) = listener.next().await;
+                    assert_eq!(sync.unwrap().request, FrontendRequest::Sync);
+                    let (id, request) = listener.next().await;
+                    assert_eq!(id, session);
+                    let request = request.unwrap();
+                    assert_eq!(request.id, Some(1));
+                    assert_eq!(request.request, FrontendRequest::Enumerate);
+                    let result = Ok(FrontendResponse::Clients(vec![]));
+                    listener.send(session, FrontendMessage::Response { id: 1, result });
+                };
+                tokio::time::timeout(Duration::from_secs(10), serve)
+                    .await
+                    .expect("no request received");
+
+                let reply = client.await.unwrap();
+                let reply = serde_json::from_str::<FrontendMessage>(&reply).unwrap();
+                assert!(
+                    matches!(
+                        reply,
+                        FrontendMessage::Response { id: 1, result: Ok(FrontendResponse::Clients(ref c)) } if c.is_empty()
+                    ),
+                    "unexpected reply: {reply:?}"
+                );
+            })
+            .await;
+    }
+}
@@ -0,0 +1,422 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+/// appended to the key of the client to compute `Sec-WebSocket-Accept` (RFC 6455)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// maximum size of the upgrade request
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// maximum size of a message (a single request)
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// maximum payload size of control frames
+const MAX_CONTROL_SIZE: u64 = 125;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Debug, Error)]
+pub(crate) enum WebSocketError {
+    #[error("invalid upgrade request: {0}")]
+    Upgrade(&'static str),
+    #[error("protocol error: {0}")]
+    Protocol(&'static str),
+    #[error("message exceeds {MAX_MESSAGE_SIZE} bytes")]
+    TooLarge,
+}
+
+/// the response to a complete upgrade request at the start of `buf`,
+/// `None` if more data is required
+pub(crate) fn upgrade(buf: &[u8]) -> Result<Option<String>, WebSocketError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        if buf.len() > MAX_REQUEST_SIZE {
+            return Err(WebSocketError::Upgrade("request too large"));
+        }
+        return Ok(None);
+    };
+    let request = std::str::from_utf8(&buf[..end])
+        .map_err(|_| WebSocketError::Upgrade("request is not valid utf-8"))?;
+    let mut lines = request.split("\r\n");
+    if !lines.next().is_some_and(|l| l.starts_with("GET ")) {
+        return Err(WebSocketError::Upgrade("expected a GET request"));
+    }
+    let (mut upgrade, mut key) = (false, None);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(WebSocketError::Upgrade("invalid header"));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(value),
+            _ => {}
+        }
+    }
+    let Some(key) = key.filter(|_| upgrade) else {
+        return Err(WebSocketError::Upgrade("not a websocket upgrade"));
+    };
+    let accept = STANDARD.encode(Sha1::digest(format!("{key}{ACCEPT_GUID}")));
+    Ok(Some(format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )))
+}
+
+/// response to an invalid upgrade request
+pub(crate) const BAD_REQUEST: &str =
+    "HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+/// a message received from the client
+#[derive(Debug)]
+pub(crate) enum Message {
+    Text(String),
+    /// a ping to be answered with the same payload
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// decodes the frames sent by a client
+#[derive(Default)]
+pub(crate) struct Decoder {
+    /// received data not decoded yet
+    buf: Vec<u8>,
+    /// payload of a fragmented message
+    fragments: Option<Vec<u8>>,
+}
+
+impl Decoder {
+    pub(crate) fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// the next complete message, if any
+    pub(crate) fn next(&mut self) -> Result<Option<Message>, WebSocketError> {
+        while let Some((fin, opcode, payload)) = self.frame()? {
+            match opcode {
+                OPCODE_CLOSE => return Ok(Some(Message::Close)),
+                OPCODE_PING => return Ok(Some(Message::Ping(payload))),
+                OPCODE_PONG => {}
+                OPCODE_TEXT | OPCODE_BINARY if self.fragments.is_some() => {
+                    return Err(WebSocketError::Protocol("expected a continuation frame"));
+                }
+                OPCODE_TEXT | OPCODE_BINARY if fin => return text(payload).map(Some),
+                OPCODE_TEXT | OPCODE_BINARY => self.fragments = Some(payload),
+                OPCODE_CONTINUATION => {
+                    let Some(fragments) = self.fragments.as_mut() else {
+                        return Err(WebSocketError::Protocol("unexpected continuation frame"));
+                    };
+                    fragments.extend_from_slice(&payload);
+                    if fragments.len() > MAX_MESSAGE_SIZE {
+                        return Err(WebSocketError::TooLarge);
+                    }
+                    if fin {
+                        let message = self.fragments.take().unwrap_or_default();
+                        return text(message).map(Some);
+                    }
+                }
+                _ => return Err(WebSocketError::Protocol("unknown opcode")),
+            }
+        }
+        Ok(None)
+    }
+
+    /// the next complete frame as `(fin, opcode, unmasked payload)`
+    fn frame(&mut self) -> Result<Option<(bool, u8, Vec<u8>)>, WebSocketError> {
+        let [b0, b1, ..] = self.buf[..] else {
+            return Ok(None);
+        };
+        let (fin, opcode) = (b0 & 0x80 != 0, b0 & 0x0f);
+        /* no extensions are negotiated */
+        if b0 & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits must not be set"));
+        }
+        if b1 & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+        let control = opcode & 0x08 != 0;
+        if control && !fin {
+            return Err(WebSocketError::Protocol(
+                "control frames must not be fragmented",
+            ));
+        }
+        let (len, header) = match b1 & 0x7f {
+            126 if self.buf.len() >= 4 => {
+                (u16::from_be_bytes([self.buf[2], self.buf[3]]) as u64, 4)
+            }
+            127 if self.buf.len() >= 10 => {
+                let len = self.buf[2..10].try_into().expect("8 bytes");
+                (u64::from_be_bytes(len), 10)
+            }
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+        if control && len > MAX_CONTROL_SIZE {
+            return Err(WebSocketError::Protocol("control frame too large"));
+        }
+        if len > MAX_MESSAGE_SIZE as u64 {
+            return Err(WebSocketError::TooLarge);
+        }
+        let len = len as usize;
+        if self.buf.len() < header + 4 + len {
+            return Ok(None);
+        }
+        let mask = [
+            self.buf[header],
+            self.buf[header + 1],
+            self.buf[header + 2],
+            self.buf[header + 3],
+        ];
+        let payload = self.buf[header + 4..header + 4 + len]
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, m)| b ^ m)
+            .collect();
+        self.buf.drain(..header + 4 + len);
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+fn text(payload: Vec<u8>) -> Result<Message, WebSocketError> {
+    String::from_utf8(payload)
+        .map(Message::Text)
+        .map_err(|_| WebSocketError::Protocol("message is not valid utf-8"))
+}
+
+/// an unmasked text frame as sent by the server
+pub(crate) fn text_frame(text: &str) -> Vec<u8> {
+    frame(OPCODE_TEXT, text.as_bytes())
+}
+
+pub(crate) fn pong_frame(payload: &[u8]) -> Vec<u8> {
+    frame(OPCODE_PONG, payload)
+}
+
+pub(crate) fn close_frame() -> Vec<u8> {
+    frame(OPCODE_CLOSE, &[])
+}
+
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Decoder, MAX_MESSAGE_SIZE, Message, OPCODE_BINARY, OPCODE_CLOSE, OPCODE_CONTINUATION,
+        OPCODE_PING, OPCODE_PONG, OPCODE_TEXT, WebSocketError, text_frame, upgrade,
+    };
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    /// a masked frame as sent by a client
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![u8::from(fin) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(payload.iter().zip(MASK.iter().cycle()).map(|(b, m)| b ^ m));
+        frame
+    }
+
+    fn decode(data: &[u8]) -> Result<Option<Message>, WebSocketError> {
+        let mut decoder = Decoder::default();
+        decoder.extend(data);
+        decoder.next()
+    }
+
+    fn protocol_error(data: &[u8]) -> &'static str {
+        match decode(data) {
+            Err(WebSocketError::Protocol(e)) => e,
+            r => panic!("expected a protocol error: {r:?}"),
+        }
+    }
+
+    #[test]
+    fn upgrade_request() {
+        /* the example of RFC 6455 */
+        let request = "GET /chat HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let response = upgrade(request.as_bytes()).unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let incomplete = &request[..request.len() - 2];
+        assert!(upgrade(incomplete.as_bytes()).unwrap().is_none());
+        let post = request.replacen("GET", "POST", 1);
+        assert!(matches!(
+            upgrade(post.as_bytes()),
+            Err(WebSocketError::Upgrade(_))
+        ));
+        let plain = request.replace("Upgrade: websocket\r\n", "");
+        assert!(matches!(
+            upgrade(plain.as_bytes()),
+            Err(WebSocketError::Upgrade(_))
+        ));
+        let large = format!("GET / HTTP/1.1\r\nX: {}", "x".repeat(16 * 1024));
+        assert!(matches!(
+            upgrade(large.as_bytes()),
+            Err(WebSocketError::Upgrade(_))
+        ));
+    }
+
+    #[test]
+    fn masked_text() {
+        let frame = masked(true, OPCODE_TEXT, br#"{"id": 1, "request": "Enumerate"}"#);
+        let mut decoder = Decoder::default();
+        /* frames may arrive in pieces */
+        for byte in &frame[..frame.len() - 1] {
+            decoder.extend(&[*byte]);
+            assert!(decoder.next().unwrap().is_none());
+        }
+        decoder.extend(&frame[frame.len() - 1..]);
+        assert!(matches!(
+            decoder.next().unwrap(),
+            Some(Message::Text(t)) if t == r#"{"id": 1, "request": "Enumerate"}"#
+        ));
+        assert!(decoder.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn extended_lengths() {
+        for len in [126, 0xffff, 0x10000] {
+            let text = "x".repeat(len);
+            let frame = masked(true, OPCODE_BINARY, text.as_bytes());
+            assert!(matches!(decode(&frame).unwrap(), Some(Message::Text(t)) if t == text));
+        }
+    }
+
+    #[test]
+    fn unmasked_frame() {
+        assert_eq!(
+            protocol_error(&text_frame("hello")),
+            "client frames must be masked"
+        );
+    }
+
+    #[test]
+    fn fragmented_message() {
+        let mut decoder = Decoder::default();
+        decoder.extend(&masked(false, OPCODE_TEXT, b"hel"));
+        /* control frames may be sent between fragments */
+        decoder.extend(&masked(true, OPCODE_PING, b"ping"));
+        decoder.extend(&masked(true, OPCODE_PONG, b""));
+        decoder.extend(&masked(false, OPCODE_CONTINUATION, b"lo "));
+        decoder.extend(&masked(true, OPCODE_CONTINUATION, b"world"));
+        assert!(matches!(decoder.next().unwrap(), Some(Message::Ping(p)) if p == b"ping"));
+        assert!(matches!(decoder.next().unwrap(), Some(Message::Text(t)) if t == "hello world"));
+        assert!(decoder.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_fragments() {
+        let continuation = masked(true, OPCODE_CONTINUATION, b"x");
+        assert_eq!(
+            protocol_error(&continuation),
+            "unexpected continuation frame"
+        );
+
+        let mut interleaved = masked(false, OPCODE_TEXT, b"a");
+        interleaved.extend(masked(true, OPCODE_TEXT, b"b"));
+        assert_eq!(
+            protocol_error(&interleaved),
+            "expected a continuation frame"
+        );
+    }
+
+    #[test]
+    fn control_frames() {
+        assert!(matches!(
+            decode(&masked(true, OPCODE_CLOSE, &[0x03, 0xe8])),
+            Ok(Some(Message::Close))
+        ));
+        assert!(decode(&masked(true, OPCODE_PONG, b"x")).unwrap().is_none());
+        assert_eq!(
+            protocol_error(&masked(false, OPCODE_PING, b"x")),
+            "control frames must not be fragmented"
+        );
+        assert_eq!(
+            protocol_error(&masked(true, OPCODE_PING, &[0; 126])),
+            "control frame too large"
+        );
+    }
+
+    #[test]
+    fn malformed_frames() {
+        assert_eq!(protocol_error(&masked(true, 0x3, b"x")), "unknown opcode");
+        assert_eq!(protocol_error(&masked(true, 0xb, b"x")), "unknown opcode");
+        let mut reserved = masked(true, OPCODE_TEXT, b"x");
+        reserved[0] |= 0x40;
+        assert_eq!(protocol_error(&reserved), "reserved bits must not be set");
+        assert_eq!(
+            protocol_error(&masked(true, OPCODE_TEXT, &[0xff, 0xfe])),
+            "message is not valid utf-8"
+        );
+    }
+
+    #[test]
+    fn oversized_frame() {
+        /* rejected as soon as the length is known */
+        let header = [0x80 | OPCODE_TEXT, 0x80 | 127, 0, 0, 0, 0, 0, 0x10, 0, 1];
+        assert!(matches!(decode(&header), Err(WebSocketError::TooLarge)));
+        let header = [0x80 | OPCODE_TEXT, 0x80 | 127, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(decode(&header), Err(WebSocketError::TooLarge)));
+
+        let limit = "x".repeat(MAX_MESSAGE_SIZE);
+        let frame = masked(true, OPCODE_TEXT, limit.as_bytes());
+        assert!(matches!(decode(&frame), Ok(Some(Message::Text(_)))));
+    }
+
+    #[test]
+    fn oversized_message() {
+        let half = vec![b'x'; MAX_MESSAGE_SIZE / 2];
+        let mut decoder = Decoder::default();
+        decoder.extend(&masked(false, OPCODE_TEXT, &half));
+        decoder.extend(&masked(false, OPCODE_CONTINUATION, &half));
+        decoder.extend(&masked(true, OPCODE_CONTINUATION, b"x"));
+        assert!(matches!(decoder.next(), Err(WebSocketError::TooLarge)));
+    }
+
+    #[test]
+    fn server_frames() {
+        assert_eq!(text_frame("hi"), [0x81, 2, b'h', b'i']);
+        let frame = text_frame(&"x".repeat(126));
+        assert_eq!(frame[..4], [0x81, 126, 0, 126]);
+        let frame = text_frame(&"x".repeat(0x10000));
+        assert_eq!(frame[..10], [0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame.len(), 10 + 0x10000);
+    }
+}
@@ -1,29 +1,40 @@
 use crate::{
+    access::AccessControl,
     capture::{Capture, CaptureType, ICaptureEvent},
     client::ClientManager,
-    config::Config,
+    config::{
+        Config, ConfigChange, ConfigClient, ConfigWatcher, ConfigWriter, DEFAULT_PROFILE,
+        LiveConfig, drop_in_dir,
+    },
     connect::LanMouseConnection,
     crypto,
+    dbus::{self, DbusService},
     dns::{DnsEvent, DnsResolver},
     emulation::{Emulation, EmulationEvent},
     listen::{LanMouseListener, ListenerCreationError},
+    management::{ManagementError, ManagementListener, SessionId},
     screen_share::ScreenShareManager,
+    stats::{Peer, Stats},
 };
 use futures::StreamExt;
 use hickory_resolver::ResolveError;
+use input_event::scancode;
 use lan_mouse_ipc::{
-    AsyncFrontendListener, ClientConfig, ClientHandle, ClientState, FrontendEvent, FrontendRequest,
-    IpcError, IpcListenerCreationError, Position, Status,
+    AsyncFrontendListener, ClientConfig, ClientHandle, ClientState, FrontendError, FrontendEvent,
+    FrontendId, FrontendMessage, FrontendRequest, FrontendResponse, IpcError,
+    IpcListenerCreationError, MacAddress, PROTOCOL_VERSION, Position, RequestEnvelope, RequestId,
+    Status,
 };
 use log;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     io,
     net::{IpAddr, SocketAddr},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 use thiserror::Error;
 use tokio::{process::Command, signal, sync::Notify};
+use webrtc_dtls::crypto::Certificate;
 
 #[derive(Debug, Error)]
 pub enum ServiceError {
@@ -37,6 +48,8 @@ pub enum ServiceError {
     ListenError(#[from] ListenerCreationError),
     #[error("failed to load certificate: `{0}`")]
     Certificate(#[from] crypto::Error),
+    #[error(transparent)]
+    Management(#[from] ManagementError),
 }
 
 pub struct Service {
@@ -48,8 +61,26 @@ pub struct Service {
     resolver: DnsResolver,
     /// frontend listener
     frontend_listener: AsyncFrontendListener,
+    /// the D-Bus interface
+    dbus: DbusService,
+    /// remote management sessions
+    management: ManagementListener,
     /// authorized public key sha256 fingerprints
     authorized_keys: Arc<RwLock<HashMap<String, String>>>,
+    /// rate limits and blocked keys / addresses for incoming connections
+    access: Arc<Mutex<AccessControl>>,
+    /// traffic and health statistics of all connections
+    stats: Stats,
+    /// the configuration the service was started with or last reloaded
+    config: Config,
+    /// notifies about changes to the config file
+    config_watcher: ConfigWatcher,
+    /// writes runtime changes back to the config file (if enabled)
+    config_writer: Option<ConfigWriter>,
+    /// handles of the clients defined in the config file (in order)
+    config_clients: Vec<ClientHandle>,
+    /// the active layout profile
+    profile: String,
     /// (outgoing) client information
     client_manager: ClientManager,
     /// current port
@@ -58,12 +89,16 @@ pub struct Service {
     public_key_fingerprint: String,
     /// notify for pending frontend events
     frontend_event_pending: Notify,
-    /// frontend events queued for sending
-    pending_frontend_events: VecDeque<FrontendEvent>,
+    /// frontend messages queued for sending
+    pending_frontend_messages: VecDeque<Outgoing>,
+    /// requests waiting for the port to be changed
+    port_change_requests: Vec<Requester>,
     /// status of input capture (enabled / disabled)
     capture_status: Status,
     /// status of input emulation (enabled / disabled)
     emulation_status: Status,
+    /// the client currently receiving captured input
+    controlling: Option<ClientHandle>,
     /// keep track of registered connections to avoid duplicate barriers
     incoming_conns: HashSet<SocketAddr>,
     /// map from capture handle to connection info
@@ -73,6 +108,26 @@ pub struct Service {
     next_trigger_handle: u64,
 }
 
+/// a message queued for frontends
+enum Outgoing {
+    /// an event sent to all frontends
+    Broadcast(FrontendEvent),
+    /// a message sent to a single frontend
+    Send(FrontendId, FrontendMessage),
+    /// a message sent to a single remote management session
+    Remote(SessionId, FrontendMessage),
+}
+
+/// where the result of a request goes
+enum Requester {
+    /// a frontend on the socket, answered if the request has an id
+    Frontend(FrontendId, Option<RequestId>),
+    /// a remote management session, answered if the request has an id
+    Remote(SessionId, Option<RequestId>),
+    /// a D-Bus method call
+    Dbus(dbus::Reply),
+}
+
 #[derive(Debug)]
 struct Incoming {
     fingerprint: String,
@@ -83,36 +138,46 @@ struct Incoming {
 impl Service {
     pub async fn new(config: Config) -> Result<Self, ServiceError> {
         let client_manager = ClientManager::default();
-        for client in config.clients() {
-            let config = ClientConfig {
-                hostname: client.hostname,
-                fix_ips: client.ips.into_iter().collect(),
-                port: client.port,
-                pos: client.pos,
-                cmd: client.enter_hook,
-            };
-            let state = ClientState {
-                active: client.active,
-                ips: HashSet::from_iter(config.fix_ips.iter().cloned()),
-                ..Default::default()
-            };
+        let profile = initial_profile(&config);
+        log::info!("using profile `{profile}`");
+        let mut config_clients = vec![];
+        for client in config.clients(&profile) {
+            let (config, state) = client_from_config(client);
             let handle = client_manager.add_client();
             client_manager.set_config(handle, config);
             client_manager.set_state(handle, state);
+            config_clients.push(handle);
         }
 
+        // create frontend communication adapter, exit if already running
+        let mut frontend_listener =
+            AsyncFrontendListener::new(config.ipc_read_only_group()).await?;
+
         // load certificate
-        let cert = crypto::load_or_generate_key_and_cert(config.cert_path())?;
+        let cert = load_certificate(&config, &mut frontend_listener).await?;
         let public_key_fingerprint = crypto::certificate_fingerprint(&cert);
 
-        // create frontend communication adapter, exit if already running
-        let frontend_listener = AsyncFrontendListener::new().await?;
+        let dbus = DbusService::new().await;
 
         let authorized_keys = Arc::new(RwLock::new(config.authorized_fingerprints()));
+        let access = Arc::new(Mutex::new(AccessControl::new(&config)));
+        let stats = Stats::default();
         // listener + connection
-        let listener =
-            LanMouseListener::new(config.port(), cert.clone(), authorized_keys.clone()).await?;
-        let conn = LanMouseConnection::new(cert.clone(), client_manager.clone());
+        let listener = LanMouseListener::new(
+            config.port(),
+            cert.clone(),
+            authorized_keys.clone(),
+            access.clone(),
+            stats.clone(),
+        )
+        .await?;
+        let management = ManagementListener::new(&config, &cert, access.clone()).await?;
+        let conn = LanMouseConnection::new(
+            cert.clone(),
+            client_manager.clone(),
+            config.wake_window(),
+            stats.clone(),
+        );
 
         // input capture + emulation
         let capture_backend = config.capture_backend().map(|b| b.into());
@@ -124,6 +189,8 @@ impl Service {
         let resolver = DnsResolver::new()?;
 
         let port = config.port();
+        let config_writer = ConfigWriter::new(&config);
+        let config_watcher = ConfigWatcher::new(&config.config_files(), drop_in_dir().as_slice());
 
         // Initialize screen sharing manager if feature is enabled
         let screen_share_manager = cfg!(feature = "screen_share")
@@ -133,14 +200,25 @@ impl Service {
             capture,
             emulation,
             frontend_listener,
+            dbus,
+            management,
             resolver,
             authorized_keys,
+            access,
+            stats,
+            config,
+            config_watcher,
+            config_writer,
+            config_clients,
+            profile,
             public_key_fingerprint,
             client_manager,
             frontend_event_pending: Default::default(),
             port,
-            pending_frontend_events: Default::default(),
+            pending_frontend_messages: Default::default(),
+            port_change_requests: Default::default(),
             capture_status: Default::default(),
+            controlling: None,
             emulation_status: Default::default(),
             incoming_conn_info: Default::default(),
             incoming_conns: Default::default(),
@@ -163,13 +241,20 @@ impl Service {
             self.activate_client(handle);
         }
 
+        for event in self.state_events() {
+            self.dbus.broadcast(&event).await;
+        }
+
         loop {
             tokio::select! {
                 request = self.frontend_listener.next() => self.handle_frontend_request(request),
+                request = self.management.next() => self.handle_remote_request(request),
+                (request, reply) = self.dbus.next() => self.handle(Requester::Dbus(reply), request),
                 _ = self.frontend_event_pending.notified() => self.handle_frontend_pending().await,
                 event = self.emulation.event() => self.handle_emulation_event(event),
                 event = self.capture.event() => self.handle_capture_event(event),
                 event = self.resolver.event() => self.handle_resolver_event(event),
+                change = self.config_watcher.changed() => self.handle_config_change(change),
                 r = signal::ctrl_c() => break r.expect("failed to wait for CTRL+C"),
             }
         }
@@ -185,36 +270,188 @@ impl Service {
         Ok(())
     }
 
-    fn handle_frontend_request(&mut self, request: Option<Result<FrontendRequest, IpcError>>) {
-        let request = match request.expect("frontend listener closed") {
+    fn handle_frontend_request(
+        &mut self,
+        request: Option<(FrontendId, Result<RequestEnvelope, IpcError>)>,
+    ) {
+        let (frontend, request) = request.expect("frontend listener closed");
+        let RequestEnvelope { id, request } = match request {
             Ok(r) => r,
-            Err(e) => return log::error!("error receiving request: {e}"),
+            Err(e) => {
+                log::error!("error receiving request: {e}");
+                let event = FrontendEvent::Error(format!("invalid request: {e}"));
+                return self.send(frontend, FrontendMessage::Event(event));
+            }
+        };
+        let requester = Requester::Frontend(frontend, id);
+        if self.frontend_listener.is_read_only(frontend) && !request.is_read_only() {
+            return self.respond(requester, Err(FrontendError::ReadOnly));
+        }
+        self.handle(requester, request);
+    }
+
+    fn handle_remote_request(
+        &mut self,
+        (session, request): (SessionId, Result<RequestEnvelope, IpcError>),
+    ) {
+        match request {
+            Ok(RequestEnvelope { id, request }) => {
+                self.handle(Requester::Remote(session, id), request)
+            }
+            Err(e) => {
+                log::warn!("invalid request of management session: {e}");
+                let event = FrontendEvent::Error(format!("invalid request: {e}"));
+                self.queue(Outgoing::Remote(session, FrontendMessage::Event(event)));
+            }
+        }
+    }
+
+    fn handle(&mut self, requester: Requester, request: FrontendRequest) {
+        /* requests changing the configuration */
+        let persist = !matches!(
+            request,
+            FrontendRequest::ChangePort(_)
+                | FrontendRequest::EnableCapture
+                | FrontendRequest::EnableEmulation
+                | FrontendRequest::Enumerate
+                | FrontendRequest::GetStats
+                | FrontendRequest::Hello { .. }
+                | FrontendRequest::ResolveDns(_)
+                | FrontendRequest::SetNetwork(_)
+                | FrontendRequest::SwitchProfile(_)
+                | FrontendRequest::Sync
+                | FrontendRequest::Unlock(_)
+        );
+        let result = match request {
+            /* answered once the emulation reports the new port */
+            FrontendRequest::ChangePort(port) if port != self.port => {
+                if !matches!(
+                    requester,
+                    Requester::Frontend(_, None) | Requester::Remote(_, None)
+                ) {
+                    self.port_change_requests.push(requester);
+                }
+                return self.change_port(port);
+            }
+            request => self.handle_request(&requester, request),
         };
+        if result.is_ok() && persist {
+            self.save_config();
+        }
+        self.respond(requester, result);
+    }
+
+    fn handle_request(
+        &mut self,
+        requester: &Requester,
+        request: FrontendRequest,
+    ) -> Result<FrontendResponse, FrontendError> {
         match request {
-            FrontendRequest::Activate(handle, active) => self.set_client_active(handle, active),
-            FrontendRequest::AuthorizeKey(desc, fp) => self.add_authorized_key(desc, fp),
-            FrontendRequest::ChangePort(port) => self.change_port(port),
-            FrontendRequest::Create => self.add_client(),
-            FrontendRequest::Delete(handle) => self.remove_client(handle),
+            FrontendRequest::Hello { version } => return hello(version),
+            FrontendRequest::Activate { handle, active } => {
+                self.check_client(handle)?;
+                self.set_client_active(handle, active)
+            }
+            FrontendRequest::AuthorizeKey {
+                description,
+                fingerprint,
+            } => self.add_authorized_key(description, fingerprint),
+            FrontendRequest::ChangePort(port) => {
+                self.change_port(port);
+                return Ok(FrontendResponse::Port(port));
+            }
+            FrontendRequest::Create => return Ok(FrontendResponse::Created(self.add_client())),
+            FrontendRequest::Delete(handle) => {
+                self.check_client(handle)?;
+                self.remove_client(handle)
+            }
             FrontendRequest::EnableCapture => self.capture.reenable(),
             FrontendRequest::EnableEmulation => self.emulation.reenable(),
-            FrontendRequest::Enumerate() => self.enumerate(),
-            FrontendRequest::UpdateFixIps(handle, fix_ips) => self.update_fix_ips(handle, fix_ips),
-            FrontendRequest::UpdateHostname(handle, host) => self.update_hostname(handle, host),
-            FrontendRequest::UpdatePort(handle, port) => self.update_port(handle, port),
-            FrontendRequest::UpdatePosition(handle, pos) => self.update_pos(handle, pos),
-            FrontendRequest::ResolveDns(handle) => self.resolve(handle),
-            FrontendRequest::Sync => self.sync_frontend(),
-            FrontendRequest::RemoveAuthorizedKey(key) => self.remove_authorized_key(key),
-            FrontendRequest::UpdateEnterHook(handle, enter_hook) => {
+            FrontendRequest::Enumerate => {
+                let clients = self.client_manager.get_client_states();
+                return Ok(FrontendResponse::Clients(clients));
+            }
+            FrontendRequest::UpdateFixIps { handle, ips } => {
+                self.check_client(handle)?;
+                self.update_fix_ips(handle, ips)
+            }
+            FrontendRequest::UpdateHostname { handle, hostname } => {
+                self.check_client(handle)?;
+                self.update_hostname(handle, hostname)
+            }
+            FrontendRequest::UpdatePort { handle, port } => {
+                self.check_client(handle)?;
+                self.update_port(handle, port)
+            }
+            FrontendRequest::UpdatePosition { handle, position } => {
+                self.check_client(handle)?;
+                self.update_pos(handle, position)
+            }
+            FrontendRequest::ResolveDns(handle) => {
+                self.check_client(handle)?;
+                self.resolve(handle)
+            }
+            FrontendRequest::Sync => match *requester {
+                Requester::Frontend(frontend, _) => self.sync_frontend(frontend),
+                Requester::Remote(session, _) => {
+                    for event in self.state_events() {
+                        let message = FrontendMessage::Event(event);
+                        self.queue(Outgoing::Remote(session, message));
+                    }
+                }
+                Requester::Dbus(_) => {}
+            },
+            FrontendRequest::RemoveAuthorizedKey(key) => self.remove_authorized_key(key)?,
+            FrontendRequest::UpdateEnterHook { handle, enter_hook } => {
+                self.check_client(handle)?;
                 self.update_enter_hook(handle, enter_hook)
             }
+            FrontendRequest::UpdateMacAddress { handle, mac } => {
+                self.check_client(handle)?;
+                self.update_mac(handle, mac)
+            }
+            FrontendRequest::Unlock(_) => log::debug!("private key is already unlocked"),
+            FrontendRequest::BlockKey(fp) => self.block_key(fp),
+            FrontendRequest::UnblockKey(fp) => self.unblock_key(fp),
+            FrontendRequest::BlockIp(ip) => self.block_ip(ip),
+            FrontendRequest::UnblockIp(ip) => self.unblock_ip(ip),
+            FrontendRequest::GetStats => {
+                return Ok(FrontendResponse::Stats {
+                    clients: self.stats.client_stats(),
+                    incoming: self.stats.incoming_stats(),
+                });
+            }
+            FrontendRequest::SwitchProfile(profile) => {
+                return self.switch_profile(profile).map(FrontendResponse::Profile);
+            }
+            FrontendRequest::SetNetwork(network) => {
+                return self.set_network(network).map(FrontendResponse::Profile);
+            }
+            FrontendRequest::SetReleaseBind(keys) => self.set_release_bind(keys)?,
+        }
+        Ok(FrontendResponse::Done)
+    }
+
+    fn check_client(&self, handle: ClientHandle) -> Result<(), FrontendError> {
+        match self.client_manager.get_state(handle) {
+            Some(_) => Ok(()),
+            None => Err(FrontendError::NoSuchClient(handle)),
         }
     }
 
     async fn handle_frontend_pending(&mut self) {
-        while let Some(event) = self.pending_frontend_events.pop_front() {
-            self.frontend_listener.broadcast(event).await;
+        while let Some(message) = self.pending_frontend_messages.pop_front() {
+            match message {
+                Outgoing::Broadcast(event) => {
+                    self.dbus.broadcast(&event).await;
+                    self.management.broadcast(&event);
+                    self.frontend_listener.broadcast(event).await
+                }
+                Outgoing::Remote(session, message) => self.management.send(session, message),
+                Outgoing::Send(frontend, message) => {
+                    self.frontend_listener.send(frontend, message).await
+                }
+            }
         }
     }
 
@@ -223,6 +460,15 @@ impl Service {
             EmulationEvent::ConnectionAttempt { fingerprint } => {
                 self.notify_frontend(FrontendEvent::ConnectionAttempt { fingerprint });
             }
+            EmulationEvent::KeyRotated {
+                old_fingerprint,
+                fingerprint,
+            } => {
+                log::info!("authorized key {old_fingerprint} replaced by {fingerprint}");
+                let keys = self.authorized_keys.read().expect("lock").clone();
+                self.notify_frontend(FrontendEvent::AuthorizedUpdated(keys));
+                self.save_config();
+            }
             EmulationEvent::Entered {
                 addr,
                 pos,
@@ -245,14 +491,33 @@ impl Service {
                     self.notify_frontend(FrontendEvent::IncomingDisconnected(addr));
                 }
             }
-            EmulationEvent::PortChanged(port) => match port {
-                Ok(port) => {
-                    self.port = port;
-                    self.notify_frontend(FrontendEvent::PortChanged(port, None));
+            EmulationEvent::PortChanged(port) => {
+                /* answer pending `ChangePort` requests */
+                let result = match &port {
+                    Ok(port) => Ok(FrontendResponse::Port(*port)),
+                    Err(e) => Err(FrontendError::PortChange(e.to_string())),
+                };
+                for requester in std::mem::take(&mut self.port_change_requests) {
+                    self.respond(requester, result.clone());
                 }
-                Err(e) => self
-                    .notify_frontend(FrontendEvent::PortChanged(self.port, Some(format!("{e}")))),
-            },
+                match port {
+                    Ok(port) => {
+                        self.port = port;
+                        self.notify_frontend(FrontendEvent::PortChanged { port, error: None });
+                        /* nothing to persist if the port was changed by (re)loading the config */
+                        if port != self.config.port() {
+                            if let Some(writer) = self.config_writer.as_mut() {
+                                writer.set_port(port);
+                            }
+                            self.save_config();
+                        }
+                    }
+                    Err(e) => self.notify_frontend(FrontendEvent::PortChanged {
+                        port: self.port,
+                        error: Some(format!("{e}")),
+                    }),
+                }
+            }
             EmulationEvent::EmulationDisabled => {
                 self.emulation_status = Status::Disabled;
                 self.notify_frontend(FrontendEvent::EmulationStatus(self.emulation_status));
@@ -288,6 +553,13 @@ impl Service {
             ICaptureEvent::ClientEntered(handle) => {
                 log::info!("entering client {handle} ...");
                 self.spawn_hook_command(handle);
+                self.controlling = Some(handle);
+                self.notify_frontend(FrontendEvent::Controlling(self.controlling));
+            }
+            ICaptureEvent::ClientLeft(handle) => {
+                log::info!("left client {handle}");
+                self.controlling = None;
+                self.notify_frontend(FrontendEvent::Controlling(self.controlling));
             }
         }
     }
@@ -317,16 +589,31 @@ impl Service {
         }
     }
 
-    fn sync_frontend(&mut self) {
-        self.enumerate();
-        self.notify_frontend(FrontendEvent::EmulationStatus(self.emulation_status));
-        self.notify_frontend(FrontendEvent::CaptureStatus(self.capture_status));
-        self.notify_frontend(FrontendEvent::PortChanged(self.port, None));
-        self.notify_frontend(FrontendEvent::PublicKeyFingerprint(
-            self.public_key_fingerprint.clone(),
-        ));
+    /// send the complete state to a single frontend
+    fn sync_frontend(&mut self, frontend: FrontendId) {
+        for event in self.state_events() {
+            self.send(frontend, FrontendMessage::Event(event));
+        }
+    }
+
+    /// the complete state as events
+    fn state_events(&self) -> [FrontendEvent; 10] {
         let keys = self.authorized_keys.read().expect("lock").clone();
-        self.notify_frontend(FrontendEvent::AuthorizedUpdated(keys));
+        [
+            FrontendEvent::Enumerate(self.client_manager.get_client_states()),
+            FrontendEvent::EmulationStatus(self.emulation_status),
+            FrontendEvent::CaptureStatus(self.capture_status),
+            FrontendEvent::Controlling(self.controlling),
+            FrontendEvent::PortChanged {
+                port: self.port,
+                error: None,
+            },
+            FrontendEvent::PublicKeyFingerprint(self.public_key_fingerprint.clone()),
+            FrontendEvent::AuthorizedUpdated(keys),
+            self.blocked_event(),
+            self.profiles_event(),
+            self.release_bind_event(),
+        ]
     }
 
     const ENTER_HANDLE_BEGIN: u64 = u64::MAX / 2 + 1;
@@ -388,7 +675,37 @@ impl Service {
     }
 
     fn notify_frontend(&mut self, event: FrontendEvent) {
-        self.pending_frontend_events.push_back(event);
+        self.queue(Outgoing::Broadcast(event));
+    }
+
+    fn send(&mut self, frontend: FrontendId, message: FrontendMessage) {
+        self.queue(Outgoing::Send(frontend, message));
+    }
+
+    /// answer a request
+    fn respond(&mut self, requester: Requester, result: Result<FrontendResponse, FrontendError>) {
+        if let Err(e) = &result {
+            log::warn!("{e}");
+        }
+        match requester {
+            Requester::Frontend(frontend, id) => {
+                if let Some(message) = response_message(id, result) {
+                    self.send(frontend, message);
+                }
+            }
+            Requester::Remote(session, id) => {
+                if let Some(message) = response_message(id, result) {
+                    self.queue(Outgoing::Remote(session, message));
+                }
+            }
+            Requester::Dbus(reply) => {
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    fn queue(&mut self, message: Outgoing) {
+        self.pending_frontend_messages.push_back(message);
         self.frontend_event_pending.notify_one();
     }
 
@@ -398,22 +715,264 @@ impl Service {
         self.notify_frontend(FrontendEvent::AuthorizedUpdated(keys));
     }
 
-    fn remove_authorized_key(&mut self, fp: String) {
-        self.authorized_keys.write().expect("lock").remove(&fp);
+    fn remove_authorized_key(&mut self, fp: String) -> Result<(), FrontendError> {
+        if self
+            .authorized_keys
+            .write()
+            .expect("lock")
+            .remove(&fp)
+            .is_none()
+        {
+            return Err(FrontendError::NoSuchKey(fp));
+        }
         let keys = self.authorized_keys.read().expect("lock").clone();
         self.notify_frontend(FrontendEvent::AuthorizedUpdated(keys));
+        Ok(())
+    }
+
+    fn block_key(&mut self, fp: String) {
+        log::info!("blocking key {fp}");
+        self.access.lock().expect("lock").block_fingerprint(fp);
+        self.notify_blocked();
+    }
+
+    fn unblock_key(&mut self, fp: String) {
+        self.access.lock().expect("lock").unblock_fingerprint(&fp);
+        self.notify_blocked();
+    }
+
+    fn block_ip(&mut self, ip: IpAddr) {
+        log::info!("blocking {ip}");
+        self.access.lock().expect("lock").block_ip(ip);
+        self.notify_blocked();
+    }
+
+    fn unblock_ip(&mut self, ip: IpAddr) {
+        self.access.lock().expect("lock").unblock_ip(ip);
+        self.notify_blocked();
+    }
+
+    fn notify_blocked(&mut self) {
+        self.notify_frontend(self.blocked_event());
+    }
+
+    fn blocked_event(&self) -> FrontendEvent {
+        let (fingerprints, ips) = self.blocked();
+        FrontendEvent::BlockedUpdated { fingerprints, ips }
+    }
+
+    /// blocked fingerprints and ip addresses
+    fn blocked(&self) -> (HashSet<String>, HashSet<IpAddr>) {
+        let access = self.access.lock().expect("lock");
+        (access.blocked_fingerprints(), access.blocked_ips())
+    }
+
+    /// write the current state back to the config file
+    fn save_config(&mut self) {
+        let Some(writer) = self.config_writer.as_mut() else {
+            return;
+        };
+        let access = self.access.lock().expect("lock");
+        let live = LiveConfig {
+            profile: self.profile.clone(),
+            clients: self.client_manager.get_client_states(),
+            authorized_fingerprints: self.authorized_keys.read().expect("lock").clone(),
+            blocked_fingerprints: access.blocked_fingerprints(),
+            blocked_ips: access.blocked_ips(),
+        };
+        drop(access);
+        match writer.save(&mut self.config, &live, &self.config_clients) {
+            Ok(handles) => self.config_clients = handles,
+            Err(e) => log::warn!("failed to save config: {e}"),
+        }
     }
 
-    fn enumerate(&mut self) {
-        let clients = self.client_manager.get_client_states();
-        self.notify_frontend(FrontendEvent::Enumerate(clients));
+    fn handle_config_change(&mut self, change: ConfigChange) {
+        if let ConfigChange::Files(files) = &change {
+            let writer = self.config_writer.as_ref();
+            if writer.is_some_and(|w| files.iter().all(|f| w.wrote(f))) {
+                log::debug!("ignoring config file change saved by the service");
+                return;
+            }
+        }
+        self.reload_config();
+    }
+
+    /// apply changes made to the config file
+    fn reload_config(&mut self) {
+        let config = match self.config.reload() {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("failed to reload config: {e}");
+                self.notify_frontend(FrontendEvent::Error(format!(
+                    "failed to reload config: {e}"
+                )));
+                return;
+            }
+        };
+        log::info!("reloading config");
+        let profiles = (self.profile.clone(), self.config.profiles());
+
+        if !config.has_profile(&self.profile) {
+            log::warn!(
+                "profile `{}` was removed, switching to `{DEFAULT_PROFILE}`",
+                self.profile
+            );
+            self.remove_all_clients();
+            self.profile = DEFAULT_PROFILE.to_owned();
+        }
+        self.apply_clients(config.clients(&self.profile));
+
+        let authorized = config.authorized_fingerprints();
+        if *self.authorized_keys.read().expect("lock") != authorized {
+            *self.authorized_keys.write().expect("lock") = authorized.clone();
+            self.notify_frontend(FrontendEvent::AuthorizedUpdated(authorized));
+        }
+
+        let blocked = self.blocked();
+        self.access.lock().expect("lock").update(&config);
+        if self.blocked() != blocked {
+            self.notify_blocked();
+        }
+        self.management.update(&config);
+
+        if config.release_bind() != self.capture.release_bind() {
+            self.apply_release_bind(config.release_bind());
+        }
+
+        if config.port() != self.port {
+            self.change_port(config.port());
+        }
+
+        self.config = config;
+        if (self.profile.clone(), self.config.profiles()) != profiles {
+            self.notify_profiles();
+        }
+    }
+
+    /// apply the clients of the active profile
+    fn apply_clients(&mut self, clients: Vec<ConfigClient>) {
+        /* clients are matched by their position in the config file */
+        let handles = std::mem::take(&mut self.config_clients);
+        for &handle in handles.iter().skip(clients.len()) {
+            self.remove_client(handle);
+        }
+        for (i, client) in clients.into_iter().enumerate() {
+            let handle = match handles.get(i) {
+                Some(&h) if self.client_manager.get_state(h).is_some() => h,
+                _ => self.add_client(),
+            };
+            let (config, state) = client_from_config(client);
+            self.update_client(handle, config, state.active);
+            self.config_clients.push(handle);
+        }
+    }
+
+    /// switch to `profile`, returning the active profile
+    fn switch_profile(&mut self, profile: String) -> Result<String, FrontendError> {
+        if !self.config.has_profile(&profile) {
+            return Err(FrontendError::NoSuchProfile(profile));
+        }
+        if profile != self.profile {
+            log::info!("switching to profile `{profile}`");
+            self.remove_all_clients();
+            self.profile = profile;
+            self.apply_clients(self.config.clients(&self.profile));
+        }
+        self.notify_profiles();
+        Ok(self.profile.clone())
+    }
+
+    /// select the profile configured for `network`
+    fn set_network(&mut self, network: String) -> Result<String, FrontendError> {
+        let profile = self
+            .config
+            .network_profile(&network)
+            .unwrap_or_else(|| initial_profile(&self.config));
+        log::info!("network `{network}`: using profile `{profile}`");
+        self.switch_profile(profile)
+    }
+
+    fn remove_all_clients(&mut self) {
+        for (handle, _, _) in self.client_manager.get_client_states() {
+            self.remove_client(handle);
+        }
+        self.config_clients.clear();
+    }
+
+    fn set_release_bind(&mut self, keys: Vec<String>) -> Result<(), FrontendError> {
+        self.apply_release_bind(parse_release_bind(&keys)?);
+        Ok(())
+    }
+
+    fn apply_release_bind(&mut self, release_bind: Vec<scancode::Linux>) {
+        log::info!("release bind changed to {release_bind:?}");
+        if let Some(writer) = self.config_writer.as_mut() {
+            writer.set_release_bind(release_bind.iter().copied().map(key_name).collect());
+        }
+        self.capture.set_release_bind(release_bind);
+        self.notify_frontend(self.release_bind_event());
+    }
+
+    fn release_bind_event(&self) -> FrontendEvent {
+        let keys = self
+            .capture
+            .release_bind()
+            .into_iter()
+            .map(key_name)
+            .collect();
+        FrontendEvent::ReleaseBind(keys)
+    }
+
+    fn notify_profiles(&mut self) {
+        self.notify_frontend(self.profiles_event());
+    }
+
+    fn profiles_event(&self) -> FrontendEvent {
+        FrontendEvent::ProfileChanged {
+            active: self.profile.clone(),
+            profiles: self.config.profiles(),
+        }
+    }
+
+    /// apply the configuration of a client, changing only what differs
+    fn update_client(&mut self, handle: ClientHandle, config: ClientConfig, active: bool) {
+        let Some((old, state)) = self.client_manager.get_state(handle) else {
+            return;
+        };
+        if old.hostname != config.hostname {
+            self.update_hostname(handle, config.hostname);
+        }
+        if HashSet::<&IpAddr>::from_iter(&old.fix_ips) != HashSet::from_iter(&config.fix_ips) {
+            self.update_fix_ips(handle, config.fix_ips);
+        }
+        if old.port != config.port {
+            self.update_port(handle, config.port);
+        }
+        if old.pos != config.pos {
+            self.update_pos(handle, config.pos);
+        }
+        if old.cmd != config.cmd {
+            self.update_enter_hook(handle, config.cmd);
+        }
+        if old.mac != config.mac {
+            self.update_mac(handle, config.mac);
+        }
+        if state.active != active {
+            self.set_client_active(handle, active);
+        }
     }
 
-    fn add_client(&mut self) {
+    fn add_client(&mut self) -> ClientHandle {
         let handle = self.client_manager.add_client();
         log::info!("added client {handle}");
-        let (c, s) = self.client_manager.get_state(handle).unwrap();
-        self.notify_frontend(FrontendEvent::Created(handle, c, s));
+        let (config, state) = self.client_manager.get_state(handle).unwrap();
+        self.notify_frontend(FrontendEvent::Created {
+            handle,
+            config,
+            state,
+        });
+        handle
     }
 
     fn set_client_active(&mut self, handle: ClientHandle, active: bool) {
@@ -463,7 +1022,10 @@ impl Service {
         if self.port != port {
             self.emulation.request_port_change(port);
         } else {
-            self.notify_frontend(FrontendEvent::PortChanged(self.port, None));
+            self.notify_frontend(FrontendEvent::PortChanged {
+                port: self.port,
+                error: None,
+            });
         }
     }
 
@@ -476,6 +1038,7 @@ impl Service {
         {
             self.capture.destroy(handle);
         }
+        self.stats.remove(&Peer::Client(handle));
         self.notify_frontend(FrontendEvent::Deleted(handle));
     }
 
@@ -511,13 +1074,19 @@ impl Service {
         self.broadcast_client(handle);
     }
 
+    fn update_mac(&mut self, handle: ClientHandle, mac: Option<MacAddress>) {
+        self.client_manager.set_mac(handle, mac);
+        self.broadcast_client(handle);
+    }
+
     fn broadcast_client(&mut self, handle: ClientHandle) {
-        let event = self
-            .client_manager
-            .get_state(handle)
-            .map(|(c, s)| FrontendEvent::State(handle, c, s))
-            .unwrap_or(FrontendEvent::NoSuchClient(handle));
-        self.notify_frontend(event);
+        if let Some((config, state)) = self.client_manager.get_state(handle) {
+            self.notify_frontend(FrontendEvent::State {
+                handle,
+                config,
+                state,
+            });
+        }
     }
 
     fn spawn_hook_command(&self, handle: ClientHandle) {
@@ -546,3 +1115,189 @@ impl Service {
         });
     }
 }
+
+/// load the certificate, waiting for a frontend to
+/// supply the passphrase if the private key is encrypted
+async fn load_certificate(
+    config: &Config,
+    frontend_listener: &mut AsyncFrontendListener,
+) -> Result<Certificate, ServiceError> {
+    let path = config.cert_path();
+    let storage = config.key_storage();
+    let mut passphrase = config.key_passphrase()?;
+    /* the frontend that sent the passphrase */
+    let mut unlock = None;
+    loop {
+        let result =
+            match crypto::load_or_generate_key_and_cert(path, storage, passphrase.as_deref()).await
+            {
+                Err(crypto::Error::PassphraseRequired) => Err(FrontendError::Locked),
+                Err(crypto::Error::InvalidPassphrase) => {
+                    log::warn!("invalid passphrase");
+                    Err(FrontendError::InvalidPassphrase)
+                }
+                r => {
+                    let cert = r?;
+                    if let Some((frontend, id)) = unlock {
+                        if let Some(message) = response_message(id, Ok(FrontendResponse::Done)) {
+                            frontend_listener.send(frontend, message).await;
+                        }
+                    }
+                    return Ok(cert);
+                }
+            };
+        match unlock.take() {
+            Some((frontend, id)) => {
+                if let Some(message) = response_message(id, result) {
+                    frontend_listener.send(frontend, message).await;
+                }
+            }
+            None if matches!(result, Err(FrontendError::InvalidPassphrase)) => {
+                let event = FrontendEvent::Error(FrontendError::InvalidPassphrase.to_string());
+                frontend_listener.broadcast(event).await;
+            }
+            None => {}
+        }
+        log::info!("waiting for the passphrase of the private key ...");
+        frontend_listener
+            .broadcast(FrontendEvent::PassphraseRequired)
+            .await;
+        passphrase = loop {
+            tokio::select! {
+                request = frontend_listener.next() => {
+                    let (frontend, request) = request.expect("frontend listener closed");
+                    match request {
+                        Ok(RequestEnvelope { id, request: FrontendRequest::Unlock(_) })
+                            if frontend_listener.is_read_only(frontend) =>
+                        {
+                            log::warn!("rejected passphrase of read-only frontend {frontend}");
+                            if let Some(message) = response_message(id, Err(FrontendError::ReadOnly)) {
+                                frontend_listener.send(frontend, message).await;
+                            }
+                        }
+                        Ok(RequestEnvelope { id, request: FrontendRequest::Unlock(passphrase) }) => {
+                            unlock = Some((frontend, id));
+                            break Some(passphrase);
+                        }
+                        Ok(RequestEnvelope { id, request: FrontendRequest::Hello { version } }) => {
+                            if let Some(message) = response_message(id, hello(version)) {
+                                frontend_listener.send(frontend, message).await;
+                            }
+                        }
+                        Ok(RequestEnvelope { id, request: FrontendRequest::Sync }) => {
+                            let event = FrontendMessage::Event(FrontendEvent::PassphraseRequired);
+                            frontend_listener.send(frontend, event).await;
+                            if let Some(message) = response_message(id, Ok(FrontendResponse::Done)) {
+                                frontend_listener.send(frontend, message).await;
+                            }
+                        }
+                        Ok(RequestEnvelope { id, .. }) => {
+                            log::debug!("ignoring request: private key is locked");
+                            if let Some(message) = response_message(id, Err(FrontendError::Locked)) {
+                                frontend_listener.send(frontend, message).await;
+                            }
+                        }
+                        Err(e) => log::error!("error receiving request: {e}"),
+                    }
+                }
+                r = signal::ctrl_c() => {
+                    r.expect("failed to wait for CTRL+C");
+                    return Err(crypto::Error::PassphraseRequired.into());
+                }
+            }
+        };
+    }
+}
+
+/// answer the protocol version announced by a frontend
+fn hello(version: u32) -> Result<FrontendResponse, FrontendError> {
+    match version {
+        PROTOCOL_VERSION => Ok(FrontendResponse::Hello { version }),
+        _ => Err(FrontendError::UnsupportedVersion {
+            version,
+            supported: PROTOCOL_VERSION,
+        }),
+    }
+}
+
+/// the message answering a request with the given `id`.
+/// Failed requests without an id are reported as [`FrontendEvent::Error`].
+fn response_message(
+    id: Option<RequestId>,
+    result: Result<FrontendResponse, FrontendError>,
+) -> Option<FrontendMessage> {
+    match (id, result) {
+        (Some(id), result) => Some(FrontendMessage::Response { id, result }),
+        (None, Err(e)) => Some(FrontendMessage::Event(FrontendEvent::Error(e.to_string()))),
+        (None, Ok(_)) => None,
+    }
+}
+
+/// the configured profile to start with, if it exists
+fn initial_profile(config: &Config) -> String {
+    let profile = config.profile();
+    if config.has_profile(&profile) {
+        return profile;
+    }
+    log::warn!("no such profile: `{profile}`, using `{DEFAULT_PROFILE}`");
+    DEFAULT_PROFILE.to_owned()
+}
+
+/// a release bind needs at least one of these,
+/// so that it is not triggered while typing
+const MODIFIERS: [scancode::Linux; 8] = [
+    scancode::Linux::KeyLeftCtrl,
+    scancode::Linux::KeyRightCtrl,
+    scancode::Linux::KeyLeftShift,
+    scancode::Linux::KeyRightShift,
+    scancode::Linux::KeyLeftAlt,
+    scancode::Linux::KeyRightalt,
+    scancode::Linux::KeyLeftMeta,
+    scancode::Linux::KeyRightmeta,
+];
+
+/// the keys of a release bind named like in the config file, without duplicates
+fn parse_release_bind(keys: &[String]) -> Result<Vec<scancode::Linux>, FrontendError> {
+    if keys.is_empty() {
+        return Err(FrontendError::InvalidReleaseBind("no keys".into()));
+    }
+    let mut release_bind = vec![];
+    for name in keys {
+        let key = serde_json::from_value(serde_json::Value::String(name.clone()))
+            .map_err(|_| FrontendError::InvalidReleaseBind(format!("unknown key `{name}`")))?;
+        if !release_bind.contains(&key) {
+            release_bind.push(key);
+        }
+    }
+    if !release_bind.iter().any(|k| MODIFIERS.contains(k)) {
+        return Err(FrontendError::InvalidReleaseBind(
+            "must contain Ctrl, Shift, Alt or Meta".into(),
+        ));
+    }
+    Ok(release_bind)
+}
+
+/// name of a key as used in the config file
+fn key_name(key: scancode::Linux) -> String {
+    match serde_json::to_value(key) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{key:?}"),
+    }
+}
+
+fn client_from_config(client: ConfigClient) -> (ClientConfig, ClientState) {
+    let config = ClientConfig {
+        hostname: client.hostname,
+        fix_ips: client.ips.into_iter().collect(),
+        port: client.port,
+        pos: client.pos,
+        cmd: client.enter_hook,
+        mac: client.mac,
+    };
+    let state = ClientState {
+        active: client.active,
+        ips: HashSet::from_iter(config.fix_ips.iter().cloned()),
+        ..Default::default()
+    };
+    (config, state)
+}
@@ -0,0 +1,169 @@
+use std::{cell::RefCell, collections::HashMap, net::SocketAddr, rc::Rc, time::Instant};
+
+use input_event::{Event, KeyboardEvent, PointerEvent};
+use lan_mouse_ipc::{ClientHandle, ConnectionState, ConnectionStats};
+use lan_mouse_proto::ProtoEvent;
+
+/// connection for which statistics are tracked
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Peer {
+    /// outgoing connection to a client
+    Client(ClientHandle),
+    /// incoming connection (identified by its certificate fingerprint)
+    Incoming(String),
+}
+
+#[derive(Default)]
+struct Counters {
+    stats: ConnectionStats,
+    last_event: Option<Instant>,
+    /// whether the connection was established before
+    connected_once: bool,
+}
+
+/// traffic and health statistics of all connections
+#[derive(Clone, Default)]
+pub(crate) struct Stats {
+    peers: Rc<RefCell<HashMap<Peer, Counters>>>,
+    /// fingerprints of incoming connections by address
+    incoming: Rc<RefCell<HashMap<SocketAddr, String>>>,
+}
+
+impl Stats {
+    /// the peer connected at `addr`
+    pub(crate) fn connected(&self, peer: Peer, addr: SocketAddr) {
+        if let Peer::Incoming(fingerprint) = &peer {
+            self.incoming.borrow_mut().insert(addr, fingerprint.clone());
+        }
+        let mut peers = self.peers.borrow_mut();
+        let counters = peers.entry(peer).or_default();
+        if counters.connected_once {
+            counters.stats.reconnects += 1;
+        }
+        counters.connected_once = true;
+        counters.stats.state = ConnectionState::Connected;
+        counters.stats.addr = Some(addr);
+    }
+
+    pub(crate) fn connecting(&self, peer: Peer) {
+        let mut peers = self.peers.borrow_mut();
+        peers.entry(peer).or_default().stats.state = ConnectionState::Connecting;
+    }
+
+    pub(crate) fn disconnected(&self, peer: Peer) {
+        self.with(peer, |c| {
+            c.stats.state = ConnectionState::Disconnected;
+            c.stats.addr = None;
+        });
+    }
+
+    /// the incoming connection at `addr` was closed
+    pub(crate) fn incoming_disconnected(&self, addr: SocketAddr) {
+        if let Some(fingerprint) = self.incoming.borrow_mut().remove(&addr) {
+            self.disconnected(Peer::Incoming(fingerprint));
+        }
+    }
+
+    /// look up the incoming connection at `addr`
+    pub(crate) fn incoming(&self, addr: SocketAddr) -> Option<Peer> {
+        self.incoming
+            .borrow()
+            .get(&addr)
+            .cloned()
+            .map(Peer::Incoming)
+    }
+
+    pub(crate) fn sent(&self, peer: Peer, event: &ProtoEvent, len: usize) {
+        self.with(peer, |c| {
+            *c.stats
+                .events_sent
+                .entry(event_type(event).into())
+                .or_default() += 1;
+            c.stats.bytes_sent += len as u64;
+            c.stats.datagrams_sent += 1;
+            c.last_event = Some(Instant::now());
+        });
+    }
+
+    /// a datagram of `len` bytes was received, `event` is `None` if it could not be decoded
+    pub(crate) fn received(&self, peer: Peer, event: Option<&ProtoEvent>, len: usize) {
+        self.with(peer, |c| {
+            if let Some(event) = event {
+                *c.stats
+                    .events_received
+                    .entry(event_type(event).into())
+                    .or_default() += 1;
+                c.last_event = Some(Instant::now());
+            }
+            c.stats.bytes_received += len as u64;
+            c.stats.datagrams_received += 1;
+        });
+    }
+
+    pub(crate) fn send_error(&self, peer: Peer) {
+        self.with(peer, |c| c.stats.send_errors += 1);
+    }
+
+    pub(crate) fn remove(&self, peer: &Peer) {
+        self.peers.borrow_mut().remove(peer);
+    }
+
+    /// statistics of all clients
+    pub(crate) fn client_stats(&self) -> Vec<(ClientHandle, ConnectionStats)> {
+        let mut stats = self.collect(|peer| match peer {
+            Peer::Client(handle) => Some(*handle),
+            Peer::Incoming(_) => None,
+        });
+        stats.sort_by_key(|(h, _)| *h);
+        stats
+    }
+
+    /// statistics of all incoming connections by fingerprint
+    pub(crate) fn incoming_stats(&self) -> Vec<(String, ConnectionStats)> {
+        let mut stats = self.collect(|peer| match peer {
+            Peer::Client(_) => None,
+            Peer::Incoming(fingerprint) => Some(fingerprint.clone()),
+        });
+        stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        stats
+    }
+
+    fn collect<K>(&self, key: impl Fn(&Peer) -> Option<K>) -> Vec<(K, ConnectionStats)> {
+        let now = Instant::now();
+        self.peers
+            .borrow()
+            .iter()
+            .filter_map(|(peer, counters)| {
+                let mut stats = counters.stats.clone();
+                stats.since_last_event = counters.last_event.map(|t| now.duration_since(t));
+                key(peer).map(|k| (k, stats))
+            })
+            .collect()
+    }
+
+    fn with(&self, peer: Peer, f: impl FnOnce(&mut Counters)) {
+        if let Some(counters) = self.peers.borrow_mut().get_mut(&peer) {
+            f(counters)
+        }
+    }
+}
+
+fn event_type(event: &ProtoEvent) -> &'static str {
+    match event {
+        ProtoEvent::Enter(_) => "enter",
+        ProtoEvent::Leave(_) => "leave",
+        ProtoEvent::Ack(_) => "ack",
+        ProtoEvent::Input(Event::Pointer(e)) => match e {
+            PointerEvent::Motion { .. } => "motion",
+            PointerEvent::Button { .. } => "button",
+            PointerEvent::Axis { .. } => "axis",
+            PointerEvent::AxisDiscrete120 { .. } => "axis-discrete",
+        },
+        ProtoEvent::Input(Event::Keyboard(e)) => match e {
+            KeyboardEvent::Key { .. } => "key",
+            KeyboardEvent::Modifiers { .. } => "modifiers",
+        },
+        ProtoEvent::Ping => "ping",
+        ProtoEvent::Pong(_) => "pong",
+    }
+}
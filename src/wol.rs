@@ -0,0 +1,54 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use lan_mouse_ipc::MacAddress;
+use tokio::net::UdpSocket;
+
+/// port magic packets are sent to (discard protocol)
+const WOL_PORT: u16 = 9;
+
+/// send a Wake-on-LAN magic packet for `mac` to the local broadcast
+/// address and each of the given (last known) ipv4 addresses
+pub(crate) async fn wake(mac: MacAddress, ips: &[IpAddr]) -> io::Result<()> {
+    let packet = magic_packet(mac);
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&packet, (Ipv4Addr::BROADCAST, WOL_PORT))
+        .await?;
+    for &ip in ips.iter().filter(|ip| ip.is_ipv4()) {
+        if let Err(e) = socket.send_to(&packet, SocketAddr::new(ip, WOL_PORT)).await {
+            log::debug!("failed to send magic packet to {ip}: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// 6 x 0xff followed by 16 repetitions of the mac address
+fn magic_packet(mac: MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac.0);
+    }
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use lan_mouse_ipc::MacAddress;
+
+    use super::magic_packet;
+
+    #[test]
+    fn magic_packet_layout() {
+        let mac = MacAddress([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]);
+        let packet = magic_packet(mac);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(packet[..6], [0xff; 6]);
+        for chunk in packet[6..].chunks(6) {
+            assert_eq!(chunk, mac.0);
+        }
+    }
+}